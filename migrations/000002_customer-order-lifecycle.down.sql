@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS customer_order_status_change;
+ALTER TABLE customer_order DROP COLUMN IF EXISTS updated_at;
+DROP TYPE IF EXISTS actor_role;
@@ -0,0 +1,17 @@
+CREATE TYPE actor_role AS ENUM ('Admin', 'Customer', 'Partner');
+
+ALTER TABLE customer_order
+    ADD COLUMN updated_at timestamp without time zone NOT NULL DEFAULT now();
+
+CREATE TABLE customer_order_status_change (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    customer_order_id uuid NOT NULL REFERENCES customer_order(id),
+    from_status customer_order_status NOT NULL,
+    to_status customer_order_status NOT NULL,
+    changed_by uuid NOT NULL,
+    changed_by_role actor_role NOT NULL
+);
+
+CREATE INDEX customer_order_status_change_customer_order_id_idx
+    ON customer_order_status_change (customer_order_id);
@@ -0,0 +1,8 @@
+ALTER TABLE dish_order DROP COLUMN IF EXISTS unit_price;
+ALTER TABLE customer_order
+    DROP COLUMN IF EXISTS discount,
+    DROP COLUMN IF EXISTS service_charge_rate,
+    DROP COLUMN IF EXISTS tax_rate;
+ALTER TABLE restaurant
+    DROP COLUMN IF EXISTS service_charge_rate,
+    DROP COLUMN IF EXISTS tax_rate;
@@ -0,0 +1,18 @@
+-- Rates are stored in basis points, e.g. 1000 is 10%.
+ALTER TABLE restaurant
+    ADD COLUMN tax_rate int NOT NULL DEFAULT 0,
+    ADD COLUMN service_charge_rate int NOT NULL DEFAULT 0;
+
+ALTER TABLE customer_order
+    ADD COLUMN tax_rate int NOT NULL DEFAULT 0,
+    ADD COLUMN service_charge_rate int NOT NULL DEFAULT 0,
+    ADD COLUMN discount int NOT NULL DEFAULT 0;
+
+ALTER TABLE dish_order ADD COLUMN unit_price int;
+
+UPDATE dish_order
+SET unit_price = dish.price
+FROM dish
+WHERE dish.id = dish_order.dish_id;
+
+ALTER TABLE dish_order ALTER COLUMN unit_price SET NOT NULL;
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS payment;
+DROP TYPE IF EXISTS payment_status;
+DROP TYPE IF EXISTS payment_tender;
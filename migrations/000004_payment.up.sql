@@ -0,0 +1,21 @@
+CREATE TYPE payment_tender AS ENUM ('Cash', 'Card', 'QrEwallet');
+-- a payment is Pending from being recorded until the provider answers
+CREATE TYPE payment_status AS ENUM ('Pending', 'Succeeded', 'Failed');
+
+CREATE TABLE payment (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    customer_order_id uuid NOT NULL REFERENCES customer_order(id),
+    tender payment_tender NOT NULL,
+    status payment_status NOT NULL,
+    amount int NOT NULL,
+    tendered int NOT NULL,
+    change int NOT NULL DEFAULT 0,
+    provider character varying(50) NOT NULL,
+    provider_reference text,
+    failure_reason text,
+    created_by uuid NOT NULL,
+    created_by_role actor_role NOT NULL
+);
+
+CREATE INDEX payment_customer_order_id_idx ON payment (customer_order_id);
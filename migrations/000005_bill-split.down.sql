@@ -0,0 +1,4 @@
+ALTER TABLE payment DROP COLUMN IF EXISTS bill_check_id;
+DROP TABLE IF EXISTS bill_check_line;
+DROP TABLE IF EXISTS bill_check;
+DROP TYPE IF EXISTS bill_check_status;
@@ -0,0 +1,21 @@
+CREATE TYPE bill_check_status AS ENUM ('Open', 'Paid');
+
+CREATE TABLE bill_check (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    customer_order_id uuid NOT NULL REFERENCES customer_order(id),
+    label character varying(50) NOT NULL,
+    position int NOT NULL,
+    amount int NOT NULL,
+    status bill_check_status NOT NULL DEFAULT 'Open'
+);
+
+CREATE INDEX bill_check_customer_order_id_idx ON bill_check (customer_order_id);
+
+CREATE TABLE bill_check_line (
+    bill_check_id uuid NOT NULL REFERENCES bill_check(id) ON DELETE CASCADE,
+    dish_order_id uuid NOT NULL REFERENCES dish_order(id),
+    PRIMARY KEY (bill_check_id, dish_order_id)
+);
+
+ALTER TABLE payment ADD COLUMN bill_check_id uuid REFERENCES bill_check(id);
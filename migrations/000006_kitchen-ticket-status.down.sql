@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS dish_order_status_created_at_idx;
+ALTER TABLE dish_order
+    DROP COLUMN IF EXISTS voided_at,
+    DROP COLUMN IF EXISTS served_at,
+    DROP COLUMN IF EXISTS ready_at,
+    DROP COLUMN IF EXISTS preparing_at,
+    DROP COLUMN IF EXISTS status;
+DROP TYPE IF EXISTS dish_order_status;
@@ -0,0 +1,16 @@
+CREATE TYPE dish_order_status AS ENUM ('Queued', 'Preparing', 'Ready', 'Served', 'Voided');
+
+ALTER TABLE dish_order
+    ADD COLUMN status dish_order_status NOT NULL DEFAULT 'Queued',
+    ADD COLUMN preparing_at timestamp without time zone,
+    ADD COLUMN ready_at timestamp without time zone,
+    ADD COLUMN served_at timestamp without time zone,
+    ADD COLUMN voided_at timestamp without time zone;
+
+-- items of orders that are already settled have left the kitchen
+UPDATE dish_order
+SET status = 'Served', served_at = customer_order.updated_at
+FROM customer_order
+WHERE customer_order.id = dish_order.customer_order_id AND customer_order.status = 'Done';
+
+CREATE INDEX dish_order_status_created_at_idx ON dish_order (status, created_at);
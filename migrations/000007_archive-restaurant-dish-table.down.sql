@@ -0,0 +1,3 @@
+ALTER TABLE dining_table DROP COLUMN IF EXISTS archived_at;
+ALTER TABLE dish DROP COLUMN IF EXISTS archived_at;
+ALTER TABLE restaurant DROP COLUMN IF EXISTS archived_at;
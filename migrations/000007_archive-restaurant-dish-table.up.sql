@@ -0,0 +1,3 @@
+ALTER TABLE restaurant ADD COLUMN archived_at timestamp without time zone;
+ALTER TABLE dish ADD COLUMN archived_at timestamp without time zone;
+ALTER TABLE dining_table ADD COLUMN archived_at timestamp without time zone;
@@ -0,0 +1,3 @@
+ALTER TABLE dish DROP COLUMN IF EXISTS position;
+ALTER TABLE dish DROP COLUMN IF EXISTS menu_category_id;
+DROP TABLE IF EXISTS menu_category;
@@ -0,0 +1,12 @@
+CREATE TABLE menu_category (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    restaurant_id uuid NOT NULL REFERENCES restaurant(id),
+    name character varying(50) NOT NULL,
+    position int NOT NULL
+);
+
+CREATE INDEX menu_category_restaurant_id_idx ON menu_category (restaurant_id);
+
+ALTER TABLE dish ADD COLUMN menu_category_id uuid REFERENCES menu_category(id) ON DELETE SET NULL;
+ALTER TABLE dish ADD COLUMN position int NOT NULL DEFAULT 0;
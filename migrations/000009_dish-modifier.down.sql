@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS dish_order_modifier;
+DROP TABLE IF EXISTS modifier_option;
+DROP TABLE IF EXISTS modifier_group;
@@ -0,0 +1,35 @@
+CREATE TABLE modifier_group (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    dish_id uuid NOT NULL REFERENCES dish(id),
+    name character varying(50) NOT NULL,
+    min_selections int NOT NULL DEFAULT 0,
+    max_selections int NOT NULL DEFAULT 1,
+    position int NOT NULL,
+    CHECK (min_selections >= 0 AND max_selections >= 1 AND min_selections <= max_selections)
+);
+
+CREATE INDEX modifier_group_dish_id_idx ON modifier_group (dish_id);
+
+CREATE TABLE modifier_option (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    modifier_group_id uuid NOT NULL REFERENCES modifier_group(id) ON DELETE CASCADE,
+    name character varying(50) NOT NULL,
+    price_delta int NOT NULL DEFAULT 0,
+    position int NOT NULL
+);
+
+CREATE INDEX modifier_option_modifier_group_id_idx ON modifier_option (modifier_group_id);
+
+-- selections are snapshotted so menu edits don't change placed orders
+CREATE TABLE dish_order_modifier (
+    dish_order_id uuid NOT NULL REFERENCES dish_order(id) ON DELETE CASCADE,
+    modifier_option_id uuid REFERENCES modifier_option(id) ON DELETE SET NULL,
+    group_name character varying(50) NOT NULL,
+    option_name character varying(50) NOT NULL,
+    price_delta int NOT NULL,
+    position int NOT NULL
+);
+
+CREATE INDEX dish_order_modifier_dish_order_id_idx ON dish_order_modifier (dish_order_id);
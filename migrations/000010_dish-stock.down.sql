@@ -0,0 +1,3 @@
+ALTER TABLE dish
+    DROP COLUMN IF EXISTS stock,
+    DROP COLUMN IF EXISTS available;
@@ -0,0 +1,4 @@
+-- a NULL stock means the dish isn't counted and only the availability toggle applies
+ALTER TABLE dish
+    ADD COLUMN available boolean NOT NULL DEFAULT true,
+    ADD COLUMN stock int CHECK (stock >= 0);
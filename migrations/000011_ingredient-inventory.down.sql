@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS ingredient_movement;
+DROP TYPE IF EXISTS ingredient_movement_kind;
+DROP TABLE IF EXISTS recipe_line;
+DROP TABLE IF EXISTS ingredient;
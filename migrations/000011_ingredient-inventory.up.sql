@@ -0,0 +1,35 @@
+-- quantities are whole units of the ingredient's smallest measure, e.g. grams, ml or pieces
+CREATE TABLE ingredient (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    restaurant_id uuid NOT NULL REFERENCES restaurant(id),
+    name character varying(50) NOT NULL,
+    unit character varying(20) NOT NULL,
+    on_hand int NOT NULL DEFAULT 0,
+    reorder_point int NOT NULL DEFAULT 0
+);
+
+CREATE INDEX ingredient_restaurant_id_idx ON ingredient (restaurant_id);
+
+CREATE TABLE recipe_line (
+    dish_id uuid NOT NULL REFERENCES dish(id),
+    ingredient_id uuid NOT NULL REFERENCES ingredient(id),
+    quantity int NOT NULL CHECK (quantity > 0),
+    PRIMARY KEY (dish_id, ingredient_id)
+);
+
+CREATE TYPE ingredient_movement_kind AS ENUM ('Depletion', 'StockTake', 'Wastage');
+
+CREATE TABLE ingredient_movement (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    ingredient_id uuid NOT NULL REFERENCES ingredient(id),
+    kind ingredient_movement_kind NOT NULL,
+    quantity_change int NOT NULL,
+    on_hand_after int NOT NULL,
+    dish_order_id uuid REFERENCES dish_order(id),
+    note text,
+    recorded_by uuid
+);
+
+CREATE INDEX ingredient_movement_ingredient_id_idx ON ingredient_movement (ingredient_id, created_at);
@@ -0,0 +1,8 @@
+ALTER TABLE ingredient_movement DROP COLUMN IF EXISTS purchase_order_line_id;
+DROP TABLE IF EXISTS purchase_order_line;
+DROP TABLE IF EXISTS purchase_order;
+DROP TYPE IF EXISTS purchase_order_status;
+DROP TABLE IF EXISTS supplier;
+ALTER TABLE ingredient DROP COLUMN IF EXISTS average_cost;
+-- enum values can't be dropped, so receipts are folded into stock-takes
+UPDATE ingredient_movement SET kind = 'StockTake' WHERE kind = 'Receipt';
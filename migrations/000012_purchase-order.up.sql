@@ -0,0 +1,50 @@
+ALTER TYPE ingredient_movement_kind ADD VALUE 'Receipt';
+
+-- average_cost is in ten-thousandths of a minor currency unit, so a unit
+-- cost can be finer than one minor unit yet still add up exactly
+ALTER TABLE ingredient ADD COLUMN average_cost bigint NOT NULL DEFAULT 0;
+
+CREATE TABLE supplier (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    restaurant_id uuid NOT NULL REFERENCES restaurant(id),
+    name character varying(50) NOT NULL,
+    contact_name character varying(50),
+    phone character varying(20),
+    email character varying(50)
+);
+
+CREATE INDEX supplier_restaurant_id_idx ON supplier (restaurant_id);
+
+CREATE TYPE purchase_order_status AS ENUM ('Draft', 'Ordered', 'PartiallyReceived', 'Received', 'Cancelled');
+
+CREATE TABLE purchase_order (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    restaurant_id uuid NOT NULL REFERENCES restaurant(id),
+    supplier_id uuid NOT NULL REFERENCES supplier(id),
+    status purchase_order_status NOT NULL DEFAULT 'Draft',
+    note text,
+    created_by uuid NOT NULL,
+    ordered_at timestamp without time zone,
+    received_at timestamp without time zone,
+    cancelled_at timestamp without time zone
+);
+
+CREATE INDEX purchase_order_restaurant_id_idx ON purchase_order (restaurant_id, created_at);
+
+-- expected_cost is the whole line's cost at the quantity ordered
+CREATE TABLE purchase_order_line (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    purchase_order_id uuid NOT NULL REFERENCES purchase_order(id),
+    ingredient_id uuid NOT NULL REFERENCES ingredient(id),
+    position int NOT NULL,
+    quantity_ordered int NOT NULL CHECK (quantity_ordered > 0),
+    expected_cost int NOT NULL CHECK (expected_cost >= 0),
+    quantity_received int NOT NULL DEFAULT 0,
+    received_cost int NOT NULL DEFAULT 0
+);
+
+CREATE INDEX purchase_order_line_purchase_order_id_idx ON purchase_order_line (purchase_order_id);
+
+ALTER TABLE ingredient_movement ADD COLUMN purchase_order_line_id uuid REFERENCES purchase_order_line(id);
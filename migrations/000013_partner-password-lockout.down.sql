@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS partner_username_idx;
+ALTER TABLE partner
+    DROP COLUMN IF EXISTS locked_until,
+    DROP COLUMN IF EXISTS failed_sign_in_attempts;
@@ -0,0 +1,5 @@
+ALTER TABLE partner
+    ADD COLUMN failed_sign_in_attempts int NOT NULL DEFAULT 0,
+    ADD COLUMN locked_until timestamp without time zone;
+
+CREATE UNIQUE INDEX IF NOT EXISTS partner_username_idx ON partner (username);
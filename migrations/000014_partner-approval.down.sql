@@ -0,0 +1,5 @@
+ALTER TABLE partner
+    DROP COLUMN IF EXISTS deactivated_at,
+    DROP COLUMN IF EXISTS approved_by,
+    DROP COLUMN IF EXISTS approved_at,
+    DROP COLUMN IF EXISTS is_owner;
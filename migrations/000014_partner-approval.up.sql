@@ -0,0 +1,17 @@
+ALTER TABLE partner
+    ADD COLUMN is_owner boolean NOT NULL DEFAULT false,
+    ADD COLUMN approved_at timestamp without time zone,
+    ADD COLUMN approved_by uuid,
+    ADD COLUMN deactivated_at timestamp without time zone;
+
+-- partners from before approval existed keep working, and the first of each
+-- restaurant becomes its owner
+UPDATE partner SET is_active = true, approved_at = created_at;
+
+UPDATE partner
+SET is_owner = true
+WHERE id IN (
+    SELECT DISTINCT ON (restaurant_id) id
+    FROM partner
+    ORDER BY restaurant_id, created_at
+);
@@ -0,0 +1,4 @@
+ALTER TABLE partner ADD COLUMN is_owner boolean NOT NULL DEFAULT false;
+UPDATE partner SET is_owner = (staff_role = 'Owner');
+ALTER TABLE partner DROP COLUMN IF EXISTS staff_role;
+DROP TYPE IF EXISTS staff_role;
@@ -0,0 +1,9 @@
+CREATE TYPE staff_role AS ENUM ('Owner', 'Manager', 'Cashier', 'Waiter', 'Kitchen');
+
+ALTER TABLE partner ADD COLUMN staff_role staff_role NOT NULL DEFAULT 'Waiter';
+
+-- partners could do everything before roles existed, so they start out as managers
+UPDATE partner
+SET staff_role = CASE WHEN is_owner THEN 'Owner'::staff_role ELSE 'Manager'::staff_role END;
+
+ALTER TABLE partner DROP COLUMN is_owner;
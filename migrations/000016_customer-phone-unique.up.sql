@@ -0,0 +1,2 @@
+-- a phone number identifies one customer; anonymous customers have none
+CREATE UNIQUE INDEX IF NOT EXISTS customer_phone_idx ON customer (phone);
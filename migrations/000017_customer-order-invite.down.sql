@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS customer_order_participant;
+DROP INDEX IF EXISTS customer_order_invite_code_idx;
+ALTER TABLE customer_order DROP COLUMN IF EXISTS invite_code;
@@ -0,0 +1,13 @@
+ALTER TABLE customer_order ADD COLUMN invite_code character varying(12);
+
+CREATE UNIQUE INDEX IF NOT EXISTS customer_order_invite_code_idx ON customer_order (invite_code);
+
+-- other diners who joined an order with its invite code
+CREATE TABLE customer_order_participant (
+    customer_order_id uuid NOT NULL REFERENCES customer_order(id),
+    customer_id uuid NOT NULL REFERENCES customer(id),
+    joined_at timestamp without time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (customer_order_id, customer_id)
+);
+
+CREATE INDEX IF NOT EXISTS customer_order_participant_customer_idx ON customer_order_participant (customer_id);
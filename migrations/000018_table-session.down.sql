@@ -0,0 +1,2 @@
+ALTER TABLE dish_order DROP COLUMN IF EXISTS customer_id;
+DROP TABLE IF EXISTS table_session;
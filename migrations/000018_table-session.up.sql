@@ -0,0 +1,21 @@
+-- a table is occupied while it has a session without closed_at; every diner
+-- seated during the session orders onto the session's customer_order
+CREATE TABLE table_session (
+    id uuid PRIMARY KEY,
+    dining_table_id uuid NOT NULL REFERENCES dining_table(id),
+    customer_order_id uuid NOT NULL REFERENCES customer_order(id),
+    opened_by uuid NOT NULL REFERENCES customer(id),
+    opened_at timestamp without time zone NOT NULL DEFAULT now(),
+    closed_at timestamp without time zone
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS table_session_open_idx ON table_session (dining_table_id) WHERE closed_at IS NULL;
+CREATE INDEX IF NOT EXISTS table_session_customer_order_idx ON table_session (customer_order_id);
+
+-- the diner who ordered the item
+ALTER TABLE dish_order ADD COLUMN customer_id uuid REFERENCES customer(id);
+
+UPDATE dish_order
+SET customer_id = customer_order.customer_id
+FROM customer_order
+WHERE customer_order.id = dish_order.customer_order_id;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS table_session_event;
+DROP TYPE IF EXISTS table_session_event_kind;
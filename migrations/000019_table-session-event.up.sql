@@ -0,0 +1,18 @@
+CREATE TYPE table_session_event_kind AS ENUM ('Transfer', 'Merge', 'Split');
+
+-- who moved, merged or split which table, for settling disputes afterwards
+CREATE TABLE table_session_event (
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    kind table_session_event_kind NOT NULL,
+    table_session_id uuid NOT NULL REFERENCES table_session(id),
+    -- the session merged into, or split off into
+    other_table_session_id uuid REFERENCES table_session(id),
+    from_dining_table_id uuid NOT NULL REFERENCES dining_table(id),
+    to_dining_table_id uuid NOT NULL REFERENCES dining_table(id),
+    dish_order_count int NOT NULL DEFAULT 0,
+    performed_by uuid NOT NULL REFERENCES partner(id)
+);
+
+CREATE INDEX table_session_event_table_session_id_idx ON table_session_event (table_session_id, created_at);
+CREATE INDEX table_session_event_other_table_session_id_idx ON table_session_event (other_table_session_id);
@@ -0,0 +1 @@
+ALTER TABLE dining_table DROP COLUMN IF EXISTS qr_token_version;
@@ -0,0 +1,2 @@
+-- bumped to invalidate the table's printed QR codes
+ALTER TABLE dining_table ADD COLUMN qr_token_version int NOT NULL DEFAULT 1;
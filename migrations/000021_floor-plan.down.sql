@@ -0,0 +1,9 @@
+ALTER TABLE dining_table
+    DROP COLUMN IF EXISTS floor_area_id,
+    DROP COLUMN IF EXISTS capacity,
+    DROP COLUMN IF EXISTS shape,
+    DROP COLUMN IF EXISTS position_x,
+    DROP COLUMN IF EXISTS position_y,
+    DROP COLUMN IF EXISTS needs_cleaning;
+DROP TYPE IF EXISTS dining_table_shape;
+DROP TABLE IF EXISTS floor_area;
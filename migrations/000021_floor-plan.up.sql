@@ -0,0 +1,21 @@
+-- named parts of the dining room such as indoor, terrace or second floor
+CREATE TABLE floor_area (
+    id uuid PRIMARY KEY,
+    restaurant_id uuid NOT NULL REFERENCES restaurant(id),
+    name text NOT NULL,
+    sort_order int NOT NULL DEFAULT 0,
+    created_at timestamp without time zone NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS floor_area_restaurant_idx ON floor_area (restaurant_id);
+
+CREATE TYPE dining_table_shape AS ENUM ('Square', 'Rectangle', 'Round');
+
+-- positions are in the floor plan's own units, the partner app scales them
+ALTER TABLE dining_table
+    ADD COLUMN floor_area_id uuid REFERENCES floor_area(id) ON DELETE SET NULL,
+    ADD COLUMN capacity int NOT NULL DEFAULT 4 CHECK (capacity > 0),
+    ADD COLUMN shape dining_table_shape NOT NULL DEFAULT 'Square',
+    ADD COLUMN position_x double precision NOT NULL DEFAULT 0,
+    ADD COLUMN position_y double precision NOT NULL DEFAULT 0,
+    ADD COLUMN needs_cleaning boolean NOT NULL DEFAULT false;
@@ -2,6 +2,8 @@ extern crate iron;
 extern crate juniper_iron;
 extern crate mount;
 extern crate pretty_env_logger;
+#[macro_use]
+extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_json;
@@ -21,14 +23,27 @@ extern crate uuid;
 #[macro_use]
 extern crate serde_derive;
 extern crate crypto;
+extern crate image;
+extern crate qrcode;
+extern crate ws;
+mod notifier;
+mod password;
+mod payment;
 mod schema;
+mod state;
+mod subscriptions;
+mod table_qr;
 
 use std::env;
 use std::error::Error;
+use std::sync::Arc;
+use std::thread;
 
 use self::schema::context::context_factory;
 use self::schema::mutation::Mutation;
 use self::schema::query::Query;
+use self::state::AppState;
+use self::table_qr::TableQrHandler;
 
 use dotenv::dotenv;
 use ijr::{JsonResponse, JsonResponseMiddleware};
@@ -58,8 +73,25 @@ impl AfterMiddleware for ResponseError {
 fn main() {
     dotenv().ok();
     pretty_env_logger::init();
+    let state = Arc::new(AppState::from_env().expect("Failed to initialize application state"));
+    let subscriptions_state = state.clone();
+    let subscriptions_host =
+        env::var("SUBSCRIPTIONS_LISTEN").unwrap_or_else(|_| "0.0.0.0:4001".to_owned());
+    let subscriptions_server = subscriptions::bind(subscriptions_state, &subscriptions_host)
+        .expect("Failed to start GraphQL subscriptions");
+    println!("GraphQL subscriptions started on {}", subscriptions_host);
+    thread::spawn(move || {
+        if let Err(e) = subscriptions_server.run() {
+            error!("GraphQL subscriptions stopped: {}", e);
+        }
+    });
     let mut mount = Mount::new();
-    let graphql_endpoint = GraphQLHandler::new(context_factory, Query, Mutation);
+    mount.mount("/qr", TableQrHandler::new(state.clone()));
+    let graphql_endpoint = GraphQLHandler::new(
+        move |req: &mut Request| context_factory(&state, req),
+        Query,
+        Mutation,
+    );
     let graphiql_endpoint = GraphiQLHandler::new("/graphql");
     let mut graphql_chain = Chain::new(graphql_endpoint);
     graphql_chain.link_after(ResponseError);
@@ -0,0 +1,44 @@
+use chrono::prelude::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+
+use super::{Message, Notifier, NotifierError};
+
+/// Appends each message as a JSON line to a file, so tests and local tooling
+/// can read the codes back.
+pub struct FileNotifier {
+    path: String,
+    lock: Mutex<()>,
+}
+
+impl FileNotifier {
+    pub fn new(path: String) -> FileNotifier {
+        FileNotifier {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn send(&self, message: &Message) -> Result<(), NotifierError> {
+        let line = json!({
+            "sent_at": Utc::now().to_rfc3339(),
+            "channel": message.channel,
+            "phone": message.phone,
+            "body": message.body,
+        });
+        let _guard = self.lock.lock().map_err(|e| NotifierError(e.to_string()))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| NotifierError(e.to_string()))?;
+        writeln!(file, "{}", line).map_err(|e| NotifierError(e.to_string()))
+    }
+}
@@ -0,0 +1,19 @@
+use super::{Message, Notifier, NotifierError};
+
+/// Logs messages, login codes included, at info level instead of sending them.
+/// For development only.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn send(&self, message: &Message) -> Result<(), NotifierError> {
+        info!(
+            "{:?} to {}: {}",
+            message.channel, message.phone, message.body
+        );
+        Ok(())
+    }
+}
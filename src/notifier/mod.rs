@@ -0,0 +1,130 @@
+pub mod file;
+pub mod log;
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+
+use self::file::FileNotifier;
+use self::log::LogNotifier;
+
+/// How a message reaches the customer's phone.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum)]
+pub enum Channel {
+    Sms,
+    WhatsApp,
+}
+
+pub enum Template<'a> {
+    LoginCode {
+        code: &'a str,
+        expires_in_minutes: usize,
+    },
+}
+
+impl<'a> Template<'a> {
+    /// SMS stays within a single 160 character segment, WhatsApp can afford
+    /// a little formatting.
+    pub fn render(&self, channel: Channel) -> String {
+        match (self, channel) {
+            (
+                Template::LoginCode {
+                    code,
+                    expires_in_minutes,
+                },
+                Channel::Sms,
+            ) => format!(
+                "{} is your Ordina login code. It expires in {} minutes. Do not share it.",
+                code, expires_in_minutes
+            ),
+            (
+                Template::LoginCode {
+                    code,
+                    expires_in_minutes,
+                },
+                Channel::WhatsApp,
+            ) => format!(
+                "Your Ordina login code is *{}*.\n\nIt expires in {} minutes. Never share this code, not even with restaurant staff.",
+                code, expires_in_minutes
+            ),
+        }
+    }
+}
+
+pub struct Message<'a> {
+    pub channel: Channel,
+    pub phone: &'a str,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct NotifierError(pub String);
+
+impl fmt::Display for NotifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Notifier error: {}", self.0)
+    }
+}
+
+impl Error for NotifierError {}
+
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &str;
+    fn send(&self, message: &Message) -> Result<(), NotifierError>;
+}
+
+/// Picks the backend from `NOTIFIER_BACKEND`, which has no default: `log`
+/// prints login codes, so it has to be chosen on purpose. The `file` backend
+/// appends to `NOTIFIER_FILE`, or `notifications.log` when unset.
+pub fn from_env() -> Result<Box<dyn Notifier>, Box<dyn Error>> {
+    let backend = env::var("NOTIFIER_BACKEND")
+        .map_err(|_| NotifierError("NOTIFIER_BACKEND must be set".to_owned()))?;
+    match backend.as_str() {
+        "log" => Ok(Box::new(LogNotifier)),
+        "file" => {
+            let path = env::var("NOTIFIER_FILE").unwrap_or_else(|_| "notifications.log".to_owned());
+            Ok(Box::new(FileNotifier::new(path)))
+        }
+        _ => Err(Box::new(NotifierError(format!(
+            "unknown notifier backend {}",
+            backend
+        )))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGIN_CODE: Template = Template::LoginCode {
+        code: "042917",
+        expires_in_minutes: 5,
+    };
+
+    #[test]
+    fn login_code_sms_fits_one_segment() {
+        let body = LOGIN_CODE.render(Channel::Sms);
+        assert_eq!(
+            body,
+            "042917 is your Ordina login code. It expires in 5 minutes. Do not share it."
+        );
+        assert!(body.chars().count() <= 160);
+    }
+
+    #[test]
+    fn login_code_whatsapp_highlights_the_code() {
+        let body = LOGIN_CODE.render(Channel::WhatsApp);
+        assert!(body.starts_with("Your Ordina login code is *042917*."));
+        assert!(body.contains("It expires in 5 minutes."));
+    }
+
+    #[test]
+    fn longest_sms_still_fits_one_segment() {
+        let body = Template::LoginCode {
+            code: "999999",
+            expires_in_minutes: usize::MAX,
+        }
+        .render(Channel::Sms);
+        assert!(body.chars().count() <= 160);
+    }
+}
@@ -0,0 +1,83 @@
+//! Password storage. New hashes use scrypt in rust-crypto's self-describing
+//! `$rscrypt$` format, which carries its own salt and cost parameters. Rows
+//! written before that are unsalted SHA3-224 hex digests and get replaced on
+//! the next successful sign-in.
+
+use crypto::digest::Digest;
+use crypto::scrypt::{scrypt_check, scrypt_simple, ScryptParams};
+use crypto::sha3::Sha3;
+use crypto::util::fixed_time_eq;
+use std::io;
+
+const SCRYPT_PREFIX: &str = "$rscrypt$";
+
+/// N = 2^15, r = 8, p = 1 takes 32 MiB per hash.
+const LOG_N: u8 = 15;
+const R: u32 = 8;
+const P: u32 = 1;
+
+/// Checked against when the account doesn't exist, so unknown usernames take
+/// as long to reject as wrong passwords.
+const DUMMY_HASH: &str =
+    "$rscrypt$0$DwgB$6dGxCEORc/0r/bV7VPzjDA==$RhrjgoeR5mcwSJ1Wi9mUfgW9SzbwrKpCVa3b2QTZjt8=$";
+
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// The password matched a legacy hash and should be stored again with `hash`.
+    ValidLegacy,
+}
+
+pub fn hash(password: &str) -> io::Result<String> {
+    scrypt_simple(password, &ScryptParams::new(LOG_N, R, P))
+}
+
+pub fn verify(password: &str, stored: &str) -> Verification {
+    if stored.starts_with(SCRYPT_PREFIX) {
+        return match scrypt_check(password, stored) {
+            Ok(true) => Verification::Valid,
+            _ => Verification::Invalid,
+        };
+    }
+    let mut hasher = Sha3::sha3_224();
+    hasher.input_str(password);
+    if fixed_time_eq(hasher.result_str().as_bytes(), stored.as_bytes()) {
+        Verification::ValidLegacy
+    } else {
+        Verification::Invalid
+    }
+}
+
+/// Spends the time of a real verification without a stored hash to compare to.
+pub fn verify_dummy(password: &str) {
+    let _ = scrypt_check(password, DUMMY_HASH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA3-224 of "abc", from the FIPS 202 examples.
+    const LEGACY_ABC: &str = "e642824c3f8cf24ad09234ee7d3c766fc9a3a5168d0c94ad73b46fdf";
+
+    #[test]
+    fn hashes_verify_against_their_password() {
+        let stored = hash("correct horse").unwrap();
+        assert!(stored.starts_with(SCRYPT_PREFIX));
+        assert_eq!(verify("correct horse", &stored), Verification::Valid);
+        assert_eq!(verify("battery staple", &stored), Verification::Invalid);
+    }
+
+    #[test]
+    fn legacy_hashes_verify_and_ask_for_an_upgrade() {
+        assert_eq!(verify("abc", LEGACY_ABC), Verification::ValidLegacy);
+        assert_eq!(verify("abd", LEGACY_ABC), Verification::Invalid);
+    }
+
+    #[test]
+    fn dummy_hash_is_well_formed() {
+        assert_eq!(scrypt_check("anything", DUMMY_HASH), Ok(false));
+        assert_eq!(verify("anything", DUMMY_HASH), Verification::Invalid);
+    }
+}
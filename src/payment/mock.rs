@@ -0,0 +1,30 @@
+use super::{ChargeOutcome, ChargeRequest, PaymentError, PaymentProvider};
+
+/// In-process gateway for development and tests. Every charge is approved
+/// unless the client passes the token `decline`, or `unavailable` to simulate
+/// a gateway outage.
+pub struct MockProvider;
+
+impl PaymentProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn charge(&self, request: &ChargeRequest) -> Result<ChargeOutcome, PaymentError> {
+        debug!(
+            "mock {:?} charge of {} for order {}",
+            request.tender,
+            request.amount,
+            request.customer_order_id.hyphenated()
+        );
+        match request.token {
+            Some("decline") => Ok(ChargeOutcome::Declined {
+                reason: "Declined by mock gateway".to_owned(),
+            }),
+            Some("unavailable") => Err(PaymentError("mock gateway is unavailable".to_owned())),
+            _ => Ok(ChargeOutcome::Approved {
+                reference: Some(format!("mock-{}", request.payment_id.hyphenated())),
+            }),
+        }
+    }
+}
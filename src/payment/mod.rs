@@ -0,0 +1,81 @@
+pub mod cash;
+pub mod mock;
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use uuid::Uuid;
+
+use self::cash::CashProvider;
+use self::mock::MockProvider;
+use crate::schema::payment::PaymentTender;
+
+pub struct ChargeRequest<'a> {
+    pub payment_id: &'a Uuid,
+    pub customer_order_id: &'a Uuid,
+    pub tender: PaymentTender,
+    pub amount: i32,
+    /// Card token or scanned QR payload handed over by the client, if any.
+    pub token: Option<&'a str>,
+}
+
+pub enum ChargeOutcome {
+    Approved { reference: Option<String> },
+    Declined { reason: String },
+}
+
+/// The provider could not give an answer, as opposed to declining the charge.
+#[derive(Debug)]
+pub struct PaymentError(pub String);
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Payment provider error: {}", self.0)
+    }
+}
+
+impl Error for PaymentError {}
+
+pub trait PaymentProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn charge(&self, request: &ChargeRequest) -> Result<ChargeOutcome, PaymentError>;
+}
+
+pub struct PaymentProviders {
+    cash: Box<dyn PaymentProvider>,
+    card: Box<dyn PaymentProvider>,
+    qr_ewallet: Box<dyn PaymentProvider>,
+}
+
+impl PaymentProviders {
+    /// Picks the card and QR e-wallet providers from `PAYMENT_CARD_PROVIDER` and
+    /// `PAYMENT_QR_EWALLET_PROVIDER`, both defaulting to the in-process mock.
+    pub fn from_env() -> Result<PaymentProviders, Box<dyn Error>> {
+        let card = env::var("PAYMENT_CARD_PROVIDER").unwrap_or_else(|_| "mock".to_owned());
+        let qr_ewallet =
+            env::var("PAYMENT_QR_EWALLET_PROVIDER").unwrap_or_else(|_| "mock".to_owned());
+        Ok(PaymentProviders {
+            cash: Box::new(CashProvider),
+            card: provider_by_name(&card)?,
+            qr_ewallet: provider_by_name(&qr_ewallet)?,
+        })
+    }
+
+    pub fn for_tender(&self, tender: PaymentTender) -> &dyn PaymentProvider {
+        match tender {
+            PaymentTender::Cash => self.cash.as_ref(),
+            PaymentTender::Card => self.card.as_ref(),
+            PaymentTender::QrEwallet => self.qr_ewallet.as_ref(),
+        }
+    }
+}
+
+fn provider_by_name(name: &str) -> Result<Box<dyn PaymentProvider>, Box<dyn Error>> {
+    match name {
+        "mock" => Ok(Box::new(MockProvider)),
+        _ => Err(Box::new(PaymentError(format!(
+            "unknown payment provider {}",
+            name
+        )))),
+    }
+}
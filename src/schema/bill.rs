@@ -0,0 +1,212 @@
+use juniper::{FieldError, FieldResult};
+use postgres::GenericConnection;
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+/// Tax and service-charge rates are stored in basis points, so 1000 is 10%.
+const BASIS_POINTS: i64 = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub struct Bill {
+    pub subtotal: i32,
+    pub discount: i32,
+    pub service_charge: i32,
+    pub tax: i32,
+    pub total: i32,
+}
+
+impl Bill {
+    /// The discount comes off the subtotal first, the service charge is taken
+    /// on the discounted amount and tax is taken on both. Every step rounds
+    /// half up to a whole currency unit so the same order always bills the same.
+    pub fn compute(
+        subtotal: i64,
+        discount: i64,
+        service_charge_rate: i32,
+        tax_rate: i32,
+    ) -> FieldResult<Bill> {
+        let discount = discount.max(0).min(subtotal);
+        let discounted = subtotal - discount;
+        let service_charge = apply_rate(discounted, service_charge_rate);
+        let tax = apply_rate(discounted + service_charge, tax_rate);
+        let total = discounted + service_charge + tax;
+        Ok(Bill {
+            subtotal: to_amount(subtotal)?,
+            discount: to_amount(discount)?,
+            service_charge: to_amount(service_charge)?,
+            tax: to_amount(tax)?,
+            total: to_amount(total)?,
+        })
+    }
+
+    pub fn for_customer_order(
+        conn: &dyn GenericConnection,
+        customer_order_id: &Uuid,
+    ) -> FieldResult<Bill> {
+        let rows = conn.query(
+            "
+            SELECT
+                customer_order.discount,
+                customer_order.tax_rate,
+                customer_order.service_charge_rate,
+                COALESCE((
+                    SELECT SUM(dish_order.unit_price::bigint * dish_order.quantity)
+                    FROM dish_order
+                    WHERE dish_order.customer_order_id = customer_order.id
+                        AND dish_order.status <> 'Voided'
+                ), 0)::bigint AS subtotal
+            FROM customer_order
+            WHERE customer_order.id = $1
+        ",
+            &[customer_order_id],
+        )?;
+        if rows.is_empty() {
+            return Err(FieldError::new(
+                "Order does not exist",
+                graphql_value!({ "external_error": "Order does not exist" }),
+            ));
+        }
+        let row = rows.get(0);
+        let subtotal: i64 = row.get("subtotal");
+        let discount: i32 = row.get("discount");
+        Bill::compute(
+            subtotal,
+            i64::from(discount),
+            row.get("service_charge_rate"),
+            row.get("tax_rate"),
+        )
+    }
+}
+
+fn apply_rate(amount: i64, rate: i32) -> i64 {
+    (amount * i64::from(rate) + BASIS_POINTS / 2) / BASIS_POINTS
+}
+
+pub fn to_amount(amount: i64) -> FieldResult<i32> {
+    i32::try_from(amount).map_err(|_| {
+        FieldError::new(
+            "Bill amount is out of range",
+            graphql_value!({ "internal_error": "Bill amount is out of range" }),
+        )
+    })
+}
+
+/// Divides `total` into `count` shares that differ by at most one unit, with
+/// the earlier shares taking the remainder.
+pub fn split_evenly(total: i32, count: usize) -> Vec<i32> {
+    allocate(total, &vec![1; count])
+}
+
+/// Divides `total` in proportion to `weights` using the largest remainder
+/// method, so the shares always add back up to `total` exactly. Ties go to
+/// the earlier share, and all-zero weights fall back to an even split.
+pub fn allocate(total: i32, weights: &[i64]) -> Vec<i32> {
+    let weight_sum: i64 = weights.iter().sum();
+    if weight_sum <= 0 {
+        return if weights.is_empty() {
+            vec![]
+        } else {
+            split_evenly(total, weights.len())
+        };
+    }
+    let total = i64::from(total);
+    let mut shares: Vec<i64> = weights.iter().map(|w| total * w / weight_sum).collect();
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    by_remainder.sort_by_key(|&i| (-(total * weights[i] % weight_sum), i));
+    let leftover = total - shares.iter().sum::<i64>();
+    for &i in by_remainder.iter().take(leftover as usize) {
+        shares[i] += 1;
+    }
+    shares.into_iter().map(|share| share as i32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_takes_service_charge_before_tax() {
+        let bill = Bill::compute(1000, 0, 1000, 1100).unwrap();
+        assert_eq!(
+            bill,
+            Bill {
+                subtotal: 1000,
+                discount: 0,
+                service_charge: 100,
+                tax: 121,
+                total: 1221,
+            }
+        );
+    }
+
+    #[test]
+    fn compute_rounds_half_up() {
+        assert_eq!(Bill::compute(5, 0, 1000, 0).unwrap().service_charge, 1);
+        assert_eq!(Bill::compute(4, 0, 1000, 0).unwrap().service_charge, 0);
+        assert_eq!(Bill::compute(15, 0, 0, 1000).unwrap().tax, 2);
+        assert_eq!(Bill::compute(14, 0, 0, 1000).unwrap().tax, 1);
+    }
+
+    #[test]
+    fn compute_rounds_each_step() {
+        // 10% of 15 rounds to 2, then 10% of 17 rounds to 2
+        let bill = Bill::compute(15, 0, 1000, 1000).unwrap();
+        assert_eq!((bill.service_charge, bill.tax, bill.total), (2, 2, 19));
+    }
+
+    #[test]
+    fn compute_applies_the_discount_first() {
+        let bill = Bill::compute(1000, 200, 1000, 1000).unwrap();
+        assert_eq!((bill.service_charge, bill.tax, bill.total), (80, 88, 968));
+    }
+
+    #[test]
+    fn compute_clamps_the_discount() {
+        let bill = Bill::compute(1000, 5000, 1000, 1000).unwrap();
+        assert_eq!((bill.discount, bill.total), (1000, 0));
+        let bill = Bill::compute(1000, -50, 0, 0).unwrap();
+        assert_eq!((bill.discount, bill.total), (0, 1000));
+    }
+
+    #[test]
+    fn compute_rejects_totals_out_of_range() {
+        assert!(Bill::compute(i64::from(i32::MAX), 0, 1000, 0).is_err());
+    }
+
+    #[test]
+    fn split_evenly_gives_the_remainder_to_earlier_shares() {
+        assert_eq!(split_evenly(100, 3), vec![34, 33, 33]);
+        assert_eq!(split_evenly(90, 3), vec![30, 30, 30]);
+        assert_eq!(split_evenly(2, 3), vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn allocate_is_proportional_and_adds_up() {
+        let shares = allocate(1000, &[1, 2, 3]);
+        assert_eq!(shares, vec![167, 333, 500]);
+        assert_eq!(shares.iter().sum::<i32>(), 1000);
+    }
+
+    #[test]
+    fn allocate_breaks_remainder_ties_in_order() {
+        assert_eq!(allocate(10, &[1, 1, 1]), vec![4, 3, 3]);
+        assert_eq!(allocate(11, &[1, 1, 1]), vec![4, 4, 3]);
+    }
+
+    #[test]
+    fn allocate_favours_the_largest_remainders() {
+        // exact shares are 3.3, 3.3 and 3.4
+        assert_eq!(allocate(10, &[33, 33, 34]), vec![3, 3, 4]);
+    }
+
+    #[test]
+    fn allocate_splits_evenly_without_weights() {
+        assert_eq!(allocate(10, &[0, 0]), vec![5, 5]);
+        assert_eq!(allocate(10, &[]), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn allocate_gives_nothing_to_zero_weights() {
+        assert_eq!(allocate(10, &[0, 1]), vec![0, 10]);
+    }
+}
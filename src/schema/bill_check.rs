@@ -0,0 +1,392 @@
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::bill::{allocate, split_evenly, Bill};
+use super::context::Context;
+use super::customer_order::{CustomerOrder, CustomerOrderStatus};
+use super::dish_order::DishOrder;
+use super::payment::{Payment, PaymentStatus};
+use super::permission::Permission;
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "bill_check_status")]
+pub enum BillCheckStatus {
+    Open,
+    Paid,
+}
+
+pub struct BillCheck {
+    pub id: String,
+    pub customer_order_id: String,
+    pub label: String,
+    pub position: i32,
+    pub amount: i32,
+    pub status: BillCheckStatus,
+}
+
+impl BillCheck {
+    pub fn from_row(row: &Row) -> BillCheck {
+        let id: Uuid = row.get("id");
+        let customer_order_id: Uuid = row.get("customer_order_id");
+        BillCheck {
+            id: id.hyphenated().to_string(),
+            customer_order_id: customer_order_id.hyphenated().to_string(),
+            label: row.get("label"),
+            position: row.get("position"),
+            amount: row.get("amount"),
+            status: row.get("status"),
+        }
+    }
+
+    pub fn find_for_update(conn: &dyn GenericConnection, id: &Uuid) -> FieldResult<BillCheck> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM bill_check
+            WHERE id = $1
+            FOR UPDATE
+        ",
+            &[id],
+        )?;
+        if rows.is_empty() {
+            return Err(split_error("Check does not exist"));
+        }
+        Ok(BillCheck::from_row(&rows.get(0)))
+    }
+
+    pub fn for_customer_order(
+        conn: &dyn GenericConnection,
+        customer_order_id: &Uuid,
+    ) -> FieldResult<Vec<BillCheck>> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM bill_check
+            WHERE customer_order_id = $1
+            ORDER BY position
+        ",
+            &[customer_order_id],
+        )?;
+        Ok(rows.iter().map(|row| BillCheck::from_row(&row)).collect())
+    }
+}
+
+pub fn check_amount_paid(conn: &dyn GenericConnection, bill_check_id: &Uuid) -> FieldResult<i32> {
+    let rows = conn.query(
+        "
+        SELECT COALESCE(SUM(amount), 0)::int AS amount_paid
+        FROM payment
+        WHERE bill_check_id = $1 AND status = $2
+    ",
+        &[bill_check_id, &PaymentStatus::Succeeded],
+    )?;
+    Ok(rows.get(0).get("amount_paid"))
+}
+
+graphql_object!(BillCheck: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field customer_order_id() -> &str {
+    self.customer_order_id.as_str()
+  }
+  field label() -> &str {
+    self.label.as_str()
+  }
+  field position() -> i32 {
+    self.position
+  }
+  field amount() -> i32 {
+    self.amount
+  }
+  field status() -> &BillCheckStatus {
+    &self.status
+  }
+  field amount_paid(&executor) -> FieldResult<i32> {
+    let conn = executor.context().pool.get()?;
+    let bill_check_uuid = Uuid::parse_str(&self.id)?;
+    check_amount_paid(&*conn, &bill_check_uuid)
+  }
+  field amount_due(&executor) -> FieldResult<i32> {
+    let conn = executor.context().pool.get()?;
+    let bill_check_uuid = Uuid::parse_str(&self.id)?;
+    Ok(self.amount - check_amount_paid(&*conn, &bill_check_uuid)?)
+  }
+  field dishes(&executor) -> FieldResult<Vec<DishOrder>> {
+    let conn = executor.context().pool.get()?;
+    let bill_check_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT dish_order.*
+      FROM dish_order
+      JOIN bill_check_line ON bill_check_line.dish_order_id = dish_order.id
+      WHERE bill_check_line.bill_check_id = $1
+      ORDER BY dish_order.created_at
+    ", &[&bill_check_uuid])?;
+    let mut dishes = vec!();
+    for row in &rows {
+      dishes.push(DishOrder::from_row(&row));
+    }
+    Ok(dishes)
+  }
+  field payments(&executor) -> FieldResult<Vec<Payment>> {
+    let conn = executor.context().pool.get()?;
+    let bill_check_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM payment
+      WHERE bill_check_id = $1
+      ORDER BY created_at
+    ", &[&bill_check_uuid])?;
+    let mut payments = vec!();
+    for row in &rows {
+      payments.push(Payment::from_row(&row));
+    }
+    Ok(payments)
+  }
+});
+
+#[derive(GraphQLInputObject)]
+pub struct ItemCheck {
+    pub label: String,
+    pub dish_order_ids: Vec<String>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct AmountCheck {
+    pub label: String,
+    pub amount: i32,
+}
+
+struct PlannedCheck {
+    label: String,
+    amount: i32,
+    dish_order_ids: Vec<Uuid>,
+}
+
+fn split_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+/// Locks a closed, still unpaid order for splitting and returns its bill.
+fn lock_order_for_split(
+    context: &Context,
+    conn: &dyn GenericConnection,
+    customer_order_uuid: &Uuid,
+) -> FieldResult<(CustomerOrder, Bill)> {
+    let customer_order = CustomerOrder::find_for_update(conn, customer_order_uuid)?;
+    context.authorize_customer_order(&customer_order, Permission::SplitBills)?;
+    if customer_order.status != CustomerOrderStatus::Closed {
+        return Err(split_error("Order must be closed before it can be split"));
+    }
+    let rows = conn.query(
+        "
+        SELECT 1
+        FROM payment
+        WHERE customer_order_id = $1 AND status <> $2
+    ",
+        &[customer_order_uuid, &PaymentStatus::Failed],
+    )?;
+    if !rows.is_empty() {
+        return Err(split_error(
+            "Order already has payments and cannot be split",
+        ));
+    }
+    let bill = Bill::for_customer_order(conn, customer_order_uuid)?;
+    Ok((customer_order, bill))
+}
+
+fn replace_checks(
+    conn: &dyn GenericConnection,
+    customer_order_uuid: &Uuid,
+    checks: Vec<PlannedCheck>,
+) -> FieldResult<Vec<BillCheck>> {
+    // payments must be positive, so a check with nothing on it could never be paid
+    if checks.iter().any(|check| check.amount <= 0) {
+        return Err(split_error(
+            "Every check must have something to pay, try fewer checks",
+        ));
+    }
+    delete_checks(conn, customer_order_uuid)?;
+    for (position, check) in checks.iter().enumerate() {
+        let bill_check_uuid = Uuid::new_v4();
+        conn.execute(
+            "
+            INSERT INTO bill_check (
+                id,
+                customer_order_id,
+                label,
+                position,
+                amount
+            ) VALUES ($1, $2, $3, $4, $5)
+        ",
+            &[
+                &bill_check_uuid,
+                customer_order_uuid,
+                &check.label,
+                &(position as i32),
+                &check.amount,
+            ],
+        )?;
+        for dish_order_uuid in &check.dish_order_ids {
+            conn.execute(
+                "
+                INSERT INTO bill_check_line (
+                    bill_check_id,
+                    dish_order_id
+                ) VALUES ($1, $2)
+            ",
+                &[&bill_check_uuid, dish_order_uuid],
+            )?;
+        }
+    }
+    BillCheck::for_customer_order(conn, customer_order_uuid)
+}
+
+/// Removes every check of an order. Only valid while none of them has been paid.
+pub fn delete_checks(conn: &dyn GenericConnection, customer_order_uuid: &Uuid) -> FieldResult<()> {
+    conn.execute(
+        "
+        DELETE FROM bill_check
+        WHERE customer_order_id = $1
+    ",
+        &[customer_order_uuid],
+    )?;
+    Ok(())
+}
+
+/// Every line of the order goes onto exactly one check. Each check carries its
+/// lines' share of the discount, service charge and tax.
+pub fn split_by_items(
+    context: &Context,
+    customer_order_id: &str,
+    checks: Vec<ItemCheck>,
+) -> FieldResult<Vec<BillCheck>> {
+    let customer_order_uuid = Uuid::parse_str(customer_order_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let (_, bill) = lock_order_for_split(context, &trans, &customer_order_uuid)?;
+    if checks.len() < 2 {
+        return Err(split_error("A split needs at least two checks"));
+    }
+
+    let rows = trans.query(
+        "
+        SELECT id, unit_price::bigint * quantity AS line_total
+        FROM dish_order
+        WHERE customer_order_id = $1 AND status <> 'Voided'
+    ",
+        &[&customer_order_uuid],
+    )?;
+    let mut unassigned: HashSet<Uuid> = rows.iter().map(|row| row.get("id")).collect();
+    let mut planned = vec![];
+    let mut weights = vec![];
+    for check in checks {
+        if check.dish_order_ids.is_empty() {
+            return Err(split_error("Every check needs at least one dish"));
+        }
+        let mut dish_order_ids = vec![];
+        let mut weight = 0;
+        for id in &check.dish_order_ids {
+            let dish_order_uuid = Uuid::parse_str(id)?;
+            if !unassigned.remove(&dish_order_uuid) {
+                return Err(split_error(
+                    "Each dish must belong to this order and appear on one check only",
+                ));
+            }
+            let line_total: i64 = rows
+                .iter()
+                .find(|row| row.get::<_, Uuid>("id") == dish_order_uuid)
+                .map(|row| row.get("line_total"))
+                .unwrap_or(0);
+            weight += line_total;
+            dish_order_ids.push(dish_order_uuid);
+        }
+        weights.push(weight);
+        planned.push(PlannedCheck {
+            label: check.label,
+            amount: 0,
+            dish_order_ids,
+        });
+    }
+    if !unassigned.is_empty() {
+        return Err(split_error("Every dish must be assigned to a check"));
+    }
+    for (check, amount) in planned.iter_mut().zip(allocate(bill.total, &weights)) {
+        check.amount = amount;
+    }
+
+    let checks = replace_checks(&trans, &customer_order_uuid, planned)?;
+    trans.commit()?;
+    Ok(checks)
+}
+
+pub fn split_evenly_into(
+    context: &Context,
+    customer_order_id: &str,
+    count: i32,
+) -> FieldResult<Vec<BillCheck>> {
+    if !(2..=50).contains(&count) {
+        return Err(split_error("An even split needs between 2 and 50 checks"));
+    }
+    let customer_order_uuid = Uuid::parse_str(customer_order_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let (_, bill) = lock_order_for_split(context, &trans, &customer_order_uuid)?;
+    let planned = split_evenly(bill.total, count as usize)
+        .into_iter()
+        .enumerate()
+        .map(|(i, amount)| PlannedCheck {
+            label: format!("Check {}", i + 1),
+            amount,
+            dish_order_ids: vec![],
+        })
+        .collect();
+    let checks = replace_checks(&trans, &customer_order_uuid, planned)?;
+    trans.commit()?;
+    Ok(checks)
+}
+
+pub fn split_by_amounts(
+    context: &Context,
+    customer_order_id: &str,
+    checks: Vec<AmountCheck>,
+) -> FieldResult<Vec<BillCheck>> {
+    let customer_order_uuid = Uuid::parse_str(customer_order_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let (_, bill) = lock_order_for_split(context, &trans, &customer_order_uuid)?;
+    if checks.len() < 2 {
+        return Err(split_error("A split needs at least two checks"));
+    }
+    if checks.iter().any(|check| check.amount <= 0) {
+        return Err(split_error("Every check amount must be positive"));
+    }
+    let sum: i64 = checks.iter().map(|check| i64::from(check.amount)).sum();
+    if sum != i64::from(bill.total) {
+        return Err(split_error("Check amounts must add up to the order total"));
+    }
+    let planned = checks
+        .into_iter()
+        .map(|check| PlannedCheck {
+            label: check.label,
+            amount: check.amount,
+            dish_order_ids: vec![],
+        })
+        .collect();
+    let checks = replace_checks(&trans, &customer_order_uuid, planned)?;
+    trans.commit()?;
+    Ok(checks)
+}
+
+pub fn remove_split(context: &Context, customer_order_id: &str) -> FieldResult<CustomerOrder> {
+    let customer_order_uuid = Uuid::parse_str(customer_order_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let (customer_order, _) = lock_order_for_split(context, &trans, &customer_order_uuid)?;
+    delete_checks(&trans, &customer_order_uuid)?;
+    trans.commit()?;
+    Ok(customer_order)
+}
@@ -1,17 +1,39 @@
-use chrono::prelude::*;
+use chrono::Utc;
+use crypto::digest::Digest;
+use crypto::sha3::Sha3;
 use iron::headers::{Authorization, Bearer};
 use iron::prelude::*;
 use juniper::{FieldError, FieldResult};
-use jwt::{decode, encode, Header, TokenData, Validation};
+use jwt::{decode, TokenData, Validation};
 use r2d2::Pool;
-use r2d2_postgres::{PostgresConnectionManager, TlsMode};
-use r2d2_redis::redis::Commands;
-use r2d2_redis::{r2d2, RedisConnectionManager};
-use rand::{thread_rng, Rng};
+use r2d2_postgres::PostgresConnectionManager;
+use r2d2_redis::redis::{self, Commands, PipelineCommands};
+use r2d2_redis::RedisConnectionManager;
+use rand::rngs::OsRng;
+use rand::Rng;
 use std::env;
+use std::sync::Arc;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+use super::customer::identify_customer;
+use super::customer_order::{CustomerOrder, CustomerOrderError};
+use super::permission::{Permission, StaffRole, CUSTOMER_PERMISSIONS};
+use super::rate_limit::check_rate_limit;
+use super::session::{is_session_active, start_session, AuthTokens};
+use crate::notifier::{Channel, Message, Notifier, Template};
+use crate::payment::PaymentProviders;
+use crate::state::AppState;
+
+/// How long a login code stays valid.
+const AUTH_CODE_SECONDS: usize = 5 * 60;
+/// Wrong guesses before a login code is thrown away.
+const AUTH_CODE_ATTEMPTS: i32 = 5;
+const AUTH_CODE_WINDOW_SECONDS: usize = 15 * 60;
+const AUTH_CODES_PER_PHONE: i64 = 3;
+const AUTH_CODES_PER_IP: i64 = 10;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "actor_role")]
 pub enum Roles {
     Admin,
     Customer,
@@ -24,12 +46,43 @@ pub struct Claims {
     pub company: String,
     pub exp: i64,
     pub role: Roles,
+    /// The session that issued the token; see `session`.
+    pub sid: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AuthCode {
-    client_id: String,
-    role: Roles,
+/// Checks a login code and spends it in one step. Wrong guesses count
+/// against the code, which is deleted once it runs out of attempts.
+const VERIFY_AUTH_CODE_SCRIPT: &str = r"
+local code_hash = redis.call('HGET', KEYS[1], 'code_hash')
+if not code_hash then
+    return 0
+end
+if code_hash == ARGV[1] then
+    redis.call('DEL', KEYS[1])
+    return 1
+end
+if redis.call('HINCRBY', KEYS[1], 'attempts', 1) >= tonumber(ARGV[2]) then
+    redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+fn auth_code_key(phone: &str) -> String {
+    format!("auth_code:{}", phone)
+}
+
+/// A six digit code, leading zeros included.
+fn generate_auth_code<R: Rng>(rng: &mut R) -> String {
+    format!("{:06}", rng.gen_range(0, 1_000_000))
+}
+
+/// Codes are stored hashed, salted with the phone they were sent to.
+fn hash_auth_code(phone: &str, code: &str) -> String {
+    let mut hasher = Sha3::sha3_256();
+    hasher.input_str(phone);
+    hasher.input_str(":");
+    hasher.input_str(code);
+    hasher.result_str()
 }
 
 #[derive(GraphQLInputObject)]
@@ -40,11 +93,46 @@ pub struct AuthInfo {
 pub struct Context {
     pub pool: Pool<PostgresConnectionManager>,
     pub redis_pool: Pool<RedisConnectionManager>,
+    pub payment_providers: Arc<PaymentProviders>,
+    pub notifier: Arc<dyn Notifier>,
     pub claims: Option<TokenData<Claims>>,
+    /// Address of the peer that sent the request, when known.
+    pub client_ip: Option<String>,
 }
 
 impl Context {
-    pub fn create_anonymous_customer_token(&self) -> FieldResult<String> {
+    /// Builds the per-request context from the shared state and the caller's
+    /// bearer token, if any. An invalid token leaves the caller unauthenticated.
+    pub fn new(state: &AppState, token: Option<&str>, client_ip: Option<String>) -> Context {
+        let key = env::var("JWT_AUTH_SECRET").unwrap();
+        let claims = token
+            .and_then(|token| {
+                let validation = Validation::default();
+                decode::<Claims>(token, key.as_ref(), &validation).ok()
+            })
+            .filter(|token_data| is_session_active(&state.redis_pool, &token_data.claims.sid));
+        Context {
+            pool: state.pool.clone(),
+            redis_pool: state.redis_pool.clone(),
+            payment_providers: state.payment_providers.clone(),
+            notifier: state.notifier.clone(),
+            claims,
+            client_ip,
+        }
+    }
+    /// Whether the caller's token is still good, for contexts that outlive a
+    /// request: it must not have expired and its session must not have been
+    /// ended since. Anonymous callers have nothing to lose.
+    pub fn is_token_current(&self, check_session: bool) -> bool {
+        match &self.claims {
+            Some(claims) => {
+                claims.claims.exp > Utc::now().timestamp()
+                    && (!check_session || is_session_active(&self.redis_pool, &claims.claims.sid))
+            }
+            None => true,
+        }
+    }
+    pub fn create_anonymous_customer_token(&self) -> FieldResult<AuthTokens> {
         let conn = self.pool.get()?;
         let anonymous_customer_id = Uuid::new_v4();
         let _inserts = conn.query(
@@ -55,41 +143,30 @@ impl Context {
         ",
             &[&anonymous_customer_id],
         )?;
-        let key = env::var("JWT_AUTH_SECRET")?;
-        let claims = Claims {
-            sub: anonymous_customer_id.hyphenated().to_string(),
-            company: "RRMDN".to_owned(),
-            exp: Utc::now().timestamp() + (60 * 60 * 24),
-            role: Roles::Customer,
-        };
-        let token = encode(&Header::default(), &claims, key.as_ref())?;
-        Ok(token)
+        start_session(
+            self,
+            &anonymous_customer_id.hyphenated().to_string(),
+            Roles::Customer,
+        )
     }
-    pub fn authenticate(&self, code: &String) -> FieldResult<String> {
+    pub fn authenticate(&self, phone: &str, code: &str) -> FieldResult<AuthTokens> {
         let redis = self.redis_pool.get()?;
-        let key = env::var("JWT_AUTH_SECRET")?;
-        let auth_code_string: String = redis.get(code)?;
-        let auth_code: AuthCode = serde_json::from_str(&auth_code_string)?;
-        let claims = Claims {
-            sub: auth_code.client_id,
-            company: "RRMDN".to_owned(),
-            exp: Utc::now().timestamp() + (60 * 60 * 24),
-            role: auth_code.role,
-        };
-        let _: () = redis.del(code)?;
-        let token = encode(&Header::default(), &claims, key.as_ref())?;
-        Ok(token)
-    }
-    pub fn create_partner_token(&self, partner_id: &String) -> FieldResult<String> {
-        let key = env::var("JWT_AUTH_SECRET")?;
-        let claims = Claims {
-            sub: partner_id.to_owned(),
-            company: "RRMDN".to_owned(),
-            exp: Utc::now().timestamp() + (60 * 60 * 24),
-            role: Roles::Partner,
-        };
-        let token = encode(&Header::default(), &claims, key.as_ref())?;
-        Ok(token)
+        let verified: i32 = redis::Script::new(VERIFY_AUTH_CODE_SCRIPT)
+            .key(auth_code_key(phone))
+            .arg(hash_auth_code(phone, code))
+            .arg(AUTH_CODE_ATTEMPTS)
+            .invoke(&*redis)?;
+        if verified != 1 {
+            return Err(FieldError::new(
+                "Code is no longer valid",
+                graphql_value!({ "internal_error": "Code is no longer valid" }),
+            ));
+        }
+        let customer_id = identify_customer(self, phone)?;
+        start_session(self, &customer_id, Roles::Customer)
+    }
+    pub fn create_partner_token(&self, partner_id: &str) -> FieldResult<AuthTokens> {
+        start_session(self, partner_id, Roles::Partner)
     }
     pub fn authorize(&self, role: Roles) -> FieldResult<()> {
         if let Some(claims) = &self.claims {
@@ -98,13 +175,85 @@ impl Context {
             } else {
                 return Err(FieldError::new(
                     "Unauthorized",
-                    graphql_value!({ "internal_error": "Unauthorized" }),
+                    graphql_value!({ "internal_error": "Unauthorized", "code": "UNAUTHORIZED" }),
                 ));
             }
         }
         Err(FieldError::new(
             "Unauthenticated",
-            graphql_value!({ "internal_error": "Unauthenticated" }),
+            graphql_value!({ "internal_error": "Unauthenticated", "code": "UNAUTHENTICATED" }),
+        ))
+    }
+    pub fn get_role(&self) -> FieldResult<Roles> {
+        if let Some(claims) = &self.claims {
+            return Ok(claims.claims.role);
+        }
+        Err(FieldError::new(
+            "Unauthenticated",
+            graphql_value!({ "internal_error": "Unauthenticated", "code": "UNAUTHENTICATED" }),
+        ))
+    }
+    /// Customers may only act on orders they placed or joined, within
+    /// `CUSTOMER_PERMISSIONS`, and partners only on orders placed at their
+    /// restaurant, as far as their role permits.
+    pub fn authorize_customer_order(
+        &self,
+        customer_order: &CustomerOrder,
+        permission: Permission,
+    ) -> FieldResult<()> {
+        match self.get_role()? {
+            Roles::Customer => {
+                if !CUSTOMER_PERMISSIONS.contains(&permission) {
+                    return Err(FieldError::new(
+                        "Unauthorized",
+                        graphql_value!({ "internal_error": "Unauthorized", "code": "UNAUTHORIZED" }),
+                    ));
+                }
+                let customer_id = self.get_client_id()?;
+                if customer_id != &customer_order.customer_id {
+                    let conn = self.pool.get()?;
+                    if !customer_order.has_participant(&*conn, customer_id)? {
+                        return Err(CustomerOrderError::Forbidden.into());
+                    }
+                }
+            }
+            Roles::Partner => {
+                if self.authorize_permission(permission)? != customer_order.restaurant_id {
+                    return Err(CustomerOrderError::Forbidden.into());
+                }
+            }
+            Roles::Admin => {
+                return Err(FieldError::new(
+                    "Unauthorized",
+                    graphql_value!({ "internal_error": "Unauthorized", "code": "UNAUTHORIZED" }),
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// Admins may manage every restaurant and partners only their own, as far
+    /// as their role permits.
+    pub fn authorize_restaurant(
+        &self,
+        restaurant_id: &str,
+        permission: Permission,
+    ) -> FieldResult<()> {
+        match self.get_role()? {
+            Roles::Admin => Ok(()),
+            Roles::Partner if self.authorize_permission(permission)? == restaurant_id => Ok(()),
+            _ => Err(FieldError::new(
+                "Unauthorized",
+                graphql_value!({ "internal_error": "Unauthorized", "code": "UNAUTHORIZED" }),
+            )),
+        }
+    }
+    pub fn get_session_id(&self) -> FieldResult<&String> {
+        if let Some(claims) = &self.claims {
+            return Ok(&claims.claims.sid);
+        }
+        Err(FieldError::new(
+            "Unauthenticated",
+            graphql_value!({ "internal_error": "Unauthenticated", "code": "UNAUTHENTICATED" }),
         ))
     }
     pub fn get_client_id(&self) -> FieldResult<&String> {
@@ -113,7 +262,7 @@ impl Context {
         }
         Err(FieldError::new(
             "Unauthenticated",
-            graphql_value!({ "internal_error": "Unauthenticated" }),
+            graphql_value!({ "internal_error": "Unauthenticated", "code": "UNAUTHENTICATED" }),
         ))
     }
     pub fn get_partner_restaurant_id(&self) -> FieldResult<String> {
@@ -123,7 +272,7 @@ impl Context {
         let db = self.pool.get()?;
         let rows = db.query(
             "
-            SELECT restaurant_id
+            SELECT restaurant_id, is_active
             FROM partner
             WHERE id = $1
         ",
@@ -136,19 +285,30 @@ impl Context {
             ));
         }
         let row = rows.get(0);
+        // deactivation takes effect immediately, not when the token expires
+        let is_active: bool = row.get("is_active");
+        if !is_active {
+            return Err(FieldError::new(
+                "Partner account is not active",
+                graphql_value!({ "internal_error": "Partner account is not active" }),
+            ));
+        }
         let restaurant_id: Uuid = row.get("restaurant_id");
         Ok(restaurant_id.hyphenated().to_string())
     }
-    pub fn request_customer_auth(&self, phone: &String, role: Roles) -> FieldResult<()> {
-        let redis = self.redis_pool.get()?;
+    /// Checks that the caller is active staff whose role grants `permission`
+    /// and returns their restaurant.
+    pub fn authorize_permission(&self, permission: Permission) -> FieldResult<String> {
+        self.authorize(Roles::Partner)?;
+        let partner_uuid = Uuid::parse_str(self.get_client_id()?)?;
         let db = self.pool.get()?;
         let rows = db.query(
             "
-            SELECT *
-            FROM customer
-            WHERE phone = $1
+            SELECT restaurant_id, is_active, staff_role
+            FROM partner
+            WHERE id = $1
         ",
-            &[&phone],
+            &[&partner_uuid],
         )?;
         if rows.is_empty() {
             return Err(FieldError::new(
@@ -157,44 +317,125 @@ impl Context {
             ));
         }
         let row = rows.get(0);
-        let customer_id: Uuid = row.get("id");
-        let code = thread_rng().gen_range(100000, 999999);
-        let auth_code = AuthCode {
-            client_id: customer_id.hyphenated().to_string(),
-            role: role,
+        let is_active: bool = row.get("is_active");
+        let staff_role: StaffRole = row.get("staff_role");
+        if !is_active || !staff_role.permits(permission) {
+            return Err(FieldError::new(
+                "Unauthorized",
+                graphql_value!({ "internal_error": "Unauthorized", "code": "UNAUTHORIZED" }),
+            ));
+        }
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        Ok(restaurant_id.hyphenated().to_string())
+    }
+    /// Sends a one-time login code to the phone. The customer is only looked
+    /// up, or signed up, once the code comes back. Sends are rate-limited per
+    /// phone number and per client IP.
+    pub fn request_customer_auth(&self, phone: &String, channel: Channel) -> FieldResult<()> {
+        // the IP goes first so one client can't use up someone else's phone quota
+        if let Some(client_ip) = &self.client_ip {
+            check_rate_limit(
+                self,
+                &format!("auth_code:ip:{}", client_ip),
+                AUTH_CODES_PER_IP,
+                AUTH_CODE_WINDOW_SECONDS,
+            )?;
+        }
+        check_rate_limit(
+            self,
+            &format!("auth_code:phone:{}", phone),
+            AUTH_CODES_PER_PHONE,
+            AUTH_CODE_WINDOW_SECONDS,
+        )?;
+        let redis = self.redis_pool.get()?;
+        let code = generate_auth_code(&mut OsRng::new()?);
+        // replacing the hash also drops any earlier code and its attempts
+        let _: () = redis::pipe()
+            .atomic()
+            .del(auth_code_key(phone))
+            .ignore()
+            .hset_multiple(
+                auth_code_key(phone),
+                &[
+                    ("code_hash", hash_auth_code(phone, &code)),
+                    ("attempts", "0".to_owned()),
+                ],
+            )
+            .ignore()
+            .expire(auth_code_key(phone), AUTH_CODE_SECONDS)
+            .ignore()
+            .query(&*redis)?;
+        let message = Message {
+            channel,
+            phone,
+            body: Template::LoginCode {
+                code: &code,
+                expires_in_minutes: AUTH_CODE_SECONDS / 60,
+            }
+            .render(channel),
         };
-        let auth_code_string = serde_json::to_string(&auth_code)?;
-        let _: () = redis.set_ex(code, auth_code_string, 3600)?;
+        if let Err(e) = self.notifier.send(&message) {
+            let _: () = redis.del(auth_code_key(phone))?;
+            return Err(FieldError::new(
+                "Could not send the login code",
+                graphql_value!({ "internal_error": (format!("{} notifier: {}", self.notifier.name(), e)) }),
+            ));
+        }
         Ok(())
     }
 }
 
 impl juniper::Context for Context {}
 
-pub fn context_factory(req: &mut Request) -> IronResult<Context> {
+pub fn context_factory(state: &AppState, req: &mut Request) -> IronResult<Context> {
     let auth_header = req.headers.get::<Authorization<Bearer>>();
-    let key = env::var("JWT_AUTH_SECRET").unwrap();
-    let claims = {
-        if let Some(bearer) = auth_header {
-            let validation = Validation::default();
-            match decode::<Claims>(&bearer.0.token, key.as_ref(), &validation) {
-                Err(_) => None,
-                Ok(t) => Some(t),
-            }
-        } else {
-            None
-        }
-    };
-    let manager = PostgresConnectionManager::new(
-        env::var("POSTGRES_CONNECTION_STRING").unwrap(),
-        TlsMode::None,
-    )
-    .unwrap();
-    let redis_manager =
-        RedisConnectionManager::new(env::var("REDIS_CONNECTION_STRING").unwrap().as_str()).unwrap();
-    Ok(Context {
-        pool: Pool::new(manager).unwrap(),
-        redis_pool: Pool::new(redis_manager).unwrap(),
-        claims,
-    })
+    let token = auth_header.map(|bearer| bearer.0.token.as_str());
+    let client_ip = req.remote_addr.ip().to_string();
+    Ok(Context::new(state, token, Some(client_ip)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    #[test]
+    fn auth_codes_are_six_digits() {
+        assert_eq!(generate_auth_code(&mut StepRng::new(0, 0)), "000000");
+        let mut rng = OsRng::new().unwrap();
+        for _ in 0..100 {
+            let code = generate_auth_code(&mut rng);
+            assert_eq!(code.len(), 6);
+            assert!(code.chars().all(|c| c.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn auth_code_hash_is_stable() {
+        let hash = hash_auth_code("+628123456789", "123456");
+        assert_eq!(hash, hash_auth_code("+628123456789", "123456"));
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("123456"));
+    }
+
+    #[test]
+    fn auth_code_hash_is_bound_to_the_phone() {
+        assert_ne!(
+            hash_auth_code("+628123456789", "123456"),
+            hash_auth_code("+628123456780", "123456")
+        );
+    }
+
+    #[test]
+    fn auth_code_hash_tells_codes_apart() {
+        assert_ne!(
+            hash_auth_code("+628123456789", "123456"),
+            hash_auth_code("+628123456789", "123457")
+        );
+        // the separator keeps phone and code from running together
+        assert_ne!(
+            hash_auth_code("+62812", "3456"),
+            hash_auth_code("+6281", "23456")
+        );
+    }
 }
@@ -1,3 +1,11 @@
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use uuid::Uuid;
+
+use super::context::{Context, Roles};
+use super::session::revoke_client_sessions;
+
 #[derive(GraphQLObject)]
 pub struct Customer {
     pub id: String,
@@ -5,3 +13,198 @@ pub struct Customer {
     pub phone: Option<String>,
     pub email: Option<String>,
 }
+
+impl Customer {
+    pub fn from_row(row: &Row) -> Customer {
+        let id: Uuid = row.get("id");
+        Customer {
+            id: id.hyphenated().to_string(),
+            name: row.get("name"),
+            phone: row.get("phone"),
+            email: row.get("email"),
+        }
+    }
+}
+
+/// The signed-in customer; `phone` is empty while they are still anonymous.
+pub fn current_customer(context: &Context) -> FieldResult<Customer> {
+    context.authorize(Roles::Customer)?;
+    let customer_uuid = Uuid::parse_str(context.get_client_id()?)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT *
+        FROM customer
+        WHERE id = $1
+    ",
+        &[&customer_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(FieldError::new(
+            "Not found",
+            graphql_value!({ "internal_error": "Not found" }),
+        ));
+    }
+    Ok(Customer::from_row(&rows.get(0)))
+}
+
+/// Resolves a verified phone number to a customer id, signing the customer up
+/// if nobody owns the number yet. A caller still holding an anonymous customer
+/// token keeps their orders and devices: the anonymous customer either takes
+/// the number or, when the number already has an account, is merged into it.
+pub fn identify_customer(context: &Context, phone: &str) -> FieldResult<String> {
+    let caller_uuid = match context.get_role() {
+        Ok(Roles::Customer) => Some(Uuid::parse_str(context.get_client_id()?)?),
+        _ => None,
+    };
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let rows = trans.query(
+        "
+        SELECT id
+        FROM customer
+        WHERE phone = $1
+        FOR UPDATE
+    ",
+        &[&phone],
+    )?;
+    let identified_uuid: Option<Uuid> = rows.iter().next().map(|row| row.get("id"));
+    let anonymous_uuid = match caller_uuid {
+        Some(caller_uuid) => {
+            let rows = trans.query(
+                "
+                SELECT id
+                FROM customer
+                WHERE id = $1 AND phone IS NULL
+                FOR UPDATE
+            ",
+                &[&caller_uuid],
+            )?;
+            if rows.is_empty() {
+                None
+            } else {
+                Some(caller_uuid)
+            }
+        }
+        None => None,
+    };
+    let mut merged_uuid = None;
+    let customer_uuid = match (identified_uuid, anonymous_uuid) {
+        (Some(identified_uuid), Some(anonymous_uuid)) => {
+            merge_customer(&trans, &anonymous_uuid, &identified_uuid)?;
+            merged_uuid = Some(anonymous_uuid);
+            identified_uuid
+        }
+        (Some(identified_uuid), None) => identified_uuid,
+        (None, Some(anonymous_uuid)) => {
+            trans.execute(
+                "
+                UPDATE customer
+                SET phone = $2
+                WHERE id = $1
+            ",
+                &[&anonymous_uuid, &phone],
+            )?;
+            anonymous_uuid
+        }
+        (None, None) => {
+            let customer_uuid = Uuid::new_v4();
+            trans.execute(
+                "
+                INSERT INTO customer (
+                    id,
+                    phone
+                ) VALUES ($1, $2)
+            ",
+                &[&customer_uuid, &phone],
+            )?;
+            customer_uuid
+        }
+    };
+    trans.commit()?;
+    // the anonymous customer is gone, so are its tokens
+    if let Some(merged_uuid) = merged_uuid {
+        revoke_client_sessions(&context.redis_pool, &merged_uuid.hyphenated().to_string())?;
+    }
+    Ok(customer_uuid.hyphenated().to_string())
+}
+
+/// Moves everything the anonymous customer owns onto the identified one and
+/// deletes the anonymous row.
+fn merge_customer(
+    conn: &dyn GenericConnection,
+    anonymous_uuid: &Uuid,
+    identified_uuid: &Uuid,
+) -> FieldResult<()> {
+    conn.execute(
+        "
+        UPDATE customer_order
+        SET customer_id = $2
+        WHERE customer_id = $1
+    ",
+        &[anonymous_uuid, identified_uuid],
+    )?;
+    conn.execute(
+        "
+        UPDATE dish_order
+        SET customer_id = $2
+        WHERE customer_id = $1
+    ",
+        &[anonymous_uuid, identified_uuid],
+    )?;
+    conn.execute(
+        "
+        UPDATE table_session
+        SET opened_by = $2
+        WHERE opened_by = $1
+    ",
+        &[anonymous_uuid, identified_uuid],
+    )?;
+    conn.execute(
+        "
+        UPDATE device_info
+        SET customer_id = $2
+        WHERE customer_id = $1
+    ",
+        &[anonymous_uuid, identified_uuid],
+    )?;
+    conn.execute(
+        "
+        INSERT INTO customer_order_participant (
+            customer_order_id,
+            customer_id,
+            joined_at
+        )
+        SELECT customer_order_id, $2, joined_at
+        FROM customer_order_participant
+        WHERE customer_id = $1
+        ON CONFLICT DO NOTHING
+    ",
+        &[anonymous_uuid, identified_uuid],
+    )?;
+    conn.execute(
+        "
+        DELETE FROM customer_order_participant
+        WHERE customer_id = $1
+    ",
+        &[anonymous_uuid],
+    )?;
+    conn.execute(
+        "
+        UPDATE customer
+        SET name = COALESCE(customer.name, anonymous.name),
+            email = COALESCE(customer.email, anonymous.email)
+        FROM customer anonymous
+        WHERE customer.id = $2 AND anonymous.id = $1
+    ",
+        &[anonymous_uuid, identified_uuid],
+    )?;
+    conn.execute(
+        "
+        DELETE FROM customer
+        WHERE id = $1
+    ",
+        &[anonymous_uuid],
+    )?;
+    Ok(())
+}
@@ -1,9 +1,27 @@
-use super::context::Context;
+use super::bill::{to_amount, Bill};
+use super::bill_check::{delete_checks, BillCheck, BillCheckStatus};
+use super::context::{Context, Roles};
 use super::dish_order::DishOrder;
-use juniper::FieldResult;
+use super::order_event::{publish_order_event, OrderEvent, OrderEventKind};
+use super::payment::{amount_paid, Payment};
+use super::permission::Permission;
+use super::rate_limit::check_rate_limit;
+use super::table_session::close_table_session_for_order;
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use rand::rngs::OsRng;
+use rand::Rng;
 use uuid::Uuid;
 
-#[derive(Debug, ToSql, FromSql, GraphQLEnum)]
+/// Invite codes avoid characters that are easy to misread off a phone screen.
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_CODE_LENGTH: usize = 8;
+const JOINS_PER_CUSTOMER: i64 = 10;
+const JOIN_WINDOW_SECONDS: usize = 15 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSql, FromSql, GraphQLEnum)]
 #[postgres(name = "customer_order_status")]
 pub enum CustomerOrderStatus {
     Open,
@@ -11,12 +29,344 @@ pub enum CustomerOrderStatus {
     Done,
 }
 
+impl CustomerOrderStatus {
+    /// An open order can be closed for billing, a closed order can be reopened
+    /// or settled, and a settled order is final.
+    pub fn can_transition_to(self, next: CustomerOrderStatus) -> bool {
+        matches!(
+            (self, next),
+            (CustomerOrderStatus::Open, CustomerOrderStatus::Closed)
+                | (CustomerOrderStatus::Closed, CustomerOrderStatus::Open)
+                | (CustomerOrderStatus::Closed, CustomerOrderStatus::Done)
+        )
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            CustomerOrderStatus::Open => "OPEN",
+            CustomerOrderStatus::Closed => "CLOSED",
+            CustomerOrderStatus::Done => "DONE",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CustomerOrderError {
+    NotFound,
+    Forbidden,
+    NotOpen,
+    Unpaid {
+        amount_due: i32,
+    },
+    InvalidTransition {
+        from: CustomerOrderStatus,
+        to: CustomerOrderStatus,
+    },
+}
+
+impl From<CustomerOrderError> for FieldError {
+    fn from(error: CustomerOrderError) -> FieldError {
+        match error {
+            CustomerOrderError::NotFound => FieldError::new(
+                "Order does not exist",
+                graphql_value!({ "external_error": "Order does not exist", "code": "NOT_FOUND" }),
+            ),
+            CustomerOrderError::Forbidden => FieldError::new(
+                "Order belongs to someone else",
+                graphql_value!({ "external_error": "Order belongs to someone else", "code": "FORBIDDEN" }),
+            ),
+            CustomerOrderError::NotOpen => FieldError::new(
+                "Order is no longer open",
+                graphql_value!({ "external_error": "Order is no longer open", "code": "ORDER_NOT_OPEN" }),
+            ),
+            CustomerOrderError::Unpaid { amount_due } => {
+                let message = format!("Order still has {} to pay", amount_due);
+                FieldError::new(
+                    &message,
+                    graphql_value!({
+                        "external_error": (message.as_str()),
+                        "code": "ORDER_NOT_PAID",
+                        "amount_due": amount_due
+                    }),
+                )
+            }
+            CustomerOrderError::InvalidTransition { from, to } => {
+                let message = format!("Order cannot go from {} to {}", from.as_str(), to.as_str());
+                let from = from.as_str();
+                let to = to.as_str();
+                FieldError::new(
+                    &message,
+                    graphql_value!({
+                        "external_error": (message.as_str()),
+                        "code": "INVALID_TRANSITION",
+                        "from": from,
+                        "to": to
+                    }),
+                )
+            }
+        }
+    }
+}
+
+/// What one diner at a shared table has ordered, before discount, service
+/// charge and tax.
+#[derive(GraphQLObject)]
+pub struct DinerSubtotal {
+    pub customer_id: Option<String>,
+    pub name: Option<String>,
+    pub subtotal: i32,
+}
+
 pub struct CustomerOrder {
     pub id: String,
     pub restaurant_id: String,
     pub dining_table_id: String,
     pub customer_id: String,
     pub status: CustomerOrderStatus,
+    /// Lets other diners at the table join the order.
+    pub invite_code: Option<String>,
+}
+
+impl CustomerOrder {
+    pub fn from_row(row: &Row) -> CustomerOrder {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        let dining_table_id: Uuid = row.get("dining_table_id");
+        let customer_id: Uuid = row.get("customer_id");
+        CustomerOrder {
+            id: id.hyphenated().to_string(),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            dining_table_id: dining_table_id.hyphenated().to_string(),
+            customer_id: customer_id.hyphenated().to_string(),
+            status: row.get("status"),
+            invite_code: row.get("invite_code"),
+        }
+    }
+
+    /// Whether the customer joined the order with its invite code.
+    pub fn has_participant(
+        &self,
+        conn: &dyn GenericConnection,
+        customer_id: &str,
+    ) -> FieldResult<bool> {
+        let customer_order_uuid = Uuid::parse_str(&self.id)?;
+        let customer_uuid = Uuid::parse_str(customer_id)?;
+        let rows = conn.query(
+            "
+            SELECT 1
+            FROM customer_order_participant
+            WHERE customer_order_id = $1 AND customer_id = $2
+        ",
+            &[&customer_order_uuid, &customer_uuid],
+        )?;
+        Ok(!rows.is_empty())
+    }
+
+    pub fn bill(&self, context: &Context) -> FieldResult<Bill> {
+        let conn = context.pool.get()?;
+        let customer_order_uuid = Uuid::parse_str(&self.id)?;
+        Bill::for_customer_order(&*conn, &customer_order_uuid)
+    }
+
+    pub fn find(conn: &dyn GenericConnection, id: &Uuid) -> FieldResult<CustomerOrder> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM customer_order
+            WHERE id = $1
+        ",
+            &[id],
+        )?;
+        if rows.is_empty() {
+            return Err(CustomerOrderError::NotFound.into());
+        }
+        Ok(CustomerOrder::from_row(&rows.get(0)))
+    }
+
+    /// Loads the order and locks its row until the surrounding transaction ends.
+    pub fn find_for_update(conn: &dyn GenericConnection, id: &Uuid) -> FieldResult<CustomerOrder> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM customer_order
+            WHERE id = $1
+            FOR UPDATE
+        ",
+            &[id],
+        )?;
+        if rows.is_empty() {
+            return Err(CustomerOrderError::NotFound.into());
+        }
+        Ok(CustomerOrder::from_row(&rows.get(0)))
+    }
+
+    /// Moves the order to `next` and records who did it. The caller owns the
+    /// transaction and must already hold the row lock.
+    pub fn transition(
+        self,
+        conn: &dyn GenericConnection,
+        next: CustomerOrderStatus,
+        changed_by: &Uuid,
+        changed_by_role: Roles,
+    ) -> FieldResult<CustomerOrder> {
+        if !self.status.can_transition_to(next) {
+            return Err(CustomerOrderError::InvalidTransition {
+                from: self.status,
+                to: next,
+            }
+            .into());
+        }
+        let customer_order_uuid = Uuid::parse_str(&self.id)?;
+        if next == CustomerOrderStatus::Done {
+            // only a fully paid order can be settled, see `create_payment`
+            let bill = Bill::for_customer_order(conn, &customer_order_uuid)?;
+            let amount_due = bill.total - amount_paid(conn, &customer_order_uuid)?;
+            if amount_due > 0 {
+                return Err(CustomerOrderError::Unpaid { amount_due }.into());
+            }
+            // a split order closes only when every check is settled
+            let open_checks = BillCheck::for_customer_order(conn, &customer_order_uuid)?
+                .into_iter()
+                .filter(|bill_check| bill_check.status != BillCheckStatus::Paid)
+                .count();
+            if open_checks > 0 {
+                return Err(FieldError::new(
+                    "Order still has checks to pay",
+                    graphql_value!({ "external_error": "Order still has checks to pay", "code": "ORDER_NOT_PAID" }),
+                ));
+            }
+            close_table_session_for_order(conn, &customer_order_uuid)?;
+        }
+        if next == CustomerOrderStatus::Open {
+            // reopening changes the bill, so any split has to be redone; a
+            // payment taken or still in flight pins it
+            let rows = conn.query(
+                "
+                SELECT 1
+                FROM payment
+                WHERE payment.customer_order_id = $1 AND payment.status <> 'Failed'
+            ",
+                &[&customer_order_uuid],
+            )?;
+            if !rows.is_empty() {
+                return Err(FieldError::new(
+                    "Order has payments and cannot be reopened",
+                    graphql_value!({ "external_error": "Order has payments and cannot be reopened" }),
+                ));
+            }
+            delete_checks(conn, &customer_order_uuid)?;
+        }
+        conn.execute(
+            "
+            UPDATE customer_order
+            SET status = $2, updated_at = now()
+            WHERE id = $1
+        ",
+            &[&customer_order_uuid, &next],
+        )?;
+        conn.execute(
+            "
+            INSERT INTO customer_order_status_change (
+                id,
+                customer_order_id,
+                from_status,
+                to_status,
+                changed_by,
+                changed_by_role
+            ) VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+            &[
+                &Uuid::new_v4(),
+                &customer_order_uuid,
+                &self.status,
+                &next,
+                changed_by,
+                &changed_by_role,
+            ],
+        )?;
+        Ok(CustomerOrder {
+            status: next,
+            ..self
+        })
+    }
+}
+
+/// Runs a single lifecycle transition on behalf of the caller, who must be the
+/// ordering customer or a partner of the order's restaurant holding `permission`.
+pub fn transition_customer_order(
+    context: &Context,
+    id: &str,
+    next: CustomerOrderStatus,
+    permission: Permission,
+) -> FieldResult<CustomerOrder> {
+    let customer_order_uuid = Uuid::parse_str(id)?;
+    let actor_uuid = Uuid::parse_str(context.get_client_id()?)?;
+    let actor_role = context.get_role()?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let customer_order = CustomerOrder::find_for_update(&trans, &customer_order_uuid)?;
+    context.authorize_customer_order(&customer_order, permission)?;
+    let customer_order = customer_order.transition(&trans, next, &actor_uuid, actor_role)?;
+    trans.commit()?;
+    publish_order_event(
+        context,
+        &OrderEvent::for_customer_order(
+            OrderEventKind::CustomerOrderStatusChanged,
+            &customer_order,
+        ),
+    );
+    Ok(customer_order)
+}
+
+pub fn generate_invite_code() -> FieldResult<String> {
+    let mut rng = OsRng::new()?;
+    Ok((0..INVITE_CODE_LENGTH)
+        .map(|_| INVITE_CODE_ALPHABET[rng.gen_range(0, INVITE_CODE_ALPHABET.len())] as char)
+        .collect())
+}
+
+/// Adds the calling customer to the open order behind `invite_code`. Joins
+/// are rate-limited per customer so codes can't be guessed.
+pub fn join_customer_order(context: &Context, invite_code: &str) -> FieldResult<CustomerOrder> {
+    context.authorize(Roles::Customer)?;
+    let customer_id = context.get_client_id()?;
+    check_rate_limit(
+        context,
+        &format!("join_order:{}", customer_id),
+        JOINS_PER_CUSTOMER,
+        JOIN_WINDOW_SECONDS,
+    )?;
+    let customer_uuid = Uuid::parse_str(customer_id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT *
+        FROM customer_order
+        WHERE invite_code = $1
+    ",
+        &[&invite_code.trim().to_uppercase()],
+    )?;
+    if rows.is_empty() {
+        return Err(CustomerOrderError::NotFound.into());
+    }
+    let customer_order = CustomerOrder::from_row(&rows.get(0));
+    if customer_order.status != CustomerOrderStatus::Open {
+        return Err(CustomerOrderError::NotOpen.into());
+    }
+    if &customer_order.customer_id != customer_id {
+        let customer_order_uuid = Uuid::parse_str(&customer_order.id)?;
+        conn.execute(
+            "
+            INSERT INTO customer_order_participant (
+                customer_order_id,
+                customer_id
+            ) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+        ",
+            &[&customer_order_uuid, &customer_uuid],
+        )?;
+    }
+    Ok(customer_order)
 }
 
 graphql_object!(CustomerOrder: Context | &self | {
@@ -35,6 +385,9 @@ graphql_object!(CustomerOrder: Context | &self | {
   field status() -> &CustomerOrderStatus {
     &self.status
   }
+  field invite_code() -> &Option<String> {
+    &self.invite_code
+  }
   field dishes(&executor) -> FieldResult<Vec<DishOrder>> {
     let conn = executor.context().pool.get()?;
     let customer_order_uuid = Uuid::parse_str(&self.id)?;
@@ -44,26 +397,155 @@ graphql_object!(CustomerOrder: Context | &self | {
       WHERE customer_order_id = $1
     ", &[&customer_order_uuid])?;
     let mut dishes = vec!();
+    for row in &rows {
+      dishes.push(DishOrder::from_row(&row));
+    }
+    Ok(dishes)
+  }
+  field diner_subtotals(&executor) -> FieldResult<Vec<DinerSubtotal>> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT
+        dish_order.customer_id,
+        customer.name,
+        SUM(dish_order.unit_price::bigint * dish_order.quantity)::bigint AS subtotal
+      FROM dish_order
+      LEFT JOIN customer ON customer.id = dish_order.customer_id
+      WHERE dish_order.customer_order_id = $1 AND dish_order.status <> 'Voided'
+      GROUP BY dish_order.customer_id, customer.name
+      ORDER BY MIN(dish_order.created_at)
+    ", &[&customer_order_uuid])?;
+    let mut subtotals = vec!();
+    for row in &rows {
+      let customer_id: Option<Uuid> = row.get("customer_id");
+      let subtotal: i64 = row.get("subtotal");
+      subtotals.push(DinerSubtotal {
+        customer_id: customer_id.map(|id| id.hyphenated().to_string()),
+        name: row.get("name"),
+        subtotal: to_amount(subtotal)?,
+      });
+    }
+    Ok(subtotals)
+  }
+  field subtotal(&executor) -> FieldResult<i32> {
+    Ok(self.bill(executor.context())?.subtotal)
+  }
+  field discount(&executor) -> FieldResult<i32> {
+    Ok(self.bill(executor.context())?.discount)
+  }
+  field service_charge(&executor) -> FieldResult<i32> {
+    Ok(self.bill(executor.context())?.service_charge)
+  }
+  field tax(&executor) -> FieldResult<i32> {
+    Ok(self.bill(executor.context())?.tax)
+  }
+  field total(&executor) -> FieldResult<i32> {
+    Ok(self.bill(executor.context())?.total)
+  }
+  field amount_paid(&executor) -> FieldResult<i32> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.id)?;
+    amount_paid(&*conn, &customer_order_uuid)
+  }
+  field amount_due(&executor) -> FieldResult<i32> {
+    let context = executor.context();
+    let conn = context.pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.id)?;
+    Ok(self.bill(context)?.total - amount_paid(&*conn, &customer_order_uuid)?)
+  }
+  field payments(&executor) -> FieldResult<Vec<Payment>> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM payment
+      WHERE customer_order_id = $1
+      ORDER BY created_at
+    ", &[&customer_order_uuid])?;
+    let mut payments = vec!();
+    for row in &rows {
+      payments.push(Payment::from_row(&row));
+    }
+    Ok(payments)
+  }
+  field checks(&executor) -> FieldResult<Vec<BillCheck>> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.id)?;
+    BillCheck::for_customer_order(&*conn, &customer_order_uuid)
+  }
+  field status_changes(&executor) -> FieldResult<Vec<CustomerOrderStatusChange>> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM customer_order_status_change
+      WHERE customer_order_id = $1
+      ORDER BY created_at
+    ", &[&customer_order_uuid])?;
+    let mut status_changes = vec!();
     for row in &rows {
       let id: Uuid = row.get("id");
-      let dish_id: Uuid = row.get("dish_id");
-      let customer_order_id: Uuid = row.get("customer_order_id");
-      let note: Option<String> = row.get("note");
-      let quantity: i32 = row.get("quantity");
-      let dish = DishOrder {
+      let changed_by: Uuid = row.get("changed_by");
+      status_changes.push(CustomerOrderStatusChange {
         id: id.hyphenated().to_string(),
-        dish_id: dish_id.hyphenated().to_string(),
-        customer_order_id: customer_order_id.hyphenated().to_string(),
-        note: note,
-        quantity: quantity,
-      };
-      dishes.push(dish);
+        from_status: row.get("from_status"),
+        to_status: row.get("to_status"),
+        changed_by: changed_by.hyphenated().to_string(),
+        changed_by_role: row.get("changed_by_role"),
+        created_at: row.get("created_at"),
+      });
     }
-    Ok(dishes)
+    Ok(status_changes)
   }
 });
 
+#[derive(GraphQLObject)]
+pub struct CustomerOrderStatusChange {
+    pub id: String,
+    pub from_status: CustomerOrderStatus,
+    pub to_status: CustomerOrderStatus,
+    pub changed_by: String,
+    pub changed_by_role: Roles,
+    pub created_at: NaiveDateTime,
+}
+
 #[derive(GraphQLInputObject)]
 pub struct NewCustomerOrder {
-    pub dining_table_id: String,
+    /// Read from the QR code on the table.
+    pub table_token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_STATUSES: [CustomerOrderStatus; 3] = [
+        CustomerOrderStatus::Open,
+        CustomerOrderStatus::Closed,
+        CustomerOrderStatus::Done,
+    ];
+
+    #[test]
+    fn open_orders_can_only_be_closed() {
+        let open = CustomerOrderStatus::Open;
+        assert!(open.can_transition_to(CustomerOrderStatus::Closed));
+        assert!(!open.can_transition_to(CustomerOrderStatus::Done));
+        assert!(!open.can_transition_to(CustomerOrderStatus::Open));
+    }
+
+    #[test]
+    fn closed_orders_can_be_reopened_or_settled() {
+        let closed = CustomerOrderStatus::Closed;
+        assert!(closed.can_transition_to(CustomerOrderStatus::Open));
+        assert!(closed.can_transition_to(CustomerOrderStatus::Done));
+        assert!(!closed.can_transition_to(CustomerOrderStatus::Closed));
+    }
+
+    #[test]
+    fn settled_orders_are_final() {
+        for next in ALL_STATUSES.iter() {
+            assert!(!CustomerOrderStatus::Done.can_transition_to(*next));
+        }
+    }
 }
@@ -1,11 +1,462 @@
-#[derive(GraphQLObject)]
+use chrono::NaiveDateTime;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use std::env;
+use uuid::Uuid;
+
+use super::context::Context;
+use super::customer_order::CustomerOrderStatus;
+use super::floor_area::FloorArea;
+use super::permission::Permission;
+use super::table_session::TableSession;
+
+/// Hex characters of the HMAC kept in a table token; 128 bits is plenty and
+/// keeps the QR code small.
+const TABLE_TOKEN_SIGNATURE_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "dining_table_shape")]
+pub enum DiningTableShape {
+    Square,
+    Rectangle,
+    Round,
+}
+
+/// Where a table is in its cycle, as shown on the partner floor map.
+#[derive(Debug, Clone, Copy, PartialEq, GraphQLEnum)]
+pub enum DiningTableStatus {
+    Free,
+    Occupied,
+    /// The order has been closed for billing and awaits payment.
+    BillRequested,
+    /// The party has left and staff have not yet cleared the table.
+    NeedsCleaning,
+}
+
+impl DiningTableStatus {
+    /// Derives the status from the order of the table's open session, if any.
+    pub fn derive(
+        open_order_status: Option<CustomerOrderStatus>,
+        needs_cleaning: bool,
+    ) -> DiningTableStatus {
+        match open_order_status {
+            Some(CustomerOrderStatus::Closed) => DiningTableStatus::BillRequested,
+            Some(_) => DiningTableStatus::Occupied,
+            None if needs_cleaning => DiningTableStatus::NeedsCleaning,
+            None => DiningTableStatus::Free,
+        }
+    }
+}
+
 pub struct DiningTable {
     pub id: String,
     pub name: String,
     pub restaurant_id: String,
+    pub archived_at: Option<NaiveDateTime>,
+    pub floor_area_id: Option<String>,
+    pub capacity: i32,
+    pub shape: DiningTableShape,
+    pub position_x: f64,
+    pub position_y: f64,
+    pub needs_cleaning: bool,
+}
+
+impl DiningTable {
+    pub fn from_row(row: &Row) -> DiningTable {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        let floor_area_id: Option<Uuid> = row.get("floor_area_id");
+        DiningTable {
+            id: id.hyphenated().to_string(),
+            name: row.get("name"),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            archived_at: row.get("archived_at"),
+            floor_area_id: floor_area_id.map(|id| id.hyphenated().to_string()),
+            capacity: row.get("capacity"),
+            shape: row.get("shape"),
+            position_x: row.get("position_x"),
+            position_y: row.get("position_y"),
+            needs_cleaning: row.get("needs_cleaning"),
+        }
+    }
 }
 
 #[derive(GraphQLInputObject)]
 pub struct NewDiningTable {
     pub name: String,
+    pub floor_area_id: Option<String>,
+    pub capacity: Option<i32>,
+    pub shape: Option<DiningTableShape>,
+    pub position_x: Option<f64>,
+    pub position_y: Option<f64>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct DiningTableUpdate {
+    pub name: Option<String>,
+    pub floor_area_id: Option<String>,
+    /// Takes the table out of its floor area.
+    pub clear_floor_area: Option<bool>,
+    pub capacity: Option<i32>,
+    pub shape: Option<DiningTableShape>,
+    pub position_x: Option<f64>,
+    pub position_y: Option<f64>,
+}
+
+pub fn validate_capacity(capacity: Option<i32>) -> FieldResult<()> {
+    match capacity {
+        Some(capacity) if capacity < 1 => Err(FieldError::new(
+            "A table seats at least one diner",
+            graphql_value!({ "external_error": "A table seats at least one diner", "code": "INVALID_CAPACITY" }),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Flags a table for clearing once its party has left it.
+pub fn mark_needs_cleaning(
+    conn: &dyn GenericConnection,
+    dining_table_id: &Uuid,
+) -> FieldResult<()> {
+    conn.execute(
+        "
+        UPDATE dining_table
+        SET needs_cleaning = true
+        WHERE id = $1
+    ",
+        &[dining_table_id],
+    )?;
+    Ok(())
+}
+
+/// Clears a table after its party has left, putting it back into service.
+pub fn mark_dining_table_clean(context: &Context, id: &str) -> FieldResult<DiningTable> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageOrders)?)?;
+    let dining_table_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        UPDATE dining_table
+        SET needs_cleaning = false
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[&dining_table_uuid, &restaurant_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(FieldError::new(
+            "Not found",
+            graphql_value!({ "internal_error": "Not found" }),
+        ));
+    }
+    Ok(DiningTable::from_row(&rows.get(0)))
+}
+
+fn invalid_table_token() -> FieldError {
+    FieldError::new(
+        "Table code is not valid, please scan it again",
+        graphql_value!({ "external_error": "Table code is not valid, please scan it again", "code": "INVALID_TABLE_TOKEN" }),
+    )
+}
+
+fn table_token_secret() -> FieldResult<String> {
+    Ok(env::var("TABLE_TOKEN_SECRET")?)
+}
+
+fn sign_table(secret: &str, dining_table_id: &Uuid, restaurant_id: &Uuid, version: i32) -> String {
+    let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+    mac.input(
+        format!(
+            "{}:{}:{}",
+            restaurant_id.simple(),
+            dining_table_id.simple(),
+            version
+        )
+        .as_bytes(),
+    );
+    let signature: String = mac
+        .result()
+        .code()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    signature[..TABLE_TOKEN_SIGNATURE_LENGTH].to_owned()
+}
+
+fn encode_table_token(
+    secret: &str,
+    dining_table_id: &Uuid,
+    restaurant_id: &Uuid,
+    version: i32,
+) -> String {
+    format!(
+        "{}.{}.{}",
+        dining_table_id.simple(),
+        version,
+        sign_table(secret, dining_table_id, restaurant_id, version)
+    )
+}
+
+/// The table a token names, before anything about it is trusted.
+fn token_table_id(token: &str) -> FieldResult<Uuid> {
+    let id = token.trim().split('.').next().unwrap_or("");
+    Uuid::parse_str(id).map_err(|_| invalid_table_token())
+}
+
+/// Checks that `token` was signed for this table of this restaurant at the
+/// table's current version.
+fn check_table_token(
+    secret: &str,
+    token: &str,
+    dining_table_id: &Uuid,
+    restaurant_id: &Uuid,
+    current_version: i32,
+) -> FieldResult<()> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() != 3 {
+        return Err(invalid_table_token());
+    }
+    let token_table_uuid = Uuid::parse_str(parts[0]).map_err(|_| invalid_table_token())?;
+    let version: i32 = parts[1].parse().map_err(|_| invalid_table_token())?;
+    if token_table_uuid != *dining_table_id || version != current_version {
+        return Err(invalid_table_token());
+    }
+    let expected = sign_table(secret, dining_table_id, restaurant_id, version);
+    if !fixed_time_eq(expected.as_bytes(), parts[2].as_bytes()) {
+        return Err(invalid_table_token());
+    }
+    Ok(())
+}
+
+/// The token printed in a table's QR code, `<table>.<version>.<signature>`.
+/// The signature covers the table, its restaurant and the table's current
+/// token version, so bumping the version retires every printed code.
+pub fn table_token(
+    dining_table_id: &Uuid,
+    restaurant_id: &Uuid,
+    version: i32,
+) -> FieldResult<String> {
+    Ok(encode_table_token(
+        &table_token_secret()?,
+        dining_table_id,
+        restaurant_id,
+        version,
+    ))
+}
+
+/// Checks a scanned token against the table it names and returns the table,
+/// which must still be in use.
+pub fn resolve_table_token(conn: &dyn GenericConnection, token: &str) -> FieldResult<DiningTable> {
+    let dining_table_uuid = token_table_id(token)?;
+    let rows = conn.query(
+        "
+        SELECT dining_table.*
+        FROM dining_table
+        JOIN restaurant ON restaurant.id = dining_table.restaurant_id
+        WHERE dining_table.id = $1
+            AND dining_table.archived_at IS NULL
+            AND restaurant.archived_at IS NULL
+    ",
+        &[&dining_table_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(invalid_table_token());
+    }
+    let row = rows.get(0);
+    let restaurant_uuid: Uuid = row.get("restaurant_id");
+    check_table_token(
+        &table_token_secret()?,
+        token,
+        &dining_table_uuid,
+        &restaurant_uuid,
+        row.get("qr_token_version"),
+    )?;
+    Ok(DiningTable::from_row(&row))
+}
+
+graphql_object!(DiningTable: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field name() -> &str {
+    self.name.as_str()
+  }
+  field restaurant_id() -> &str {
+    self.restaurant_id.as_str()
+  }
+  field archived_at() -> &Option<NaiveDateTime> {
+    &self.archived_at
+  }
+  field floor_area_id() -> &Option<String> {
+    &self.floor_area_id
+  }
+  field capacity() -> i32 {
+    self.capacity
+  }
+  field shape() -> DiningTableShape {
+    self.shape
+  }
+  field position_x() -> f64 {
+    self.position_x
+  }
+  field position_y() -> f64 {
+    self.position_y
+  }
+  field floor_area(&executor) -> FieldResult<Option<FloorArea>> {
+    let floor_area_uuid = match self.floor_area_id {
+      Some(ref floor_area_id) => Uuid::parse_str(floor_area_id)?,
+      None => return Ok(None),
+    };
+    let conn = executor.context().pool.get()?;
+    let rows = conn.query("
+      SELECT *
+      FROM floor_area
+      WHERE id = $1
+    ", &[&floor_area_uuid])?;
+    Ok(rows.iter().next().map(|row| FloorArea::from_row(&row)))
+  }
+  field status(&executor) -> FieldResult<DiningTableStatus> {
+    let context = executor.context();
+    context.authorize_restaurant(&self.restaurant_id, Permission::ViewOrders)?;
+    let conn = context.pool.get()?;
+    let dining_table_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT customer_order.status
+      FROM table_session
+      JOIN customer_order ON customer_order.id = table_session.customer_order_id
+      WHERE table_session.dining_table_id = $1 AND table_session.closed_at IS NULL
+    ", &[&dining_table_uuid])?;
+    let open_order_status = rows.iter().next().map(|row| row.get("status"));
+    Ok(DiningTableStatus::derive(open_order_status, self.needs_cleaning))
+  }
+  field table_session(&executor) -> FieldResult<Option<TableSession>> {
+    let context = executor.context();
+    context.authorize_restaurant(&self.restaurant_id, Permission::ViewOrders)?;
+    let conn = context.pool.get()?;
+    let dining_table_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM table_session
+      WHERE dining_table_id = $1 AND closed_at IS NULL
+    ", &[&dining_table_uuid])?;
+    Ok(rows.iter().next().map(|row| TableSession::from_row(&row)))
+  }
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    #[test]
+    fn status_is_free_without_a_session() {
+        assert_eq!(
+            DiningTableStatus::derive(None, false),
+            DiningTableStatus::Free
+        );
+    }
+
+    #[test]
+    fn status_needs_cleaning_after_the_party_leaves() {
+        assert_eq!(
+            DiningTableStatus::derive(None, true),
+            DiningTableStatus::NeedsCleaning
+        );
+    }
+
+    #[test]
+    fn status_is_occupied_while_ordering() {
+        assert_eq!(
+            DiningTableStatus::derive(Some(CustomerOrderStatus::Open), false),
+            DiningTableStatus::Occupied
+        );
+        // a seated party hides a stale cleaning flag
+        assert_eq!(
+            DiningTableStatus::derive(Some(CustomerOrderStatus::Open), true),
+            DiningTableStatus::Occupied
+        );
+    }
+
+    #[test]
+    fn status_shows_a_requested_bill() {
+        assert_eq!(
+            DiningTableStatus::derive(Some(CustomerOrderStatus::Closed), false),
+            DiningTableStatus::BillRequested
+        );
+    }
+
+    #[test]
+    fn capacity_must_seat_someone() {
+        assert!(validate_capacity(None).is_ok());
+        assert!(validate_capacity(Some(1)).is_ok());
+        assert!(validate_capacity(Some(0)).is_err());
+        assert!(validate_capacity(Some(-2)).is_err());
+    }
+
+    fn ids() -> (Uuid, Uuid) {
+        (Uuid::new_v4(), Uuid::new_v4())
+    }
+
+    #[test]
+    fn table_token_round_trips() {
+        let (table, restaurant) = ids();
+        let token = encode_table_token(SECRET, &table, &restaurant, 3);
+        assert_eq!(token_table_id(&token).unwrap(), table);
+        assert!(check_table_token(SECRET, &token, &table, &restaurant, 3).is_ok());
+    }
+
+    #[test]
+    fn table_token_rejects_a_tampered_signature() {
+        let (table, restaurant) = ids();
+        let mut token = encode_table_token(SECRET, &table, &restaurant, 1);
+        let last = if token.ends_with('0') { "1" } else { "0" };
+        token.pop();
+        token.push_str(last);
+        assert!(check_table_token(SECRET, &token, &table, &restaurant, 1).is_err());
+    }
+
+    #[test]
+    fn table_token_rejects_a_tampered_table_id() {
+        let (table, restaurant) = ids();
+        let other_table = Uuid::new_v4();
+        let token = encode_table_token(SECRET, &table, &restaurant, 1);
+        let forged = token.replacen(
+            &table.simple().to_string(),
+            &other_table.simple().to_string(),
+            1,
+        );
+        assert_eq!(token_table_id(&forged).unwrap(), other_table);
+        assert!(check_table_token(SECRET, &forged, &other_table, &restaurant, 1).is_err());
+    }
+
+    #[test]
+    fn table_token_is_bound_to_its_restaurant() {
+        let (table, restaurant) = ids();
+        let other_restaurant = Uuid::new_v4();
+        let token = encode_table_token(SECRET, &table, &restaurant, 1);
+        assert!(check_table_token(SECRET, &token, &table, &other_restaurant, 1).is_err());
+    }
+
+    #[test]
+    fn table_token_rejects_a_stale_version() {
+        let (table, restaurant) = ids();
+        let token = encode_table_token(SECRET, &table, &restaurant, 1);
+        assert!(check_table_token(SECRET, &token, &table, &restaurant, 2).is_err());
+        let bumped = token.replacen(".1.", ".2.", 1);
+        assert!(check_table_token(SECRET, &bumped, &table, &restaurant, 2).is_err());
+    }
+
+    #[test]
+    fn table_token_needs_the_secret() {
+        let (table, restaurant) = ids();
+        let token = encode_table_token("another-secret", &table, &restaurant, 1);
+        assert!(check_table_token(SECRET, &token, &table, &restaurant, 1).is_err());
+    }
 }
@@ -1,10 +1,103 @@
-#[derive(GraphQLObject)]
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use uuid::Uuid;
+
+use super::context::Context;
+use super::ingredient::{recipe_for_dish, RecipeLine};
+use super::modifier::ModifierGroup;
+use super::permission::Permission;
+
 pub struct Dish {
     pub id: String,
     pub name: String,
     pub description: String,
     pub price: i32,
     pub restaurant_id: String,
+    pub menu_category_id: Option<String>,
+    pub position: i32,
+    pub available: bool,
+    pub stock: Option<i32>,
+    pub archived_at: Option<NaiveDateTime>,
+}
+
+impl Dish {
+    pub fn from_row(row: &Row) -> Dish {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        let menu_category_id: Option<Uuid> = row.get("menu_category_id");
+        Dish {
+            id: id.hyphenated().to_string(),
+            name: row.get("name"),
+            description: row.get("description"),
+            price: row.get("price"),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            menu_category_id: menu_category_id.map(|id| id.hyphenated().to_string()),
+            position: row.get("position"),
+            available: row.get("available"),
+            stock: row.get("stock"),
+            archived_at: row.get("archived_at"),
+        }
+    }
+}
+
+graphql_object!(Dish: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field name() -> &str {
+    self.name.as_str()
+  }
+  field description() -> &str {
+    self.description.as_str()
+  }
+  field price() -> i32 {
+    self.price
+  }
+  field restaurant_id() -> &str {
+    self.restaurant_id.as_str()
+  }
+  field menu_category_id() -> &Option<String> {
+    &self.menu_category_id
+  }
+  field position() -> i32 {
+    self.position
+  }
+  field available() -> bool {
+    self.available
+  }
+  field stock() -> &Option<i32> {
+    &self.stock
+  }
+  field sold_out() -> bool {
+    !self.available || self.stock == Some(0)
+  }
+  field archived_at() -> &Option<NaiveDateTime> {
+    &self.archived_at
+  }
+  field modifier_groups(&executor) -> FieldResult<Vec<ModifierGroup>> {
+    let conn = executor.context().pool.get()?;
+    let dish_uuid = Uuid::parse_str(&self.id)?;
+    ModifierGroup::for_dish(&*conn, &dish_uuid)
+  }
+  field recipe(&executor) -> FieldResult<Vec<RecipeLine>> {
+    let context = executor.context();
+    context.authorize_restaurant(&self.restaurant_id, Permission::RecordStock)?;
+    let conn = context.pool.get()?;
+    let dish_uuid = Uuid::parse_str(&self.id)?;
+    recipe_for_dish(&*conn, &dish_uuid)
+  }
+});
+
+/// Prices are in minor currency units; a free dish is fine, a negative one isn't.
+pub fn validate_price(price: i32) -> FieldResult<i32> {
+    if price < 0 {
+        return Err(FieldError::new(
+            "Price cannot be negative",
+            graphql_value!({ "external_error": "Price cannot be negative" }),
+        ));
+    }
+    Ok(price)
 }
 
 #[derive(GraphQLInputObject)]
@@ -12,4 +105,12 @@ pub struct NewDish {
     pub name: String,
     pub description: String,
     pub price: i32,
+    pub menu_category_id: Option<String>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct DishUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub price: Option<i32>,
 }
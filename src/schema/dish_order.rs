@@ -1,14 +1,196 @@
-use super::context::Context;
+use super::bill::to_amount;
+use super::context::{Context, Roles};
+use super::customer_order::CustomerOrder;
 use super::dish::Dish;
+use super::ingredient::deplete_for_dish_order;
+use super::modifier::DishOrderModifier;
+use super::order_event::{publish_order_event, OrderEvent, OrderEventKind};
+use super::permission::Permission;
+use chrono::NaiveDateTime;
 use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
 use uuid::Uuid;
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "dish_order_status")]
+pub enum DishOrderStatus {
+    Queued,
+    Preparing,
+    Ready,
+    Served,
+    Voided,
+}
+
+impl DishOrderStatus {
+    /// The kitchen's next step for an item, if it has one.
+    pub fn next(self) -> Option<DishOrderStatus> {
+        match self {
+            DishOrderStatus::Queued => Some(DishOrderStatus::Preparing),
+            DishOrderStatus::Preparing => Some(DishOrderStatus::Ready),
+            DishOrderStatus::Ready => Some(DishOrderStatus::Served),
+            DishOrderStatus::Served | DishOrderStatus::Voided => None,
+        }
+    }
+
+    pub fn is_outstanding(self) -> bool {
+        self.next().is_some()
+    }
+
+    fn timestamp_column(self) -> Option<&'static str> {
+        match self {
+            DishOrderStatus::Queued => None,
+            DishOrderStatus::Preparing => Some("preparing_at"),
+            DishOrderStatus::Ready => Some("ready_at"),
+            DishOrderStatus::Served => Some("served_at"),
+            DishOrderStatus::Voided => Some("voided_at"),
+        }
+    }
+}
+
 pub struct DishOrder {
     pub id: String,
     pub dish_id: String,
     pub customer_order_id: String,
+    /// The diner who ordered the item.
+    pub customer_id: Option<String>,
     pub note: Option<String>,
     pub quantity: i32,
+    pub unit_price: i32,
+    pub status: DishOrderStatus,
+    pub created_at: NaiveDateTime,
+    pub preparing_at: Option<NaiveDateTime>,
+    pub ready_at: Option<NaiveDateTime>,
+    pub served_at: Option<NaiveDateTime>,
+    pub voided_at: Option<NaiveDateTime>,
+}
+
+impl DishOrder {
+    pub fn from_row(row: &Row) -> DishOrder {
+        let id: Uuid = row.get("id");
+        let dish_id: Uuid = row.get("dish_id");
+        let customer_order_id: Uuid = row.get("customer_order_id");
+        let customer_id: Option<Uuid> = row.get("customer_id");
+        DishOrder {
+            id: id.hyphenated().to_string(),
+            dish_id: dish_id.hyphenated().to_string(),
+            customer_order_id: customer_order_id.hyphenated().to_string(),
+            customer_id: customer_id.map(|id| id.hyphenated().to_string()),
+            note: row.get("note"),
+            quantity: row.get("quantity"),
+            unit_price: row.get("unit_price"),
+            status: row.get("status"),
+            created_at: row.get("created_at"),
+            preparing_at: row.get("preparing_at"),
+            ready_at: row.get("ready_at"),
+            served_at: row.get("served_at"),
+            voided_at: row.get("voided_at"),
+        }
+    }
+}
+
+fn kitchen_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+/// Moves an item of the partner's restaurant to `next` and stamps the time it happened.
+/// `None` advances the item one step through the kitchen.
+pub fn update_dish_order_status(
+    context: &Context,
+    id: &str,
+    next: Option<DishOrderStatus>,
+) -> FieldResult<DishOrder> {
+    context.authorize(Roles::Partner)?;
+    let restaurant_uuid = Uuid::parse_str(&context.get_partner_restaurant_id()?)?;
+    let dish_order_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let rows = trans.query(
+        "
+        SELECT dish_order.status
+        FROM dish_order
+        JOIN customer_order ON customer_order.id = dish_order.customer_order_id
+        WHERE dish_order.id = $1 AND customer_order.restaurant_id = $2
+        FOR UPDATE OF dish_order
+    ",
+        &[&dish_order_uuid, &restaurant_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(kitchen_error("Dish order does not exist"));
+    }
+    let status: DishOrderStatus = rows.get(0).get("status");
+    let next = match next {
+        Some(DishOrderStatus::Voided) if status.is_outstanding() => DishOrderStatus::Voided,
+        Some(next) if status.next() == Some(next) => next,
+        None if status.is_outstanding() => status.next().unwrap(),
+        _ => return Err(kitchen_error("Dish order cannot move to that status")),
+    };
+    context.authorize_permission(match next {
+        DishOrderStatus::Voided => Permission::VoidItems,
+        DishOrderStatus::Served => Permission::ServeItems,
+        _ => Permission::PrepareItems,
+    })?;
+    if next == DishOrderStatus::Voided {
+        // voiding changes the bill, which must not move under a payer's feet;
+        // a failed payment took nothing, so it doesn't count
+        let rows = trans.query(
+            "
+            SELECT 1
+            FROM dish_order
+            WHERE id = $1 AND (
+                EXISTS (
+                    SELECT 1
+                    FROM payment
+                    WHERE payment.customer_order_id = dish_order.customer_order_id
+                        AND payment.status IN ('Succeeded', 'Pending')
+                )
+                OR EXISTS (SELECT 1 FROM bill_check WHERE bill_check.customer_order_id = dish_order.customer_order_id)
+            )
+        ",
+            &[&dish_order_uuid],
+        )?;
+        if !rows.is_empty() {
+            return Err(kitchen_error(
+                "Order is already being paid, so its items can no longer be voided",
+            ));
+        }
+        // the item was never made, so it goes back into stock; untracked stock stays NULL
+        trans.execute(
+            "
+            UPDATE dish
+            SET stock = dish.stock + dish_order.quantity
+            FROM dish_order
+            WHERE dish_order.id = $1 AND dish.id = dish_order.dish_id
+        ",
+            &[&dish_order_uuid],
+        )?;
+    }
+    let timestamp_column = next.timestamp_column().unwrap();
+    let rows = trans.query(
+        &format!(
+            "
+            UPDATE dish_order
+            SET status = $2, {} = now()
+            WHERE id = $1
+            RETURNING *
+        ",
+            timestamp_column
+        ),
+        &[&dish_order_uuid, &next],
+    )?;
+    if next == DishOrderStatus::Served {
+        deplete_for_dish_order(&trans, &dish_order_uuid)?;
+    }
+    trans.commit()?;
+    let dish_order = DishOrder::from_row(&rows.get(0));
+    publish_order_event(
+        context,
+        &OrderEvent::for_dish_order(
+            OrderEventKind::DishOrderStatusChanged,
+            &restaurant_uuid,
+            &dish_order,
+        ),
+    );
+    Ok(dish_order)
 }
 
 graphql_object!(DishOrder: Context | &self | {
@@ -21,12 +203,67 @@ graphql_object!(DishOrder: Context | &self | {
   field customer_order_id() -> &str {
     self.customer_order_id.as_str()
   }
+  field customer_id() -> &Option<String> {
+    &self.customer_id
+  }
   field note() -> &Option<String> {
     &self.note
   }
   field quantity() -> i32 {
     self.quantity
   }
+  field unit_price() -> i32 {
+    self.unit_price
+  }
+  field modifiers(&executor) -> FieldResult<Vec<DishOrderModifier>> {
+    let conn = executor.context().pool.get()?;
+    let dish_order_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM dish_order_modifier
+      WHERE dish_order_id = $1
+      ORDER BY position
+    ", &[&dish_order_uuid])?;
+    let mut modifiers = vec!();
+    for row in &rows {
+      modifiers.push(DishOrderModifier::from_row(&row));
+    }
+    Ok(modifiers)
+  }
+  field line_total() -> FieldResult<i32> {
+    to_amount(i64::from(self.unit_price) * i64::from(self.quantity))
+  }
+  field status() -> &DishOrderStatus {
+    &self.status
+  }
+  field created_at() -> &NaiveDateTime {
+    &self.created_at
+  }
+  field preparing_at() -> &Option<NaiveDateTime> {
+    &self.preparing_at
+  }
+  field ready_at() -> &Option<NaiveDateTime> {
+    &self.ready_at
+  }
+  field served_at() -> &Option<NaiveDateTime> {
+    &self.served_at
+  }
+  field voided_at() -> &Option<NaiveDateTime> {
+    &self.voided_at
+  }
+  field customer_order(&executor) -> FieldResult<CustomerOrder> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.customer_order_id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM customer_order
+      WHERE id = $1
+    ", &[&customer_order_uuid])?;
+    if rows.is_empty() {
+      return Err(FieldError::new("Order does not exist", graphql_value!({ "internal_error": "Order does not exist" })));
+    }
+    Ok(CustomerOrder::from_row(&rows.get(0)))
+  }
   field dish(&executor) -> FieldResult<Dish> {
     let conn = executor.context().pool.get()?;
     let dish_uuid = Uuid::parse_str(&self.dish_id)?;
@@ -38,15 +275,7 @@ graphql_object!(DishOrder: Context | &self | {
     if rows.is_empty() {
       return Err(FieldError::new("Dish does not exist", graphql_value!({ "internal_error": "Dish does not exist" })));
     }
-    let row = rows.get(0);
-    let restaurant_id: Uuid = row.get("restaurant_id");
-    Ok(Dish {
-      id: dish_uuid.hyphenated().to_string(),
-      restaurant_id: restaurant_id.hyphenated().to_string(),
-      name: row.get("name"),
-      description: row.get("description"),
-      price: row.get("price"),
-    })
+    Ok(Dish::from_row(&rows.get(0)))
   }
 });
 
@@ -56,4 +285,5 @@ pub struct NewDishOrder {
     pub customer_order_id: String,
     pub note: Option<String>,
     pub quantity: i32,
+    pub modifier_option_ids: Option<Vec<String>>,
 }
@@ -0,0 +1,203 @@
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use uuid::Uuid;
+
+use super::context::Context;
+use super::dining_table::DiningTable;
+use super::permission::Permission;
+
+/// A named part of the dining room, such as the terrace, that the partner
+/// floor map shows as one tab.
+pub struct FloorArea {
+    pub id: String,
+    pub restaurant_id: String,
+    pub name: String,
+    pub sort_order: i32,
+    pub created_at: NaiveDateTime,
+}
+
+impl FloorArea {
+    pub fn from_row(row: &Row) -> FloorArea {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        FloorArea {
+            id: id.hyphenated().to_string(),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            name: row.get("name"),
+            sort_order: row.get("sort_order"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewFloorArea {
+    pub name: String,
+    pub sort_order: Option<i32>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct FloorAreaUpdate {
+    pub name: Option<String>,
+    pub sort_order: Option<i32>,
+}
+
+fn not_found() -> FieldError {
+    FieldError::new(
+        "Floor area not found",
+        graphql_value!({ "external_error": "Floor area not found" }),
+    )
+}
+
+/// Parses a floor area id given for a table, which must belong to the same
+/// restaurant.
+pub fn restaurant_floor_area(
+    conn: &dyn GenericConnection,
+    floor_area_id: &Option<String>,
+    restaurant_id: &Uuid,
+) -> FieldResult<Option<Uuid>> {
+    let floor_area_uuid = match floor_area_id {
+        Some(floor_area_id) => Uuid::parse_str(floor_area_id)?,
+        None => return Ok(None),
+    };
+    let rows = conn.query(
+        "
+        SELECT 1
+        FROM floor_area
+        WHERE id = $1 AND restaurant_id = $2
+    ",
+        &[&floor_area_uuid, restaurant_id],
+    )?;
+    if rows.is_empty() {
+        return Err(not_found());
+    }
+    Ok(Some(floor_area_uuid))
+}
+
+pub fn floor_areas(context: &Context, restaurant_id: &str) -> FieldResult<Vec<FloorArea>> {
+    let restaurant_uuid = Uuid::parse_str(restaurant_id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT *
+        FROM floor_area
+        WHERE restaurant_id = $1
+        ORDER BY sort_order, name
+    ",
+        &[&restaurant_uuid],
+    )?;
+    let mut floor_areas = vec![];
+    for row in &rows {
+        floor_areas.push(FloorArea::from_row(&row));
+    }
+    Ok(floor_areas)
+}
+
+pub fn create_floor_area(context: &Context, input: NewFloorArea) -> FieldResult<FloorArea> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageRestaurant)?)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        INSERT INTO floor_area (
+            id,
+            restaurant_id,
+            name,
+            sort_order
+        ) VALUES ($1, $2, $3, $4)
+        RETURNING *
+    ",
+        &[
+            &Uuid::new_v4(),
+            &restaurant_uuid,
+            &input.name,
+            &input.sort_order.unwrap_or(0),
+        ],
+    )?;
+    Ok(FloorArea::from_row(&rows.get(0)))
+}
+
+pub fn update_floor_area(
+    context: &Context,
+    id: &str,
+    input: FloorAreaUpdate,
+) -> FieldResult<FloorArea> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageRestaurant)?)?;
+    let floor_area_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        UPDATE floor_area
+        SET name = COALESCE($3, name),
+            sort_order = COALESCE($4, sort_order)
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[
+            &floor_area_uuid,
+            &restaurant_uuid,
+            &input.name,
+            &input.sort_order,
+        ],
+    )?;
+    if rows.is_empty() {
+        return Err(not_found());
+    }
+    Ok(FloorArea::from_row(&rows.get(0)))
+}
+
+/// Removes an area; its tables stay and are left without one.
+pub fn delete_floor_area(context: &Context, id: &str) -> FieldResult<FloorArea> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageRestaurant)?)?;
+    let floor_area_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        DELETE FROM floor_area
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[&floor_area_uuid, &restaurant_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(not_found());
+    }
+    Ok(FloorArea::from_row(&rows.get(0)))
+}
+
+graphql_object!(FloorArea: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field restaurant_id() -> &str {
+    self.restaurant_id.as_str()
+  }
+  field name() -> &str {
+    self.name.as_str()
+  }
+  field sort_order() -> i32 {
+    self.sort_order
+  }
+  field created_at() -> &NaiveDateTime {
+    &self.created_at
+  }
+  field dining_tables(&executor) -> FieldResult<Vec<DiningTable>> {
+    let conn = executor.context().pool.get()?;
+    let floor_area_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM dining_table
+      WHERE floor_area_id = $1 AND archived_at IS NULL
+      ORDER BY name
+    ", &[&floor_area_uuid])?;
+    let mut dining_tables = vec!();
+    for row in &rows {
+      dining_tables.push(DiningTable::from_row(&row));
+    }
+    Ok(dining_tables)
+  }
+});
@@ -0,0 +1,623 @@
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use uuid::Uuid;
+
+use super::bill::to_amount;
+use super::context::Context;
+use super::permission::Permission;
+
+/// `average_cost` is kept in ten-thousandths of a minor currency unit, so a
+/// unit cost can be finer than the currency allows.
+const COST_SCALE: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "ingredient_movement_kind")]
+pub enum IngredientMovementKind {
+    Depletion,
+    StockTake,
+    Wastage,
+    Receipt,
+}
+
+pub struct Ingredient {
+    pub id: String,
+    pub restaurant_id: String,
+    pub name: String,
+    pub unit: String,
+    pub on_hand: i32,
+    pub reorder_point: i32,
+    /// Per unit, scaled by `COST_SCALE`.
+    pub average_cost: i64,
+}
+
+impl Ingredient {
+    pub fn from_row(row: &Row) -> Ingredient {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        Ingredient {
+            id: id.hyphenated().to_string(),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            name: row.get("name"),
+            unit: row.get("unit"),
+            on_hand: row.get("on_hand"),
+            reorder_point: row.get("reorder_point"),
+            average_cost: row.get("average_cost"),
+        }
+    }
+
+    /// Locks an ingredient of the given restaurant.
+    pub fn find_for_update(
+        conn: &dyn GenericConnection,
+        id: &Uuid,
+        restaurant_id: &Uuid,
+    ) -> FieldResult<Ingredient> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM ingredient
+            WHERE id = $1 AND restaurant_id = $2
+            FOR UPDATE
+        ",
+            &[id, restaurant_id],
+        )?;
+        if rows.is_empty() {
+            return Err(inventory_error("Ingredient does not exist"));
+        }
+        Ok(Ingredient::from_row(&rows.get(0)))
+    }
+
+    pub fn needs_reorder(&self) -> bool {
+        self.on_hand <= self.reorder_point
+    }
+}
+
+#[derive(GraphQLObject)]
+pub struct IngredientMovement {
+    pub id: String,
+    pub ingredient_id: String,
+    pub kind: IngredientMovementKind,
+    pub quantity_change: i32,
+    pub on_hand_after: i32,
+    pub dish_order_id: Option<String>,
+    pub purchase_order_line_id: Option<String>,
+    pub note: Option<String>,
+    pub recorded_by: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl IngredientMovement {
+    pub fn from_row(row: &Row) -> IngredientMovement {
+        let id: Uuid = row.get("id");
+        let ingredient_id: Uuid = row.get("ingredient_id");
+        let dish_order_id: Option<Uuid> = row.get("dish_order_id");
+        let purchase_order_line_id: Option<Uuid> = row.get("purchase_order_line_id");
+        let recorded_by: Option<Uuid> = row.get("recorded_by");
+        IngredientMovement {
+            id: id.hyphenated().to_string(),
+            ingredient_id: ingredient_id.hyphenated().to_string(),
+            kind: row.get("kind"),
+            quantity_change: row.get("quantity_change"),
+            on_hand_after: row.get("on_hand_after"),
+            dish_order_id: dish_order_id.map(|id| id.hyphenated().to_string()),
+            purchase_order_line_id: purchase_order_line_id.map(|id| id.hyphenated().to_string()),
+            note: row.get("note"),
+            recorded_by: recorded_by.map(|id| id.hyphenated().to_string()),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+graphql_object!(Ingredient: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field restaurant_id() -> &str {
+    self.restaurant_id.as_str()
+  }
+  field name() -> &str {
+    self.name.as_str()
+  }
+  field unit() -> &str {
+    self.unit.as_str()
+  }
+  field on_hand() -> i32 {
+    self.on_hand
+  }
+  field reorder_point() -> i32 {
+    self.reorder_point
+  }
+  // per unit, in minor currency units rounded half up
+  field average_cost() -> FieldResult<i32> {
+    to_amount(round_scaled(i128::from(self.average_cost), i128::from(COST_SCALE)))
+  }
+  field needs_reorder() -> bool {
+    self.needs_reorder()
+  }
+  field movements(&executor, limit: Option<i32>) -> FieldResult<Vec<IngredientMovement>> {
+    let conn = executor.context().pool.get()?;
+    let ingredient_uuid = Uuid::parse_str(&self.id)?;
+    let limit = i64::from(limit.unwrap_or(50));
+    let rows = conn.query("
+      SELECT *
+      FROM ingredient_movement
+      WHERE ingredient_id = $1
+      ORDER BY created_at DESC
+      LIMIT $2
+    ", &[&ingredient_uuid, &limit])?;
+    let mut movements = vec!();
+    for row in &rows {
+      movements.push(IngredientMovement::from_row(&row));
+    }
+    Ok(movements)
+  }
+});
+
+pub struct RecipeLine {
+    pub dish_id: String,
+    pub ingredient_id: String,
+    pub quantity: i32,
+}
+
+impl RecipeLine {
+    pub fn from_row(row: &Row) -> RecipeLine {
+        let dish_id: Uuid = row.get("dish_id");
+        let ingredient_id: Uuid = row.get("ingredient_id");
+        RecipeLine {
+            dish_id: dish_id.hyphenated().to_string(),
+            ingredient_id: ingredient_id.hyphenated().to_string(),
+            quantity: row.get("quantity"),
+        }
+    }
+}
+
+graphql_object!(RecipeLine: Context | &self | {
+  field dish_id() -> &str {
+    self.dish_id.as_str()
+  }
+  field ingredient_id() -> &str {
+    self.ingredient_id.as_str()
+  }
+  field quantity() -> i32 {
+    self.quantity
+  }
+  field ingredient(&executor) -> FieldResult<Ingredient> {
+    let conn = executor.context().pool.get()?;
+    let ingredient_uuid = Uuid::parse_str(&self.ingredient_id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM ingredient
+      WHERE id = $1
+    ", &[&ingredient_uuid])?;
+    if rows.is_empty() {
+      return Err(FieldError::new("Ingredient does not exist", graphql_value!({ "internal_error": "Ingredient does not exist" })));
+    }
+    Ok(Ingredient::from_row(&rows.get(0)))
+  }
+});
+
+#[derive(GraphQLInputObject)]
+pub struct NewIngredient {
+    pub name: String,
+    pub unit: String,
+    pub on_hand: Option<i32>,
+    pub reorder_point: Option<i32>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct IngredientUpdate {
+    pub name: Option<String>,
+    pub unit: Option<String>,
+    pub reorder_point: Option<i32>,
+}
+
+pub fn inventory_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+/// A change to an ingredient's stock and what caused it.
+pub struct Movement<'a> {
+    pub kind: IngredientMovementKind,
+    pub quantity_change: i32,
+    pub dish_order_id: Option<&'a Uuid>,
+    pub purchase_order_line_id: Option<&'a Uuid>,
+    pub note: Option<&'a str>,
+    pub recorded_by: Option<&'a Uuid>,
+}
+
+impl<'a> Movement<'a> {
+    pub fn new(kind: IngredientMovementKind, quantity_change: i32) -> Movement<'a> {
+        Movement {
+            kind,
+            quantity_change,
+            dish_order_id: None,
+            purchase_order_line_id: None,
+            note: None,
+            recorded_by: None,
+        }
+    }
+}
+
+/// Applies the movement to the ingredient's on-hand count and records it.
+pub fn record_movement(
+    conn: &dyn GenericConnection,
+    ingredient_id: &Uuid,
+    movement: Movement,
+) -> FieldResult<Ingredient> {
+    let rows = conn.query(
+        "
+        UPDATE ingredient
+        SET on_hand = on_hand + $2
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[ingredient_id, &movement.quantity_change],
+    )?;
+    let ingredient = Ingredient::from_row(&rows.get(0));
+    conn.execute(
+        "
+        INSERT INTO ingredient_movement (
+            id,
+            ingredient_id,
+            kind,
+            quantity_change,
+            on_hand_after,
+            dish_order_id,
+            purchase_order_line_id,
+            note,
+            recorded_by
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+    ",
+        &[
+            &Uuid::new_v4(),
+            ingredient_id,
+            &movement.kind,
+            &movement.quantity_change,
+            &ingredient.on_hand,
+            &movement.dish_order_id,
+            &movement.purchase_order_line_id,
+            &movement.note,
+            &movement.recorded_by,
+        ],
+    )?;
+    Ok(ingredient)
+}
+
+/// Takes the recipe of a served item out of stock. Stock may go negative, since
+/// the kitchen has already used whatever it had.
+pub fn deplete_for_dish_order(
+    conn: &dyn GenericConnection,
+    dish_order_id: &Uuid,
+) -> FieldResult<()> {
+    let rows = conn.query(
+        "
+        SELECT recipe_line.ingredient_id, recipe_line.quantity * dish_order.quantity AS used
+        FROM dish_order
+        JOIN recipe_line ON recipe_line.dish_id = dish_order.dish_id
+        WHERE dish_order.id = $1
+        ORDER BY recipe_line.ingredient_id
+    ",
+        &[dish_order_id],
+    )?;
+    for row in &rows {
+        let ingredient_uuid: Uuid = row.get("ingredient_id");
+        let used: i32 = row.get("used");
+        record_movement(
+            conn,
+            &ingredient_uuid,
+            Movement {
+                dish_order_id: Some(dish_order_id),
+                ..Movement::new(IngredientMovementKind::Depletion, -used)
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// The caller's restaurant and partner id, once their role grants `permission`.
+pub fn partner_scope(context: &Context, permission: Permission) -> FieldResult<(Uuid, Uuid)> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(permission)?)?;
+    let partner_uuid = Uuid::parse_str(context.get_client_id()?)?;
+    Ok((restaurant_uuid, partner_uuid))
+}
+
+pub fn create_ingredient(context: &Context, input: NewIngredient) -> FieldResult<Ingredient> {
+    let (restaurant_uuid, partner_uuid) = partner_scope(context, Permission::ManageInventory)?;
+    let reorder_point = input.reorder_point.unwrap_or(0);
+    if reorder_point < 0 {
+        return Err(inventory_error("Reorder point cannot be negative"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let ingredient_uuid = Uuid::new_v4();
+    let rows = trans.query(
+        "
+        INSERT INTO ingredient (
+            id,
+            restaurant_id,
+            name,
+            unit,
+            reorder_point
+        ) VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+    ",
+        &[
+            &ingredient_uuid,
+            &restaurant_uuid,
+            &input.name,
+            &input.unit,
+            &reorder_point,
+        ],
+    )?;
+    let mut ingredient = Ingredient::from_row(&rows.get(0));
+    if let Some(on_hand) = input.on_hand {
+        // the opening count is a stock-take, so it shows up in the history
+        ingredient = record_movement(
+            &trans,
+            &ingredient_uuid,
+            Movement {
+                note: Some("Opening stock"),
+                recorded_by: Some(&partner_uuid),
+                ..Movement::new(IngredientMovementKind::StockTake, on_hand)
+            },
+        )?;
+    }
+    trans.commit()?;
+    Ok(ingredient)
+}
+
+pub fn update_ingredient(
+    context: &Context,
+    id: &str,
+    input: IngredientUpdate,
+) -> FieldResult<Ingredient> {
+    let (restaurant_uuid, _) = partner_scope(context, Permission::ManageInventory)?;
+    let ingredient_uuid = Uuid::parse_str(id)?;
+    if input.reorder_point.is_some_and(|point| point < 0) {
+        return Err(inventory_error("Reorder point cannot be negative"));
+    }
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        UPDATE ingredient
+        SET name = COALESCE($3, name),
+            unit = COALESCE($4, unit),
+            reorder_point = COALESCE($5, reorder_point)
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[
+            &ingredient_uuid,
+            &restaurant_uuid,
+            &input.name,
+            &input.unit,
+            &input.reorder_point,
+        ],
+    )?;
+    if rows.is_empty() {
+        return Err(inventory_error("Ingredient does not exist"));
+    }
+    Ok(Ingredient::from_row(&rows.get(0)))
+}
+
+/// Sets how much of an ingredient one portion of a dish uses. A quantity of 0
+/// takes the ingredient out of the recipe.
+pub fn set_recipe_line(
+    context: &Context,
+    dish_id: &str,
+    ingredient_id: &str,
+    quantity: i32,
+) -> FieldResult<Vec<RecipeLine>> {
+    let (restaurant_uuid, _) = partner_scope(context, Permission::ManageInventory)?;
+    let dish_uuid = Uuid::parse_str(dish_id)?;
+    let ingredient_uuid = Uuid::parse_str(ingredient_id)?;
+    if quantity < 0 {
+        return Err(inventory_error("Recipe quantity cannot be negative"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let dish_rows = trans.query(
+        "
+        SELECT 1
+        FROM dish
+        WHERE id = $1 AND restaurant_id = $2
+    ",
+        &[&dish_uuid, &restaurant_uuid],
+    )?;
+    if dish_rows.is_empty() {
+        return Err(inventory_error("Dish does not exist"));
+    }
+    Ingredient::find_for_update(&trans, &ingredient_uuid, &restaurant_uuid)?;
+    if quantity == 0 {
+        trans.execute(
+            "
+            DELETE FROM recipe_line
+            WHERE dish_id = $1 AND ingredient_id = $2
+        ",
+            &[&dish_uuid, &ingredient_uuid],
+        )?;
+    } else {
+        trans.execute(
+            "
+            INSERT INTO recipe_line (
+                dish_id,
+                ingredient_id,
+                quantity
+            ) VALUES ($1, $2, $3)
+            ON CONFLICT (dish_id, ingredient_id) DO UPDATE SET quantity = EXCLUDED.quantity
+        ",
+            &[&dish_uuid, &ingredient_uuid, &quantity],
+        )?;
+    }
+    let recipe = recipe_for_dish(&trans, &dish_uuid)?;
+    trans.commit()?;
+    Ok(recipe)
+}
+
+pub fn recipe_for_dish(
+    conn: &dyn GenericConnection,
+    dish_id: &Uuid,
+) -> FieldResult<Vec<RecipeLine>> {
+    let rows = conn.query(
+        "
+        SELECT recipe_line.*
+        FROM recipe_line
+        JOIN ingredient ON ingredient.id = recipe_line.ingredient_id
+        WHERE recipe_line.dish_id = $1
+        ORDER BY ingredient.name
+    ",
+        &[dish_id],
+    )?;
+    Ok(rows.iter().map(|row| RecipeLine::from_row(&row)).collect())
+}
+
+/// Records a physical count, moving stock by the difference from the books.
+pub fn record_stock_take(
+    context: &Context,
+    ingredient_id: &str,
+    counted: i32,
+    note: Option<String>,
+) -> FieldResult<Ingredient> {
+    let (restaurant_uuid, partner_uuid) = partner_scope(context, Permission::RecordStock)?;
+    let ingredient_uuid = Uuid::parse_str(ingredient_id)?;
+    if counted < 0 {
+        return Err(inventory_error("Counted stock cannot be negative"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let ingredient = Ingredient::find_for_update(&trans, &ingredient_uuid, &restaurant_uuid)?;
+    let ingredient = record_movement(
+        &trans,
+        &ingredient_uuid,
+        Movement {
+            note: note.as_deref(),
+            recorded_by: Some(&partner_uuid),
+            ..Movement::new(
+                IngredientMovementKind::StockTake,
+                counted - ingredient.on_hand,
+            )
+        },
+    )?;
+    trans.commit()?;
+    Ok(ingredient)
+}
+
+pub fn record_wastage(
+    context: &Context,
+    ingredient_id: &str,
+    quantity: i32,
+    note: Option<String>,
+) -> FieldResult<Ingredient> {
+    let (restaurant_uuid, partner_uuid) = partner_scope(context, Permission::RecordStock)?;
+    let ingredient_uuid = Uuid::parse_str(ingredient_id)?;
+    if quantity <= 0 {
+        return Err(inventory_error("Wasted quantity must be positive"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    Ingredient::find_for_update(&trans, &ingredient_uuid, &restaurant_uuid)?;
+    let ingredient = record_movement(
+        &trans,
+        &ingredient_uuid,
+        Movement {
+            note: note.as_deref(),
+            recorded_by: Some(&partner_uuid),
+            ..Movement::new(IngredientMovementKind::Wastage, -quantity)
+        },
+    )?;
+    trans.commit()?;
+    Ok(ingredient)
+}
+
+/// Divides and rounds half up, for a non-negative `numerator`.
+fn round_scaled(numerator: i128, denominator: i128) -> i64 {
+    ((numerator + denominator / 2) / denominator) as i64
+}
+
+/// The scaled unit cost after `quantity` units costing `cost` in total join
+/// the stock on hand.
+fn moving_average_cost(on_hand: i32, average_cost: i64, quantity: i32, cost: i32) -> i64 {
+    let held = i128::from(on_hand.max(0));
+    let units = held + i128::from(quantity);
+    if units <= 0 {
+        return average_cost;
+    }
+    let value = held * i128::from(average_cost) + i128::from(cost) * i128::from(COST_SCALE);
+    round_scaled(value, units)
+}
+
+/// Books received goods into stock and folds their cost into the moving average.
+/// Stock below zero counts as none, so a shortfall doesn't skew the average.
+pub fn receive_ingredient(
+    conn: &dyn GenericConnection,
+    ingredient_id: &Uuid,
+    quantity: i32,
+    cost: i32,
+    purchase_order_line_id: &Uuid,
+    recorded_by: &Uuid,
+) -> FieldResult<Ingredient> {
+    let rows = conn.query(
+        "
+        SELECT on_hand, average_cost
+        FROM ingredient
+        WHERE id = $1
+        FOR UPDATE
+    ",
+        &[ingredient_id],
+    )?;
+    let row = rows.get(0);
+    let average_cost =
+        moving_average_cost(row.get("on_hand"), row.get("average_cost"), quantity, cost);
+    conn.execute(
+        "
+        UPDATE ingredient
+        SET average_cost = $2
+        WHERE id = $1
+    ",
+        &[ingredient_id, &average_cost],
+    )?;
+    record_movement(
+        conn,
+        ingredient_id,
+        Movement {
+            purchase_order_line_id: Some(purchase_order_line_id),
+            recorded_by: Some(recorded_by),
+            ..Movement::new(IngredientMovementKind::Receipt, quantity)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_average_starts_from_the_first_receipt() {
+        assert_eq!(moving_average_cost(0, 0, 4, 1000), 250 * COST_SCALE);
+    }
+
+    #[test]
+    fn moving_average_weighs_stock_on_hand() {
+        // 10 units at 100 plus 10 units for 3000 is 20 units at 200
+        assert_eq!(
+            moving_average_cost(10, 100 * COST_SCALE, 10, 3000),
+            200 * COST_SCALE
+        );
+    }
+
+    #[test]
+    fn moving_average_keeps_sub_unit_precision() {
+        // 1000 for 3 units is 333.3333...
+        assert_eq!(moving_average_cost(0, 0, 3, 1000), 3_333_333);
+        assert_eq!(round_scaled(3_333_333, i128::from(COST_SCALE)), 333);
+    }
+
+    #[test]
+    fn moving_average_ignores_a_shortfall() {
+        assert_eq!(
+            moving_average_cost(-5, 100 * COST_SCALE, 2, 500),
+            250 * COST_SCALE
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::context::Context;
+use super::dish::Dish;
+use super::permission::Permission;
+
+pub struct MenuCategory {
+    pub id: String,
+    pub restaurant_id: String,
+    pub name: String,
+    pub position: i32,
+}
+
+impl MenuCategory {
+    pub fn from_row(row: &Row) -> MenuCategory {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        MenuCategory {
+            id: id.hyphenated().to_string(),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            name: row.get("name"),
+            position: row.get("position"),
+        }
+    }
+
+    /// Finds a category of the given restaurant, so partners can't touch other menus.
+    pub fn find_for_restaurant(
+        conn: &dyn GenericConnection,
+        id: &Uuid,
+        restaurant_id: &Uuid,
+    ) -> FieldResult<MenuCategory> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM menu_category
+            WHERE id = $1 AND restaurant_id = $2
+        ",
+            &[id, restaurant_id],
+        )?;
+        if rows.is_empty() {
+            return Err(menu_error("Menu category does not exist"));
+        }
+        Ok(MenuCategory::from_row(&rows.get(0)))
+    }
+
+    pub fn for_restaurant(
+        conn: &dyn GenericConnection,
+        restaurant_id: &Uuid,
+    ) -> FieldResult<Vec<MenuCategory>> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM menu_category
+            WHERE restaurant_id = $1
+            ORDER BY position, name
+        ",
+            &[restaurant_id],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| MenuCategory::from_row(&row))
+            .collect())
+    }
+}
+
+graphql_object!(MenuCategory: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field restaurant_id() -> &str {
+    self.restaurant_id.as_str()
+  }
+  field name() -> &str {
+    self.name.as_str()
+  }
+  field position() -> i32 {
+    self.position
+  }
+  field dishes(&executor) -> FieldResult<Vec<Dish>> {
+    let conn = executor.context().pool.get()?;
+    let menu_category_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM dish
+      WHERE menu_category_id = $1 AND archived_at IS NULL
+      ORDER BY position, name
+    ", &[&menu_category_uuid])?;
+    let mut dishes = vec!();
+    for row in &rows {
+      dishes.push(Dish::from_row(&row));
+    }
+    Ok(dishes)
+  }
+});
+
+#[derive(GraphQLInputObject)]
+pub struct NewMenuCategory {
+    pub name: String,
+}
+
+fn menu_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+/// Position after the last category of the restaurant.
+fn next_category_position(conn: &dyn GenericConnection, restaurant_id: &Uuid) -> FieldResult<i32> {
+    let rows = conn.query(
+        "
+        SELECT COALESCE(MAX(position) + 1, 0) AS position
+        FROM menu_category
+        WHERE restaurant_id = $1
+    ",
+        &[restaurant_id],
+    )?;
+    Ok(rows.get(0).get("position"))
+}
+
+/// Position after the last dish of the category, or of the uncategorized dishes.
+pub fn next_dish_position(
+    conn: &dyn GenericConnection,
+    restaurant_id: &Uuid,
+    menu_category_id: Option<&Uuid>,
+) -> FieldResult<i32> {
+    let rows = conn.query(
+        "
+        SELECT COALESCE(MAX(position) + 1, 0) AS position
+        FROM dish
+        WHERE restaurant_id = $1 AND menu_category_id IS NOT DISTINCT FROM $2
+    ",
+        &[restaurant_id, &menu_category_id],
+    )?;
+    Ok(rows.get(0).get("position"))
+}
+
+/// Checks that `ids` names every one of `expected` exactly once and returns them in order.
+fn parse_ordering(ids: &[String], expected: &[Uuid]) -> FieldResult<Vec<Uuid>> {
+    let mut remaining: HashSet<&Uuid> = expected.iter().collect();
+    let mut ordering = vec![];
+    for id in ids {
+        let uuid = Uuid::parse_str(id)?;
+        if !remaining.remove(&uuid) {
+            return Err(menu_error("Each item must be listed exactly once"));
+        }
+        ordering.push(uuid);
+    }
+    if !remaining.is_empty() {
+        return Err(menu_error("Every item must be listed"));
+    }
+    Ok(ordering)
+}
+
+pub fn create_menu_category(
+    context: &Context,
+    input: NewMenuCategory,
+) -> FieldResult<MenuCategory> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let position = next_category_position(&trans, &restaurant_uuid)?;
+    let rows = trans.query(
+        "
+        INSERT INTO menu_category (
+            id,
+            restaurant_id,
+            name,
+            position
+        ) VALUES ($1, $2, $3, $4)
+        RETURNING *
+    ",
+        &[&Uuid::new_v4(), &restaurant_uuid, &input.name, &position],
+    )?;
+    trans.commit()?;
+    Ok(MenuCategory::from_row(&rows.get(0)))
+}
+
+pub fn rename_menu_category(context: &Context, id: &str, name: &str) -> FieldResult<MenuCategory> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let menu_category_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        UPDATE menu_category
+        SET name = $3
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[&menu_category_uuid, &restaurant_uuid, &name],
+    )?;
+    if rows.is_empty() {
+        return Err(menu_error("Menu category does not exist"));
+    }
+    Ok(MenuCategory::from_row(&rows.get(0)))
+}
+
+/// Deleting a category leaves its dishes on the menu as uncategorized.
+pub fn delete_menu_category(context: &Context, id: &str) -> FieldResult<MenuCategory> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let menu_category_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        DELETE FROM menu_category
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[&menu_category_uuid, &restaurant_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(menu_error("Menu category does not exist"));
+    }
+    Ok(MenuCategory::from_row(&rows.get(0)))
+}
+
+pub fn reorder_menu_categories(
+    context: &Context,
+    ids: Vec<String>,
+) -> FieldResult<Vec<MenuCategory>> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let rows = trans.query(
+        "
+        SELECT id
+        FROM menu_category
+        WHERE restaurant_id = $1
+        FOR UPDATE
+    ",
+        &[&restaurant_uuid],
+    )?;
+    let existing: Vec<Uuid> = rows.iter().map(|row| row.get("id")).collect();
+    for (position, menu_category_uuid) in parse_ordering(&ids, &existing)?.iter().enumerate() {
+        trans.execute(
+            "
+            UPDATE menu_category
+            SET position = $2
+            WHERE id = $1
+        ",
+            &[menu_category_uuid, &(position as i32)],
+        )?;
+    }
+    let categories = MenuCategory::for_restaurant(&trans, &restaurant_uuid)?;
+    trans.commit()?;
+    Ok(categories)
+}
+
+/// Reorders the dishes of a category. Archived dishes keep their position and
+/// are not listed.
+pub fn reorder_dishes(
+    context: &Context,
+    menu_category_id: &str,
+    dish_ids: Vec<String>,
+) -> FieldResult<MenuCategory> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let menu_category_uuid = Uuid::parse_str(menu_category_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let menu_category =
+        MenuCategory::find_for_restaurant(&trans, &menu_category_uuid, &restaurant_uuid)?;
+    let rows = trans.query(
+        "
+        SELECT id
+        FROM dish
+        WHERE menu_category_id = $1 AND archived_at IS NULL
+        FOR UPDATE
+    ",
+        &[&menu_category_uuid],
+    )?;
+    let existing: Vec<Uuid> = rows.iter().map(|row| row.get("id")).collect();
+    for (position, dish_uuid) in parse_ordering(&dish_ids, &existing)?.iter().enumerate() {
+        trans.execute(
+            "
+            UPDATE dish
+            SET position = $2
+            WHERE id = $1
+        ",
+            &[dish_uuid, &(position as i32)],
+        )?;
+    }
+    trans.commit()?;
+    Ok(menu_category)
+}
+
+/// Moves a dish into a category, or out of every category when none is given,
+/// placing it last.
+pub fn set_dish_menu_category(
+    context: &Context,
+    dish_id: &str,
+    menu_category_id: Option<String>,
+) -> FieldResult<Dish> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let dish_uuid = Uuid::parse_str(dish_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let menu_category_uuid = match menu_category_id {
+        Some(ref id) => {
+            let uuid = Uuid::parse_str(id)?;
+            MenuCategory::find_for_restaurant(&trans, &uuid, &restaurant_uuid)?;
+            Some(uuid)
+        }
+        None => None,
+    };
+    let position = next_dish_position(&trans, &restaurant_uuid, menu_category_uuid.as_ref())?;
+    let rows = trans.query(
+        "
+        UPDATE dish
+        SET menu_category_id = $3, position = $4
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[&dish_uuid, &restaurant_uuid, &menu_category_uuid, &position],
+    )?;
+    if rows.is_empty() {
+        return Err(menu_error("Dish does not exist"));
+    }
+    trans.commit()?;
+    Ok(Dish::from_row(&rows.get(0)))
+}
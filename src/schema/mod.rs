@@ -1,9 +1,24 @@
+pub mod bill;
+pub mod bill_check;
 pub mod context;
+pub mod customer;
 pub mod customer_order;
 pub mod dining_table;
 pub mod dish;
 pub mod dish_order;
+pub mod floor_area;
+pub mod ingredient;
+pub mod menu_category;
+pub mod modifier;
 pub mod mutation;
+pub mod order_event;
 pub mod partner;
+pub mod payment;
+pub mod permission;
+pub mod purchase_order;
 pub mod query;
+pub mod rate_limit;
 pub mod restaurant;
+pub mod session;
+pub mod subscription;
+pub mod table_session;
@@ -0,0 +1,473 @@
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::context::Context;
+use super::permission::Permission;
+
+pub struct ModifierGroup {
+    pub id: String,
+    pub dish_id: String,
+    pub name: String,
+    pub min_selections: i32,
+    pub max_selections: i32,
+    pub position: i32,
+}
+
+impl ModifierGroup {
+    pub fn from_row(row: &Row) -> ModifierGroup {
+        let id: Uuid = row.get("id");
+        let dish_id: Uuid = row.get("dish_id");
+        ModifierGroup {
+            id: id.hyphenated().to_string(),
+            dish_id: dish_id.hyphenated().to_string(),
+            name: row.get("name"),
+            min_selections: row.get("min_selections"),
+            max_selections: row.get("max_selections"),
+            position: row.get("position"),
+        }
+    }
+
+    pub fn for_dish(
+        conn: &dyn GenericConnection,
+        dish_id: &Uuid,
+    ) -> FieldResult<Vec<ModifierGroup>> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM modifier_group
+            WHERE dish_id = $1
+            ORDER BY position
+        ",
+            &[dish_id],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| ModifierGroup::from_row(&row))
+            .collect())
+    }
+}
+
+#[derive(GraphQLObject)]
+pub struct ModifierOption {
+    pub id: String,
+    pub modifier_group_id: String,
+    pub name: String,
+    pub price_delta: i32,
+    pub position: i32,
+}
+
+impl ModifierOption {
+    pub fn from_row(row: &Row) -> ModifierOption {
+        let id: Uuid = row.get("id");
+        let modifier_group_id: Uuid = row.get("modifier_group_id");
+        ModifierOption {
+            id: id.hyphenated().to_string(),
+            modifier_group_id: modifier_group_id.hyphenated().to_string(),
+            name: row.get("name"),
+            price_delta: row.get("price_delta"),
+            position: row.get("position"),
+        }
+    }
+}
+
+graphql_object!(ModifierGroup: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field dish_id() -> &str {
+    self.dish_id.as_str()
+  }
+  field name() -> &str {
+    self.name.as_str()
+  }
+  field min_selections() -> i32 {
+    self.min_selections
+  }
+  field max_selections() -> i32 {
+    self.max_selections
+  }
+  field position() -> i32 {
+    self.position
+  }
+  field options(&executor) -> FieldResult<Vec<ModifierOption>> {
+    let conn = executor.context().pool.get()?;
+    let modifier_group_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM modifier_option
+      WHERE modifier_group_id = $1
+      ORDER BY position
+    ", &[&modifier_group_uuid])?;
+    let mut options = vec!();
+    for row in &rows {
+      options.push(ModifierOption::from_row(&row));
+    }
+    Ok(options)
+  }
+});
+
+/// A selection as it was priced when the item was ordered.
+#[derive(GraphQLObject)]
+pub struct DishOrderModifier {
+    pub modifier_option_id: Option<String>,
+    pub group_name: String,
+    pub option_name: String,
+    pub price_delta: i32,
+}
+
+impl DishOrderModifier {
+    pub fn from_row(row: &Row) -> DishOrderModifier {
+        let modifier_option_id: Option<Uuid> = row.get("modifier_option_id");
+        DishOrderModifier {
+            modifier_option_id: modifier_option_id.map(|id| id.hyphenated().to_string()),
+            group_name: row.get("group_name"),
+            option_name: row.get("option_name"),
+            price_delta: row.get("price_delta"),
+        }
+    }
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewModifierOption {
+    pub name: String,
+    pub price_delta: i32,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewModifierGroup {
+    pub name: String,
+    pub min_selections: i32,
+    pub max_selections: i32,
+    pub options: Vec<NewModifierOption>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct ModifierGroupUpdate {
+    pub name: Option<String>,
+    pub min_selections: Option<i32>,
+    pub max_selections: Option<i32>,
+}
+
+fn modifier_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+fn validate_selection_rules(min_selections: i32, max_selections: i32) -> FieldResult<()> {
+    if min_selections < 0 || max_selections < 1 || min_selections > max_selections {
+        return Err(modifier_error(
+            "Selections need 0 <= min <= max and a max of at least 1",
+        ));
+    }
+    Ok(())
+}
+
+/// Locks a modifier group of a dish in the partner's restaurant.
+fn find_group_for_update(
+    conn: &dyn GenericConnection,
+    id: &Uuid,
+    restaurant_id: &Uuid,
+) -> FieldResult<ModifierGroup> {
+    let rows = conn.query(
+        "
+        SELECT modifier_group.*
+        FROM modifier_group
+        JOIN dish ON dish.id = modifier_group.dish_id
+        WHERE modifier_group.id = $1 AND dish.restaurant_id = $2
+        FOR UPDATE OF modifier_group
+    ",
+        &[id, restaurant_id],
+    )?;
+    if rows.is_empty() {
+        return Err(modifier_error("Modifier group does not exist"));
+    }
+    Ok(ModifierGroup::from_row(&rows.get(0)))
+}
+
+fn insert_option(
+    conn: &dyn GenericConnection,
+    modifier_group_id: &Uuid,
+    option: &NewModifierOption,
+) -> FieldResult<ModifierOption> {
+    let rows = conn.query(
+        "
+        INSERT INTO modifier_option (
+            id,
+            modifier_group_id,
+            name,
+            price_delta,
+            position
+        ) VALUES (
+            $1, $2, $3, $4,
+            (SELECT COALESCE(MAX(position) + 1, 0) FROM modifier_option WHERE modifier_group_id = $2)
+        )
+        RETURNING *
+    ",
+        &[
+            &Uuid::new_v4(),
+            modifier_group_id,
+            &option.name,
+            &option.price_delta,
+        ],
+    )?;
+    Ok(ModifierOption::from_row(&rows.get(0)))
+}
+
+pub fn create_modifier_group(
+    context: &Context,
+    dish_id: &str,
+    input: NewModifierGroup,
+) -> FieldResult<ModifierGroup> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let dish_uuid = Uuid::parse_str(dish_id)?;
+    validate_selection_rules(input.min_selections, input.max_selections)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let dish_rows = trans.query(
+        "
+        SELECT 1
+        FROM dish
+        WHERE id = $1 AND restaurant_id = $2
+        FOR UPDATE
+    ",
+        &[&dish_uuid, &restaurant_uuid],
+    )?;
+    if dish_rows.is_empty() {
+        return Err(modifier_error("Dish does not exist"));
+    }
+    let rows = trans.query(
+        "
+        INSERT INTO modifier_group (
+            id,
+            dish_id,
+            name,
+            min_selections,
+            max_selections,
+            position
+        ) VALUES (
+            $1, $2, $3, $4, $5,
+            (SELECT COALESCE(MAX(position) + 1, 0) FROM modifier_group WHERE dish_id = $2)
+        )
+        RETURNING *
+    ",
+        &[
+            &Uuid::new_v4(),
+            &dish_uuid,
+            &input.name,
+            &input.min_selections,
+            &input.max_selections,
+        ],
+    )?;
+    let modifier_group = ModifierGroup::from_row(&rows.get(0));
+    let modifier_group_uuid = Uuid::parse_str(&modifier_group.id)?;
+    for option in &input.options {
+        insert_option(&trans, &modifier_group_uuid, option)?;
+    }
+    trans.commit()?;
+    Ok(modifier_group)
+}
+
+pub fn update_modifier_group(
+    context: &Context,
+    id: &str,
+    input: ModifierGroupUpdate,
+) -> FieldResult<ModifierGroup> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let modifier_group_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let current = find_group_for_update(&trans, &modifier_group_uuid, &restaurant_uuid)?;
+    let min_selections = input.min_selections.unwrap_or(current.min_selections);
+    let max_selections = input.max_selections.unwrap_or(current.max_selections);
+    validate_selection_rules(min_selections, max_selections)?;
+    let rows = trans.query(
+        "
+        UPDATE modifier_group
+        SET name = COALESCE($2, name),
+            min_selections = $3,
+            max_selections = $4
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[
+            &modifier_group_uuid,
+            &input.name,
+            &min_selections,
+            &max_selections,
+        ],
+    )?;
+    trans.commit()?;
+    Ok(ModifierGroup::from_row(&rows.get(0)))
+}
+
+/// Placed orders keep their snapshotted selections.
+pub fn delete_modifier_group(context: &Context, id: &str) -> FieldResult<ModifierGroup> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let modifier_group_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let modifier_group = find_group_for_update(&trans, &modifier_group_uuid, &restaurant_uuid)?;
+    trans.execute(
+        "
+        DELETE FROM modifier_group
+        WHERE id = $1
+    ",
+        &[&modifier_group_uuid],
+    )?;
+    trans.commit()?;
+    Ok(modifier_group)
+}
+
+pub fn add_modifier_option(
+    context: &Context,
+    modifier_group_id: &str,
+    input: NewModifierOption,
+) -> FieldResult<ModifierOption> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let modifier_group_uuid = Uuid::parse_str(modifier_group_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    find_group_for_update(&trans, &modifier_group_uuid, &restaurant_uuid)?;
+    let option = insert_option(&trans, &modifier_group_uuid, &input)?;
+    trans.commit()?;
+    Ok(option)
+}
+
+pub fn delete_modifier_option(context: &Context, id: &str) -> FieldResult<ModifierOption> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+    let modifier_option_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        DELETE FROM modifier_option
+        USING modifier_group, dish
+        WHERE modifier_option.id = $1
+            AND modifier_group.id = modifier_option.modifier_group_id
+            AND dish.id = modifier_group.dish_id
+            AND dish.restaurant_id = $2
+        RETURNING modifier_option.*
+    ",
+        &[&modifier_option_uuid, &restaurant_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(modifier_error("Modifier option does not exist"));
+    }
+    Ok(ModifierOption::from_row(&rows.get(0)))
+}
+
+/// Modifiers picked for an order line, checked against the dish's rules.
+pub struct Selection {
+    pub modifiers: Vec<(Uuid, DishOrderModifier)>,
+    pub price_delta: i32,
+}
+
+/// Validates `option_ids` against every modifier group of the dish: each option
+/// must belong to the dish, be picked at most once and leave every group within
+/// its min and max.
+pub fn select_modifiers(
+    conn: &dyn GenericConnection,
+    dish_id: &Uuid,
+    option_ids: &[String],
+) -> FieldResult<Selection> {
+    let mut picked = HashSet::new();
+    for id in option_ids {
+        if !picked.insert(Uuid::parse_str(id)?) {
+            return Err(modifier_error("A modifier can only be picked once"));
+        }
+    }
+    let rows = conn.query(
+        "
+        SELECT
+            modifier_group.id AS group_id,
+            modifier_group.name AS group_name,
+            modifier_group.min_selections,
+            modifier_group.max_selections,
+            modifier_option.id AS option_id,
+            modifier_option.name AS option_name,
+            modifier_option.price_delta
+        FROM modifier_group
+        LEFT JOIN modifier_option ON modifier_option.modifier_group_id = modifier_group.id
+        WHERE modifier_group.dish_id = $1
+        ORDER BY modifier_group.position, modifier_option.position
+    ",
+        &[dish_id],
+    )?;
+
+    let mut modifiers = vec![];
+    let mut price_delta = 0i64;
+    let mut counts: Vec<(Uuid, String, i32, i32, i32)> = vec![];
+    for row in &rows {
+        let group_id: Uuid = row.get("group_id");
+        if counts.last().map(|count| count.0) != Some(group_id) {
+            counts.push((
+                group_id,
+                row.get("group_name"),
+                row.get("min_selections"),
+                row.get("max_selections"),
+                0,
+            ));
+        }
+        let option_id: Option<Uuid> = row.get("option_id");
+        let option_id = match option_id {
+            Some(option_id) if picked.remove(&option_id) => option_id,
+            _ => continue,
+        };
+        counts.last_mut().unwrap().4 += 1;
+        let modifier = DishOrderModifier {
+            modifier_option_id: Some(option_id.hyphenated().to_string()),
+            group_name: row.get("group_name"),
+            option_name: row.get("option_name"),
+            price_delta: row.get("price_delta"),
+        };
+        price_delta += i64::from(modifier.price_delta);
+        modifiers.push((option_id, modifier));
+    }
+    if !picked.is_empty() {
+        return Err(modifier_error("Modifier is not available for this dish"));
+    }
+    for (_, name, min_selections, max_selections, count) in counts {
+        if count < min_selections || count > max_selections {
+            let message = format!(
+                "Pick between {} and {} of {}",
+                min_selections, max_selections, name
+            );
+            return Err(modifier_error(&message));
+        }
+    }
+    Ok(Selection {
+        modifiers,
+        price_delta: price_delta as i32,
+    })
+}
+
+pub fn insert_dish_order_modifiers(
+    conn: &dyn GenericConnection,
+    dish_order_id: &Uuid,
+    selection: &Selection,
+) -> FieldResult<()> {
+    for (position, (option_id, modifier)) in selection.modifiers.iter().enumerate() {
+        conn.execute(
+            "
+            INSERT INTO dish_order_modifier (
+                dish_order_id,
+                modifier_option_id,
+                group_name,
+                option_name,
+                price_delta,
+                position
+            ) VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+            &[
+                dish_order_id,
+                option_id,
+                &modifier.group_name,
+                &modifier.option_name,
+                &modifier.price_delta,
+                &(position as i32),
+            ],
+        )?;
+    }
+    Ok(())
+}
@@ -1,20 +1,64 @@
-use crypto::digest::Digest;
 use juniper::{FieldError, FieldResult};
 use uuid::Uuid;
 
+use super::bill_check::{
+    remove_split, split_by_amounts, split_by_items, split_evenly_into, AmountCheck, BillCheck,
+    ItemCheck,
+};
 use super::context::{Context, Roles};
-use super::customer_order::{CustomerOrder, CustomerOrderStatus, NewCustomerOrder};
-use super::dining_table::{DiningTable, NewDiningTable};
-use super::dish::{Dish, NewDish};
-use super::dish_order::{DishOrder, NewDishOrder};
-use super::partner::{NewPartner, Partner, PartnerSignIn};
-use super::restaurant::{NewRestaurant, Restaurant};
+use super::customer_order::{
+    join_customer_order, transition_customer_order, CustomerOrder, CustomerOrderError,
+    CustomerOrderStatus, NewCustomerOrder,
+};
+use super::dining_table::{
+    mark_dining_table_clean, validate_capacity, DiningTable, DiningTableShape, DiningTableUpdate,
+    NewDiningTable,
+};
+use super::dish::{validate_price, Dish, DishUpdate, NewDish};
+use super::dish_order::{update_dish_order_status, DishOrder, DishOrderStatus, NewDishOrder};
+use super::floor_area::{
+    create_floor_area, delete_floor_area, restaurant_floor_area, update_floor_area, FloorArea,
+    FloorAreaUpdate, NewFloorArea,
+};
+use super::ingredient::{
+    create_ingredient, record_stock_take, record_wastage, set_recipe_line, update_ingredient,
+    Ingredient, IngredientUpdate, NewIngredient, RecipeLine,
+};
+use super::menu_category::{
+    create_menu_category, delete_menu_category, next_dish_position, rename_menu_category,
+    reorder_dishes, reorder_menu_categories, set_dish_menu_category, MenuCategory, NewMenuCategory,
+};
+use super::modifier::{
+    add_modifier_option, create_modifier_group, delete_modifier_group, delete_modifier_option,
+    insert_dish_order_modifiers, select_modifiers, update_modifier_group, ModifierGroup,
+    ModifierGroupUpdate, ModifierOption, NewModifierGroup, NewModifierOption,
+};
+use super::order_event::{publish_order_event, OrderEvent, OrderEventKind};
+use super::partner::{
+    approve_partner, deactivate_partner, reactivate_partner, set_partner_staff_role,
+    sign_in_partner, sign_up_partner, NewPartner, Partner, PartnerSignIn,
+};
+use super::payment::{create_payment, NewPayment, Payment};
+use super::permission::{Permission, StaffRole};
+use super::purchase_order::{
+    cancel_purchase_order, create_purchase_order, create_supplier, receive_purchase_order,
+    submit_purchase_order, update_supplier, NewPurchaseOrder, NewSupplier, PurchaseOrder,
+    ReceivedLine, Supplier, SupplierUpdate,
+};
+use super::restaurant::{
+    validate_rate, NewRestaurant, Restaurant, RestaurantCharges, RestaurantUpdate,
+};
+use super::session::{end_all_sessions, end_session, refresh_session, AuthTokens};
+use super::table_session::{
+    close_table_session, merge_table_sessions, seat_customer, split_table_session,
+    transfer_table_session, TableSession,
+};
 
 pub struct Mutation;
 
 graphql_object!(Mutation: Context | &self | {
-    field create_token_from_code(&executor, code: String) -> FieldResult<String> {
-        if let Some(token) = executor.context().authenticate(&code).ok() {
+    field create_token_from_code(&executor, phone: String, code: String) -> FieldResult<AuthTokens> {
+        if let Some(token) = executor.context().authenticate(&phone, &code).ok() {
             Ok(token)
         } else {
             Err(FieldError::new(
@@ -24,15 +68,29 @@ graphql_object!(Mutation: Context | &self | {
         }
     }
 
-    field create_anonymous_customer_token(&executor) -> FieldResult<String> {
+    field create_anonymous_customer_token(&executor) -> FieldResult<AuthTokens> {
         executor.context().create_anonymous_customer_token()
     }
 
+    field refresh_tokens(&executor, refresh_token: String) -> FieldResult<AuthTokens> {
+        refresh_session(executor.context(), &refresh_token)
+    }
+
+    field log_out(&executor) -> FieldResult<bool> {
+        end_session(executor.context())
+    }
+
+    field log_out_all_devices(&executor) -> FieldResult<bool> {
+        end_all_sessions(executor.context())
+    }
+
     field create_restaurant(&executor, input: NewRestaurant) -> FieldResult<Restaurant> {
         let context = executor.context();
         context.authorize(Roles::Admin)?;
         let conn = context.pool.get()?;
         let id = Uuid::new_v4();
+        let tax_rate = validate_rate(input.tax_rate.unwrap_or(0))?;
+        let service_charge_rate = validate_rate(input.service_charge_rate.unwrap_or(0))?;
         let inserts = conn.execute("
             INSERT INTO restaurant (
                 id,
@@ -40,15 +98,19 @@ graphql_object!(Mutation: Context | &self | {
                 address,
                 logo,
                 cover,
-                location_url
-            ) VALUES ($1, $2, $3, $4, $5, $6)
+                location_url,
+                tax_rate,
+                service_charge_rate
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         ", &[
             &id,
             &input.name,
             &input.address,
             &input.logo,
             &input.cover,
-            &input.location_url
+            &input.location_url,
+            &tax_rate,
+            &service_charge_rate
         ])?;
         let rows = conn.query("
             SELECT *
@@ -56,35 +118,116 @@ graphql_object!(Mutation: Context | &self | {
             WHERE id = $1
         ", &[&id])?;
 
-        let row = rows.get(0);
-        let row_id: Uuid = row.get("id");
-        Ok(Restaurant {
-            id: row_id.hyphenated().to_string(),
-            name: row.get("name"),
-            address: row.get("address"),
-            logo: row.get("logo"),
-            cover: row.get("cover"),
-            location_url: row.get("location_url"),
-        })
+        Ok(Restaurant::from_row(&rows.get(0)))
+    }
+
+    field update_restaurant(&executor, id: String, input: RestaurantUpdate) -> FieldResult<Restaurant> {
+        let context = executor.context();
+        context.authorize_restaurant(&id, Permission::ManageRestaurant)?;
+        let restaurant_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE restaurant
+            SET name = COALESCE($2, name),
+                address = COALESCE($3, address),
+                logo = COALESCE($4, logo),
+                cover = COALESCE($5, cover),
+                location_url = COALESCE($6, location_url)
+            WHERE id = $1
+            RETURNING *
+        ", &[
+            &restaurant_uuid,
+            &input.name,
+            &input.address,
+            &input.logo,
+            &input.cover,
+            &input.location_url
+        ])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(Restaurant::from_row(&rows.get(0)))
+    }
+
+    field archive_restaurant(&executor, id: String) -> FieldResult<Restaurant> {
+        let context = executor.context();
+        context.authorize(Roles::Admin)?;
+        let restaurant_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE restaurant
+            SET archived_at = COALESCE(archived_at, now())
+            WHERE id = $1
+            RETURNING *
+        ", &[&restaurant_uuid])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(Restaurant::from_row(&rows.get(0)))
+    }
+
+    field restore_restaurant(&executor, id: String) -> FieldResult<Restaurant> {
+        let context = executor.context();
+        context.authorize(Roles::Admin)?;
+        let restaurant_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE restaurant
+            SET archived_at = NULL
+            WHERE id = $1
+            RETURNING *
+        ", &[&restaurant_uuid])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(Restaurant::from_row(&rows.get(0)))
+    }
+
+    field update_restaurant_charges(&executor, input: RestaurantCharges) -> FieldResult<Restaurant> {
+        let context = executor.context();
+        let restaurant_id = context.authorize_permission(Permission::ManageRestaurant)?;
+        let restaurant_uuid = Uuid::parse_str(&restaurant_id)?;
+        let tax_rate = validate_rate(input.tax_rate)?;
+        let service_charge_rate = validate_rate(input.service_charge_rate)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE restaurant
+            SET tax_rate = $2, service_charge_rate = $3
+            WHERE id = $1
+            RETURNING *
+        ", &[&restaurant_uuid, &tax_rate, &service_charge_rate])?;
+
+        Ok(Restaurant::from_row(&rows.get(0)))
     }
 
     field create_dining_table(&executor, input: NewDiningTable) -> FieldResult<DiningTable> {
         let context = executor.context();
-        context.authorize(Roles::Partner)?;
+        let restaurant_id = context.authorize_permission(Permission::ManageRestaurant)?;
+        validate_capacity(input.capacity)?;
         let conn = context.pool.get()?;
-        let restaurant_id = context.get_partner_restaurant_id()?;
         let restaurant_uuid = Uuid::parse_str(&restaurant_id)?;
+        let floor_area_uuid = restaurant_floor_area(&*conn, &input.floor_area_id, &restaurant_uuid)?;
         let id = Uuid::new_v4();
         let inserts = conn.execute("
             INSERT INTO dining_table (
                 id,
                 name,
-                restaurant_id
-            ) VALUES ($1, $2, $3)
+                restaurant_id,
+                floor_area_id,
+                capacity,
+                shape,
+                position_x,
+                position_y
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         ", &[
             &id,
             &input.name,
-            &restaurant_uuid
+            &restaurant_uuid,
+            &floor_area_uuid,
+            &input.capacity.unwrap_or(4),
+            &input.shape.unwrap_or(DiningTableShape::Square),
+            &input.position_x.unwrap_or(0.0),
+            &input.position_y.unwrap_or(0.0)
         ])?;
         let rows = conn.query("
             SELECT *
@@ -92,209 +235,553 @@ graphql_object!(Mutation: Context | &self | {
             WHERE id = $1
         ", &[&id])?;
 
-        let row = rows.get(0);
-        let row_id: Uuid = row.get("id");
-        Ok(DiningTable {
-            id: row_id.hyphenated().to_string(),
-            name: row.get("name"),
-            restaurant_id: restaurant_id,
-        })
+        Ok(DiningTable::from_row(&rows.get(0)))
+    }
+
+    field update_dining_table(&executor, id: String, input: DiningTableUpdate) -> FieldResult<DiningTable> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageRestaurant)?)?;
+        let dining_table_uuid = Uuid::parse_str(&id)?;
+        validate_capacity(input.capacity)?;
+        let conn = context.pool.get()?;
+        let floor_area_uuid = restaurant_floor_area(&*conn, &input.floor_area_id, &restaurant_uuid)?;
+        let rows = conn.query("
+            UPDATE dining_table
+            SET name = COALESCE($3, name),
+                floor_area_id = CASE WHEN $5 THEN NULL ELSE COALESCE($4, floor_area_id) END,
+                capacity = COALESCE($6, capacity),
+                shape = COALESCE($7, shape),
+                position_x = COALESCE($8, position_x),
+                position_y = COALESCE($9, position_y)
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[
+            &dining_table_uuid,
+            &restaurant_uuid,
+            &input.name,
+            &floor_area_uuid,
+            &input.clear_floor_area.unwrap_or(false),
+            &input.capacity,
+            &input.shape,
+            &input.position_x,
+            &input.position_y
+        ])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(DiningTable::from_row(&rows.get(0)))
+    }
+
+    field archive_dining_table(&executor, id: String) -> FieldResult<DiningTable> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageRestaurant)?)?;
+        let dining_table_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let trans = conn.transaction()?;
+        // the row lock is the one seat_customer takes, so no one sits down meanwhile
+        let table_rows = trans.query("
+            SELECT 1
+            FROM dining_table
+            WHERE id = $1 AND restaurant_id = $2
+            FOR UPDATE
+        ", &[&dining_table_uuid, &restaurant_uuid])?;
+        if table_rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        let open_order_rows = trans.query("
+            SELECT 1
+            FROM customer_order
+            WHERE dining_table_id = $1 AND status <> $2
+        ", &[&dining_table_uuid, &CustomerOrderStatus::Done])?;
+        if !open_order_rows.is_empty() {
+            return Err(FieldError::new("Dining table still has open orders", graphql_value!({"external_error": "Dining table still has open orders"})));
+        }
+        let rows = trans.query("
+            UPDATE dining_table
+            SET archived_at = COALESCE(archived_at, now())
+            WHERE id = $1
+            RETURNING *
+        ", &[&dining_table_uuid])?;
+        trans.commit()?;
+        Ok(DiningTable::from_row(&rows.get(0)))
+    }
+
+    field restore_dining_table(&executor, id: String) -> FieldResult<DiningTable> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageRestaurant)?)?;
+        let dining_table_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE dining_table
+            SET archived_at = NULL
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[&dining_table_uuid, &restaurant_uuid])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(DiningTable::from_row(&rows.get(0)))
+    }
+
+    // reprinting is needed afterwards, every earlier QR code of the table stops working
+    field rotate_dining_table_qr(&executor, id: String) -> FieldResult<DiningTable> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageRestaurant)?)?;
+        let dining_table_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE dining_table
+            SET qr_token_version = qr_token_version + 1
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[&dining_table_uuid, &restaurant_uuid])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(DiningTable::from_row(&rows.get(0)))
+    }
+
+    field mark_dining_table_clean(&executor, id: String) -> FieldResult<DiningTable> {
+        mark_dining_table_clean(executor.context(), &id)
+    }
+
+    field create_floor_area(&executor, input: NewFloorArea) -> FieldResult<FloorArea> {
+        create_floor_area(executor.context(), input)
+    }
+
+    field update_floor_area(&executor, id: String, input: FloorAreaUpdate) -> FieldResult<FloorArea> {
+        update_floor_area(executor.context(), &id, input)
+    }
+
+    // tables of the area stay, without an area
+    field delete_floor_area(&executor, id: String) -> FieldResult<FloorArea> {
+        delete_floor_area(executor.context(), &id)
     }
 
     field create_dish(&executor, input: NewDish) -> FieldResult<Dish> {
         let context = executor.context();
-        context.authorize(Roles::Partner)?;
-        let restaurant_id = context.get_partner_restaurant_id()?;
+        let restaurant_id = context.authorize_permission(Permission::ManageMenu)?;
         let restaurant_uuid = Uuid::parse_str(&restaurant_id)?;
+        validate_price(input.price)?;
         let conn = context.pool.get()?;
+        let trans = conn.transaction()?;
+        let menu_category_uuid = match input.menu_category_id {
+            Some(ref menu_category_id) => {
+                let menu_category_uuid = Uuid::parse_str(menu_category_id)?;
+                MenuCategory::find_for_restaurant(&trans, &menu_category_uuid, &restaurant_uuid)?;
+                Some(menu_category_uuid)
+            }
+            None => None,
+        };
+        let position = next_dish_position(&trans, &restaurant_uuid, menu_category_uuid.as_ref())?;
         let id = Uuid::new_v4();
-        let inserts = conn.execute("
+        let inserts = trans.execute("
             INSERT INTO dish (
                 id,
                 name,
                 price,
                 description,
-                restaurant_id
-            ) VALUES ($1, $2, $3, $4, $5)
+                restaurant_id,
+                menu_category_id,
+                position
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7)
         ", &[
             &id,
             &input.name,
             &input.price,
             &input.description,
-            &restaurant_uuid
+            &restaurant_uuid,
+            &menu_category_uuid,
+            &position
         ])?;
-        let rows = conn.query("
+        let rows = trans.query("
             SELECT *
             FROM dish
             WHERE id = $1
         ", &[&id])?;
+        trans.commit()?;
 
-        let row = rows.get(0);
-        let row_id: Uuid = row.get("id");
-        Ok(Dish {
-            id: row_id.hyphenated().to_string(),
-            name: row.get("name"),
-            price: row.get("price"),
-            description: row.get("description"),
-            restaurant_id: restaurant_id,
-        })
+        Ok(Dish::from_row(&rows.get(0)))
     }
 
-    field partner_sign_up(&executor, input: NewPartner) -> FieldResult<Partner> {
+    field set_dish_availability(&executor, id: String, available: bool) -> FieldResult<Dish> {
         let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::UpdateAvailability)?)?;
+        let dish_uuid = Uuid::parse_str(&id)?;
         let conn = context.pool.get()?;
-        let mut hasher = crypto::sha3::Sha3::sha3_224();
-        hasher.input_str(&input.password);
-        let hashed_password = hasher.result_str();
-        let restaurant_id = Uuid::parse_str(&input.restaurant_id)?;
-        let id = Uuid::new_v4();
-        let inserts = conn.execute("
-            INSERT INTO partner (
-                id,
-                name,
-                username,
-                hashed_password,
-                email,
-                phone,
-                picture,
-                restaurant_id
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-        ", &[
-            &id,
-            &input.name,
-            &input.username,
-            &hashed_password,
-            &input.email,
-            &input.phone,
-            &input.picture,
-            &restaurant_id,
-        ])?;
         let rows = conn.query("
-            SELECT *
-            FROM partner
-            WHERE id = $1
-        ", &[&id])?;
+            UPDATE dish
+            SET available = $3
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[&dish_uuid, &restaurant_uuid, &available])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(Dish::from_row(&rows.get(0)))
+    }
 
-        let row = rows.get(0);
-        let row_id: Uuid = row.get("id");
-        Ok(Partner {
-            id: row_id.hyphenated().to_string(),
-            name: row.get("name"),
-            username: row.get("username"),
-            email: row.get("email"),
-            phone: row.get("phone"),
-            picture: row.get("picture"),
-            is_active: row.get("is_active"),
-            restaurant_id: restaurant_id.hyphenated().to_string(),
-        })
-    }
-
-    field partner_sign_in(&executor, input: PartnerSignIn) -> FieldResult<String> {
+    // leaving out `stock` stops counting the dish
+    field set_dish_stock(&executor, id: String, stock: Option<i32>) -> FieldResult<Dish> {
         let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::UpdateAvailability)?)?;
+        let dish_uuid = Uuid::parse_str(&id)?;
+        if stock.is_some_and(|stock| stock < 0) {
+            return Err(FieldError::new("Stock cannot be negative", graphql_value!({"external_error": "Stock cannot be negative"})));
+        }
         let conn = context.pool.get()?;
-        let mut hasher = crypto::sha3::Sha3::sha3_224();
-        hasher.input_str(&input.password);
-        let hashed_password = hasher.result_str();
         let rows = conn.query("
-            SELECT *
-            FROM partner
-            WHERE username = $1 AND hashed_password = $2
-        ", &[&input.username, &hashed_password])?;
+            UPDATE dish
+            SET stock = $3
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[&dish_uuid, &restaurant_uuid, &stock])?;
         if rows.is_empty() {
-            return Err(FieldError::new("Unauthenticated", graphql_value!({ "internal_error": "Unauthenticated" })));
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
         }
-        let row = rows.get(0);
-        let partner_id: Uuid = row.get("id");
-        let token = context.create_partner_token(&partner_id.hyphenated().to_string())?;
-        Ok(token)
+        Ok(Dish::from_row(&rows.get(0)))
     }
 
-    field create_customer_order(&executor, input: NewCustomerOrder) -> FieldResult<CustomerOrder> {
+    field set_dish_menu_category(&executor, id: String, menu_category_id: Option<String>) -> FieldResult<Dish> {
+        set_dish_menu_category(executor.context(), &id, menu_category_id)
+    }
+
+    field create_modifier_group(&executor, dish_id: String, input: NewModifierGroup) -> FieldResult<ModifierGroup> {
+        create_modifier_group(executor.context(), &dish_id, input)
+    }
+
+    field update_modifier_group(&executor, id: String, input: ModifierGroupUpdate) -> FieldResult<ModifierGroup> {
+        update_modifier_group(executor.context(), &id, input)
+    }
+
+    field delete_modifier_group(&executor, id: String) -> FieldResult<ModifierGroup> {
+        delete_modifier_group(executor.context(), &id)
+    }
+
+    field add_modifier_option(&executor, modifier_group_id: String, input: NewModifierOption) -> FieldResult<ModifierOption> {
+        add_modifier_option(executor.context(), &modifier_group_id, input)
+    }
+
+    field delete_modifier_option(&executor, id: String) -> FieldResult<ModifierOption> {
+        delete_modifier_option(executor.context(), &id)
+    }
+
+    field create_ingredient(&executor, input: NewIngredient) -> FieldResult<Ingredient> {
+        create_ingredient(executor.context(), input)
+    }
+
+    field update_ingredient(&executor, id: String, input: IngredientUpdate) -> FieldResult<Ingredient> {
+        update_ingredient(executor.context(), &id, input)
+    }
+
+    field set_recipe_line(&executor, dish_id: String, ingredient_id: String, quantity: i32) -> FieldResult<Vec<RecipeLine>> {
+        set_recipe_line(executor.context(), &dish_id, &ingredient_id, quantity)
+    }
+
+    field record_stock_take(&executor, ingredient_id: String, counted: i32, note: Option<String>) -> FieldResult<Ingredient> {
+        record_stock_take(executor.context(), &ingredient_id, counted, note)
+    }
+
+    field record_wastage(&executor, ingredient_id: String, quantity: i32, note: Option<String>) -> FieldResult<Ingredient> {
+        record_wastage(executor.context(), &ingredient_id, quantity, note)
+    }
+
+    field create_supplier(&executor, input: NewSupplier) -> FieldResult<Supplier> {
+        create_supplier(executor.context(), input)
+    }
+
+    field update_supplier(&executor, id: String, input: SupplierUpdate) -> FieldResult<Supplier> {
+        update_supplier(executor.context(), &id, input)
+    }
+
+    field create_purchase_order(&executor, input: NewPurchaseOrder) -> FieldResult<PurchaseOrder> {
+        create_purchase_order(executor.context(), input)
+    }
+
+    field submit_purchase_order(&executor, id: String) -> FieldResult<PurchaseOrder> {
+        submit_purchase_order(executor.context(), &id)
+    }
+
+    field cancel_purchase_order(&executor, id: String) -> FieldResult<PurchaseOrder> {
+        cancel_purchase_order(executor.context(), &id)
+    }
+
+    field receive_purchase_order(&executor, id: String, lines: Vec<ReceivedLine>) -> FieldResult<PurchaseOrder> {
+        receive_purchase_order(executor.context(), &id, lines)
+    }
+
+    field create_menu_category(&executor, input: NewMenuCategory) -> FieldResult<MenuCategory> {
+        create_menu_category(executor.context(), input)
+    }
+
+    field rename_menu_category(&executor, id: String, name: String) -> FieldResult<MenuCategory> {
+        rename_menu_category(executor.context(), &id, &name)
+    }
+
+    field delete_menu_category(&executor, id: String) -> FieldResult<MenuCategory> {
+        delete_menu_category(executor.context(), &id)
+    }
+
+    field reorder_menu_categories(&executor, ids: Vec<String>) -> FieldResult<Vec<MenuCategory>> {
+        reorder_menu_categories(executor.context(), ids)
+    }
+
+    field reorder_dishes(&executor, menu_category_id: String, dish_ids: Vec<String>) -> FieldResult<MenuCategory> {
+        reorder_dishes(executor.context(), &menu_category_id, dish_ids)
+    }
+
+    field update_dish(&executor, id: String, input: DishUpdate) -> FieldResult<Dish> {
         let context = executor.context();
-        context.authorize(Roles::Customer)?;
-        let customer_id = context.get_client_id()?;
-        let customer_uuid = Uuid::parse_str(&customer_id)?;
-        let dining_table_uuid = Uuid::parse_str(&input.dining_table_id)?;
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+        let dish_uuid = Uuid::parse_str(&id)?;
+        if let Some(price) = input.price {
+            validate_price(price)?;
+        }
         let conn = context.pool.get()?;
+        // placed orders keep the price they were snapshotted with
+        let rows = conn.query("
+            UPDATE dish
+            SET name = COALESCE($3, name),
+                description = COALESCE($4, description),
+                price = COALESCE($5, price)
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[&dish_uuid, &restaurant_uuid, &input.name, &input.description, &input.price])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(Dish::from_row(&rows.get(0)))
+    }
 
-        let dining_table_rows = conn.query("
-            SELECT restaurant_id
-            FROM dining_table
-            WHERE id = $1", &[&dining_table_uuid])?;
-        if dining_table_rows.is_empty() {
-            return Err(FieldError::new("Notfound", graphql_value!({"external_error": "Dining table does not exist"})));
+    field archive_dish(&executor, id: String) -> FieldResult<Dish> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+        let dish_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE dish
+            SET archived_at = COALESCE(archived_at, now())
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[&dish_uuid, &restaurant_uuid])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
         }
+        Ok(Dish::from_row(&rows.get(0)))
+    }
 
-        let dining_table_row = dining_table_rows.get(0);
-        let restaurant_uuid: Uuid = dining_table_row.get("restaurant_id");
-        let customer_order_uuid = Uuid::new_v4();
-        let status = CustomerOrderStatus::Open;
+    field restore_dish(&executor, id: String) -> FieldResult<Dish> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageMenu)?)?;
+        let dish_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            UPDATE dish
+            SET archived_at = NULL
+            WHERE id = $1 AND restaurant_id = $2
+            RETURNING *
+        ", &[&dish_uuid, &restaurant_uuid])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(Dish::from_row(&rows.get(0)))
+    }
 
-        let inserts = conn.query("
-            INSERT INTO customer_order (
-                id,
-                restaurant_id,
-                dining_table_id,
-                customer_id,
-                status
-            ) VALUES ($1, $2, $3, $4, $5)
-        ", &[&customer_order_uuid, &restaurant_uuid, &dining_table_uuid, &customer_uuid, &status])?;
+    field partner_sign_up(&executor, input: NewPartner) -> FieldResult<Partner> {
+        sign_up_partner(executor.context(), input)
+    }
+
+    field partner_sign_in(&executor, input: PartnerSignIn) -> FieldResult<AuthTokens> {
+        sign_in_partner(executor.context(), input)
+    }
+
+    field approve_partner(&executor, id: String, staff_role: Option<StaffRole>) -> FieldResult<Partner> {
+        approve_partner(executor.context(), &id, staff_role.unwrap_or(StaffRole::Waiter))
+    }
+
+    field deactivate_partner(&executor, id: String) -> FieldResult<Partner> {
+        deactivate_partner(executor.context(), &id)
+    }
+
+    field reactivate_partner(&executor, id: String) -> FieldResult<Partner> {
+        reactivate_partner(executor.context(), &id)
+    }
+
+    field set_partner_staff_role(&executor, id: String, staff_role: StaffRole) -> FieldResult<Partner> {
+        set_partner_staff_role(executor.context(), &id, staff_role)
+    }
+
+    field create_customer_order(&executor, input: NewCustomerOrder) -> FieldResult<CustomerOrder> {
+        seat_customer(executor.context(), &input.table_token)
+    }
+
+    field join_customer_order(&executor, invite_code: String) -> FieldResult<CustomerOrder> {
+        join_customer_order(executor.context(), &invite_code)
+    }
+
+    field close_table_session(&executor, id: String) -> FieldResult<TableSession> {
+        close_table_session(executor.context(), &id)
+    }
+
+    field transfer_table_session(&executor, id: String, dining_table_id: String) -> FieldResult<TableSession> {
+        transfer_table_session(executor.context(), &id, &dining_table_id)
+    }
+
+    field merge_table_sessions(&executor, id: String, into_id: String) -> FieldResult<TableSession> {
+        merge_table_sessions(executor.context(), &id, &into_id)
+    }
+
+    field split_table_session(&executor, id: String, dining_table_id: String, customer_ids: Vec<String>) -> FieldResult<TableSession> {
+        split_table_session(executor.context(), &id, &dining_table_id, &customer_ids)
+    }
+
+    field close_customer_order(&executor, id: String) -> FieldResult<CustomerOrder> {
+        transition_customer_order(executor.context(), &id, CustomerOrderStatus::Closed, Permission::ManageOrders)
+    }
+
+    field settle_customer_order(&executor, id: String) -> FieldResult<CustomerOrder> {
+        let context = executor.context();
+        // payments settle orders on their own; this is for ones with nothing left to pay, such as fully comped orders
+        context.authorize(Roles::Partner)?;
+        transition_customer_order(context, &id, CustomerOrderStatus::Done, Permission::SettleOrders)
+    }
 
-        Ok(CustomerOrder {
-            id: customer_order_uuid.hyphenated().to_string(),
-            restaurant_id: restaurant_uuid.hyphenated().to_string(),
-            dining_table_id: dining_table_uuid.hyphenated().to_string(),
-            customer_id: customer_uuid.hyphenated().to_string(),
-            status: status,
-        })
+    field reopen_customer_order(&executor, id: String) -> FieldResult<CustomerOrder> {
+        transition_customer_order(executor.context(), &id, CustomerOrderStatus::Open, Permission::ManageOrders)
+    }
+
+    field set_customer_order_discount(&executor, id: String, discount: i32) -> FieldResult<CustomerOrder> {
+        let context = executor.context();
+        context.authorize(Roles::Partner)?;
+        if discount < 0 {
+            return Err(FieldError::new("Discount cannot be negative", graphql_value!({"external_error": "Discount cannot be negative"})));
+        }
+        let customer_order_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let trans = conn.transaction()?;
+        let customer_order = CustomerOrder::find_for_update(&trans, &customer_order_uuid)?;
+        context.authorize_customer_order(&customer_order, Permission::ApplyDiscounts)?;
+        if customer_order.status == CustomerOrderStatus::Done {
+            return Err(FieldError::new("Order is already settled", graphql_value!({"external_error": "Order is already settled"})));
+        }
+        if !BillCheck::for_customer_order(&trans, &customer_order_uuid)?.is_empty() {
+            return Err(FieldError::new("Remove the bill split before changing the discount", graphql_value!({"external_error": "Remove the bill split before changing the discount"})));
+        }
+        trans.execute("
+            UPDATE customer_order
+            SET discount = $2, updated_at = now()
+            WHERE id = $1
+        ", &[&customer_order_uuid, &discount])?;
+        trans.commit()?;
+        Ok(customer_order)
+    }
+
+    field split_customer_order_by_items(&executor, customer_order_id: String, checks: Vec<ItemCheck>) -> FieldResult<Vec<BillCheck>> {
+        split_by_items(executor.context(), &customer_order_id, checks)
+    }
+
+    field split_customer_order_evenly(&executor, customer_order_id: String, count: i32) -> FieldResult<Vec<BillCheck>> {
+        split_evenly_into(executor.context(), &customer_order_id, count)
+    }
+
+    field split_customer_order_by_amounts(&executor, customer_order_id: String, checks: Vec<AmountCheck>) -> FieldResult<Vec<BillCheck>> {
+        split_by_amounts(executor.context(), &customer_order_id, checks)
+    }
+
+    field remove_customer_order_split(&executor, customer_order_id: String) -> FieldResult<CustomerOrder> {
+        remove_split(executor.context(), &customer_order_id)
+    }
+
+    field advance_dish_order(&executor, id: String) -> FieldResult<DishOrder> {
+        update_dish_order_status(executor.context(), &id, None)
+    }
+
+    field void_dish_order(&executor, id: String) -> FieldResult<DishOrder> {
+        update_dish_order_status(executor.context(), &id, Some(DishOrderStatus::Voided))
+    }
+
+    field create_payment(&executor, input: NewPayment) -> FieldResult<Payment> {
+        create_payment(executor.context(), input)
     }
 
     field create_dish_order(&executor, input: NewDishOrder) -> FieldResult<DishOrder> {
         let context = executor.context();
         context.authorize(Roles::Customer)?;
+        let customer_uuid = Uuid::parse_str(context.get_client_id()?)?;
         let customer_order_uuid = Uuid::parse_str(&input.customer_order_id)?;
         let dish_uuid = Uuid::parse_str(&input.dish_id)?;
+        if input.quantity < 1 {
+            return Err(FieldError::new("Quantity must be at least 1", graphql_value!({"external_error": "Quantity must be at least 1"})));
+        }
 
         // validate order by checking restaurant and dish existence
         let conn = context.pool.get()?;
-        let customer_order_rows = conn.query("
-            SELECT restaurant_id 
-            FROM customer_order
-            WHERE id = $1
-        ", &[&customer_order_uuid])?;
-        if customer_order_rows.is_empty() {
-            return Err(FieldError::new("Order is not valid", graphql_value!({"external_error": "Order is not valid"})));
+        let trans = conn.transaction()?;
+        let customer_order = CustomerOrder::find_for_update(&trans, &customer_order_uuid)?;
+        context.authorize_customer_order(&customer_order, Permission::ManageOrders)?;
+        if customer_order.status != CustomerOrderStatus::Open {
+            return Err(CustomerOrderError::NotOpen.into());
         }
-        let customer_order_row = customer_order_rows.get(0);
-        let restaurant_uuid: Uuid = customer_order_row.get("restaurant_id");
+        let restaurant_uuid = Uuid::parse_str(&customer_order.restaurant_id)?;
 
-        let restaurant_dish_rows = conn.query("
+        let restaurant_dish_rows = trans.query("
             SELECT *
             FROM dish
-            WHERE id = $1 AND restaurant_id = $2
+            WHERE id = $1 AND restaurant_id = $2 AND archived_at IS NULL
+            FOR UPDATE
         ", &[&dish_uuid, &restaurant_uuid])?;
         if restaurant_dish_rows.is_empty() {
             return Err(FieldError::new("Dish does not exist", graphql_value!({"external_error": "Dish does not exist"})));
         }
 
+        let dish_row = restaurant_dish_rows.get(0);
+        let available: bool = dish_row.get("available");
+        let stock: Option<i32> = dish_row.get("stock");
+        match stock {
+            _ if !available => {
+                return Err(FieldError::new("Dish is not available", graphql_value!({"external_error": "Dish is not available", "code": "DISH_UNAVAILABLE"})));
+            }
+            Some(0) => {
+                return Err(FieldError::new("Dish is sold out", graphql_value!({"external_error": "Dish is sold out", "code": "DISH_SOLD_OUT"})));
+            }
+            Some(stock) if stock < input.quantity => {
+                let message = format!("Only {} left of this dish", stock);
+                return Err(FieldError::new(message.as_str(), graphql_value!({"external_error": "Not enough stock", "code": "DISH_SOLD_OUT"})));
+            }
+            _ => {}
+        }
+        trans.execute("
+            UPDATE dish
+            SET stock = stock - $2
+            WHERE id = $1
+        ", &[&dish_uuid, &input.quantity])?;
+        let price: i32 = dish_row.get("price");
+        let modifier_option_ids = input.modifier_option_ids.unwrap_or_default();
+        let selection = select_modifiers(&trans, &dish_uuid, &modifier_option_ids)?;
+        let unit_price = price + selection.price_delta;
+        if unit_price < 0 {
+            return Err(FieldError::new("Item price cannot be negative", graphql_value!({"external_error": "Item price cannot be negative"})));
+        }
         let dish_order_uuid = Uuid::new_v4();
 
-        let inserts = conn.query("
+        // the current menu price is snapshotted so later price edits don't change placed bills
+        let rows = trans.query("
             INSERT INTO dish_order (
                 id,
                 quantity,
                 note,
                 dish_id,
-                customer_order_id
-            ) VALUES ($1, $2, $3, $4, $5)
-        ", &[&dish_order_uuid, &input.quantity, &input.note, &dish_uuid, &customer_order_uuid])?;
-
-        Ok(DishOrder {
-            id: dish_order_uuid.hyphenated().to_string(),
-            quantity: input.quantity,
-            note: input.note,
-            dish_id: dish_uuid.hyphenated().to_string(),
-            customer_order_id: customer_order_uuid.hyphenated().to_string(),
-        })
+                customer_order_id,
+                unit_price,
+                customer_id
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+        ", &[&dish_order_uuid, &input.quantity, &input.note, &dish_uuid, &customer_order_uuid, &unit_price, &customer_uuid])?;
+        insert_dish_order_modifiers(&trans, &dish_order_uuid, &selection)?;
+        trans.commit()?;
+
+        let dish_order = DishOrder::from_row(&rows.get(0));
+        publish_order_event(context, &OrderEvent::for_dish_order(OrderEventKind::DishOrderCreated, &restaurant_uuid, &dish_order));
+        Ok(dish_order)
     }
 });
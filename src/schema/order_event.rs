@@ -0,0 +1,139 @@
+use juniper::{FieldError, FieldResult};
+use r2d2_redis::redis::Commands;
+use uuid::Uuid;
+
+use super::context::Context;
+use super::customer_order::{CustomerOrder, CustomerOrderStatus};
+use super::dish_order::{DishOrder, DishOrderStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum)]
+pub enum OrderEventKind {
+    CustomerOrderCreated,
+    CustomerOrderStatusChanged,
+    DishOrderCreated,
+    DishOrderStatusChanged,
+}
+
+/// Change notification fanned out over Redis to subscribed partner tablets
+/// and customer phones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub kind: OrderEventKind,
+    pub restaurant_id: String,
+    pub customer_order_id: String,
+    pub customer_order_status: Option<CustomerOrderStatus>,
+    pub dish_order_id: Option<String>,
+    pub dish_order_status: Option<DishOrderStatus>,
+}
+
+impl OrderEvent {
+    pub fn for_customer_order(kind: OrderEventKind, customer_order: &CustomerOrder) -> OrderEvent {
+        OrderEvent {
+            kind,
+            restaurant_id: customer_order.restaurant_id.clone(),
+            customer_order_id: customer_order.id.clone(),
+            customer_order_status: Some(customer_order.status),
+            dish_order_id: None,
+            dish_order_status: None,
+        }
+    }
+
+    pub fn for_dish_order(
+        kind: OrderEventKind,
+        restaurant_id: &Uuid,
+        dish_order: &DishOrder,
+    ) -> OrderEvent {
+        OrderEvent {
+            kind,
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            customer_order_id: dish_order.customer_order_id.clone(),
+            customer_order_status: None,
+            dish_order_id: Some(dish_order.id.clone()),
+            dish_order_status: Some(dish_order.status),
+        }
+    }
+
+    pub fn channels(&self) -> Vec<String> {
+        vec![
+            restaurant_channel(&self.restaurant_id),
+            customer_order_channel(&self.customer_order_id),
+        ]
+    }
+}
+
+pub fn restaurant_channel(restaurant_id: &str) -> String {
+    format!("events:restaurant:{}", restaurant_id)
+}
+
+pub fn customer_order_channel(customer_order_id: &str) -> String {
+    format!("events:customer_order:{}", customer_order_id)
+}
+
+/// Publishing happens after the change is committed, so a Redis hiccup is
+/// logged rather than failing a mutation that already succeeded.
+pub fn publish_order_event(context: &Context, event: &OrderEvent) {
+    let result = context
+        .redis_pool
+        .get()
+        .map_err(|e| e.to_string())
+        .and_then(|redis| {
+            let payload = serde_json::to_string(event).map_err(|e| e.to_string())?;
+            for channel in event.channels() {
+                let _: i64 = redis
+                    .publish(channel, payload.as_str())
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        });
+    if let Err(e) = result {
+        error!("Failed to publish {:?}: {}", event.kind, e);
+    }
+}
+
+graphql_object!(OrderEvent: Context | &self | {
+  field kind() -> &OrderEventKind {
+    &self.kind
+  }
+  field restaurant_id() -> &str {
+    self.restaurant_id.as_str()
+  }
+  field customer_order_id() -> &str {
+    self.customer_order_id.as_str()
+  }
+  field customer_order_status() -> &Option<CustomerOrderStatus> {
+    &self.customer_order_status
+  }
+  field dish_order_id() -> &Option<String> {
+    &self.dish_order_id
+  }
+  field dish_order_status() -> &Option<DishOrderStatus> {
+    &self.dish_order_status
+  }
+  field customer_order(&executor) -> FieldResult<CustomerOrder> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.customer_order_id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM customer_order
+      WHERE id = $1
+    ", &[&customer_order_uuid])?;
+    if rows.is_empty() {
+      return Err(FieldError::new("Order does not exist", graphql_value!({ "internal_error": "Order does not exist" })));
+    }
+    Ok(CustomerOrder::from_row(&rows.get(0)))
+  }
+  field dish_order(&executor) -> FieldResult<Option<DishOrder>> {
+    let dish_order_id = match self.dish_order_id {
+      Some(ref id) => id,
+      None => return Ok(None),
+    };
+    let conn = executor.context().pool.get()?;
+    let dish_order_uuid = Uuid::parse_str(dish_order_id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM dish_order
+      WHERE id = $1
+    ", &[&dish_order_uuid])?;
+    Ok(rows.iter().next().map(|row| DishOrder::from_row(&row)))
+  }
+});
@@ -1,3 +1,19 @@
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use uuid::Uuid;
+
+use super::context::{Context, Roles};
+use super::permission::{Permission, StaffRole};
+use super::session::{revoke_client_sessions, AuthTokens};
+use crate::password::{self, Verification};
+
+/// Failed sign-ins in a row before the account is locked.
+const MAX_FAILED_SIGN_INS: i32 = 5;
+const LOCKOUT_MINUTES: i32 = 15;
+const MIN_PASSWORD_LENGTH: usize = 8;
+
 #[derive(GraphQLObject)]
 pub struct Partner {
     pub id: String,
@@ -8,6 +24,45 @@ pub struct Partner {
     pub phone: String,
     pub email: String,
     pub is_active: bool,
+    pub staff_role: StaffRole,
+    pub approved_at: Option<NaiveDateTime>,
+    pub deactivated_at: Option<NaiveDateTime>,
+}
+
+impl Partner {
+    pub fn from_row(row: &Row) -> Partner {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        Partner {
+            id: id.hyphenated().to_string(),
+            name: row.get("name"),
+            username: row.get("username"),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            picture: row.get("picture"),
+            phone: row.get("phone"),
+            email: row.get("email"),
+            is_active: row.get("is_active"),
+            staff_role: row.get("staff_role"),
+            approved_at: row.get("approved_at"),
+            deactivated_at: row.get("deactivated_at"),
+        }
+    }
+
+    pub fn find_for_update(conn: &dyn GenericConnection, id: &Uuid) -> FieldResult<Partner> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM partner
+            WHERE id = $1
+            FOR UPDATE
+        ",
+            &[id],
+        )?;
+        if rows.is_empty() {
+            return Err(partner_error("Partner does not exist"));
+        }
+        Ok(Partner::from_row(&rows.get(0)))
+    }
 }
 
 #[derive(GraphQLInputObject)]
@@ -26,3 +81,291 @@ pub struct PartnerSignIn {
     pub username: String,
     pub password: String,
 }
+
+fn partner_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+fn unauthenticated() -> FieldError {
+    FieldError::new(
+        "Unauthenticated",
+        graphql_value!({ "internal_error": "Unauthenticated" }),
+    )
+}
+
+pub fn sign_up_partner(context: &Context, input: NewPartner) -> FieldResult<Partner> {
+    if input.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(FieldError::new(
+            "Password must be at least 8 characters",
+            graphql_value!({ "external_error": "Password must be at least 8 characters" }),
+        ));
+    }
+    let restaurant_uuid = Uuid::parse_str(&input.restaurant_id)?;
+    let hashed_password = password::hash(&input.password)?;
+    let conn = context.pool.get()?;
+    let restaurant_rows = conn.query(
+        "
+        SELECT 1
+        FROM restaurant
+        WHERE id = $1 AND archived_at IS NULL
+    ",
+        &[&restaurant_uuid],
+    )?;
+    if restaurant_rows.is_empty() {
+        return Err(partner_error("Restaurant does not exist"));
+    }
+    let existing = conn.query(
+        "
+        SELECT 1
+        FROM partner
+        WHERE username = $1
+    ",
+        &[&input.username],
+    )?;
+    if !existing.is_empty() {
+        return Err(FieldError::new(
+            "Username is taken",
+            graphql_value!({ "external_error": "Username is taken" }),
+        ));
+    }
+    let rows = conn.query(
+        "
+        INSERT INTO partner (
+            id,
+            name,
+            username,
+            hashed_password,
+            email,
+            phone,
+            picture,
+            restaurant_id
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING *
+    ",
+        &[
+            &Uuid::new_v4(),
+            &input.name,
+            &input.username,
+            &hashed_password,
+            &input.email,
+            &input.phone,
+            &input.picture,
+            &restaurant_uuid,
+        ],
+    )?;
+    Ok(Partner::from_row(&rows.get(0)))
+}
+
+/// Verifies the password, upgrading a legacy hash in place on success. Repeated
+/// failures lock the account for a while, even against the right password.
+/// Only approved, active partners get a token. The slow hash check runs
+/// without holding the row; each write is a single statement, so concurrent
+/// failures still count one by one.
+pub fn sign_in_partner(context: &Context, input: PartnerSignIn) -> FieldResult<AuthTokens> {
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT
+            id,
+            hashed_password,
+            is_active,
+            approved_at IS NOT NULL AS approved,
+            failed_sign_in_attempts,
+            COALESCE(locked_until > now(), false) AS locked
+        FROM partner
+        WHERE username = $1
+    ",
+        &[&input.username],
+    )?;
+    if rows.is_empty() {
+        password::verify_dummy(&input.password);
+        return Err(unauthenticated());
+    }
+    let row = rows.get(0);
+    let partner_uuid: Uuid = row.get("id");
+    let locked: bool = row.get("locked");
+    if locked {
+        return Err(FieldError::new(
+            "Too many failed sign-ins, try again later",
+            graphql_value!({ "external_error": "Too many failed sign-ins, try again later", "code": "ACCOUNT_LOCKED" }),
+        ));
+    }
+    let hashed_password: String = row.get("hashed_password");
+    match password::verify(&input.password, &hashed_password) {
+        Verification::Invalid => {
+            conn.execute(
+                "
+                UPDATE partner
+                SET failed_sign_in_attempts = CASE
+                        WHEN failed_sign_in_attempts + 1 >= $2 THEN 0
+                        ELSE failed_sign_in_attempts + 1
+                    END,
+                    locked_until = CASE
+                        WHEN failed_sign_in_attempts + 1 >= $2 THEN now() + make_interval(mins => $3)
+                        ELSE locked_until
+                    END
+                WHERE id = $1
+            ",
+                &[&partner_uuid, &MAX_FAILED_SIGN_INS, &LOCKOUT_MINUTES],
+            )?;
+            return Err(unauthenticated());
+        }
+        Verification::ValidLegacy => {
+            conn.execute(
+                "
+                UPDATE partner
+                SET hashed_password = $2, failed_sign_in_attempts = 0, locked_until = NULL
+                WHERE id = $1
+            ",
+                &[&partner_uuid, &password::hash(&input.password)?],
+            )?;
+        }
+        Verification::Valid => {
+            let failed_sign_in_attempts: i32 = row.get("failed_sign_in_attempts");
+            if failed_sign_in_attempts > 0 {
+                conn.execute(
+                    "
+                    UPDATE partner
+                    SET failed_sign_in_attempts = 0, locked_until = NULL
+                    WHERE id = $1
+                ",
+                    &[&partner_uuid],
+                )?;
+            }
+        }
+    }
+    let is_active: bool = row.get("is_active");
+    let approved: bool = row.get("approved");
+    if !approved {
+        return Err(FieldError::new(
+            "Partner account is awaiting approval",
+            graphql_value!({ "external_error": "Partner account is awaiting approval", "code": "PARTNER_PENDING" }),
+        ));
+    }
+    if !is_active {
+        return Err(FieldError::new(
+            "Partner account is deactivated",
+            graphql_value!({ "external_error": "Partner account is deactivated", "code": "PARTNER_DEACTIVATED" }),
+        ));
+    }
+    context.create_partner_token(&partner_uuid.hyphenated().to_string())
+}
+
+/// Approves a pending sign-up as `staff_role`. Only admins can make the partner an owner.
+pub fn approve_partner(context: &Context, id: &str, staff_role: StaffRole) -> FieldResult<Partner> {
+    let partner_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let partner = Partner::find_for_update(&trans, &partner_uuid)?;
+    context.authorize_restaurant(&partner.restaurant_id, Permission::ManageStaff)?;
+    if staff_role == StaffRole::Owner && context.get_role()? != Roles::Admin {
+        return Err(partner_error("Only admins can appoint owners"));
+    }
+    if partner.approved_at.is_some() {
+        return Err(partner_error("Partner is already approved"));
+    }
+    let approved_by = Uuid::parse_str(context.get_client_id()?)?;
+    let rows = trans.query(
+        "
+        UPDATE partner
+        SET is_active = true, staff_role = $2, approved_at = now(), approved_by = $3
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[&partner_uuid, &staff_role, &approved_by],
+    )?;
+    trans.commit()?;
+    Ok(Partner::from_row(&rows.get(0)))
+}
+
+/// Owners can't be deactivated by other owners, and nobody can deactivate
+/// themselves. The partner is signed out everywhere.
+pub fn deactivate_partner(context: &Context, id: &str) -> FieldResult<Partner> {
+    let partner_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let partner = Partner::find_for_update(&trans, &partner_uuid)?;
+    context.authorize_restaurant(&partner.restaurant_id, Permission::ManageStaff)?;
+    if context.get_client_id()? == &partner.id {
+        return Err(partner_error("You cannot deactivate your own account"));
+    }
+    if partner.staff_role == StaffRole::Owner && context.get_role()? != Roles::Admin {
+        return Err(partner_error("Only admins can deactivate owners"));
+    }
+    if !partner.is_active {
+        return Err(partner_error("Partner is not active"));
+    }
+    let rows = trans.query(
+        "
+        UPDATE partner
+        SET is_active = false, deactivated_at = now()
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[&partner_uuid],
+    )?;
+    trans.commit()?;
+    revoke_client_sessions(&context.redis_pool, &partner.id)?;
+    Ok(Partner::from_row(&rows.get(0)))
+}
+
+pub fn reactivate_partner(context: &Context, id: &str) -> FieldResult<Partner> {
+    let partner_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let partner = Partner::find_for_update(&trans, &partner_uuid)?;
+    context.authorize_restaurant(&partner.restaurant_id, Permission::ManageStaff)?;
+    if partner.staff_role == StaffRole::Owner && context.get_role()? != Roles::Admin {
+        return Err(partner_error("Only admins can reactivate owners"));
+    }
+    if partner.approved_at.is_none() {
+        return Err(partner_error("Partner has not been approved yet"));
+    }
+    if partner.is_active {
+        return Err(partner_error("Partner is already active"));
+    }
+    let rows = trans.query(
+        "
+        UPDATE partner
+        SET is_active = true, deactivated_at = NULL
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[&partner_uuid],
+    )?;
+    trans.commit()?;
+    Ok(Partner::from_row(&rows.get(0)))
+}
+
+/// Owners are appointed and stood down by admins only, and nobody can change
+/// their own role.
+pub fn set_partner_staff_role(
+    context: &Context,
+    id: &str,
+    staff_role: StaffRole,
+) -> FieldResult<Partner> {
+    let partner_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let partner = Partner::find_for_update(&trans, &partner_uuid)?;
+    context.authorize_restaurant(&partner.restaurant_id, Permission::ManageStaff)?;
+    if context.get_client_id()? == &partner.id {
+        return Err(partner_error("You cannot change your own role"));
+    }
+    if (partner.staff_role == StaffRole::Owner || staff_role == StaffRole::Owner)
+        && context.get_role()? != Roles::Admin
+    {
+        return Err(partner_error("Only admins can appoint or remove owners"));
+    }
+    let rows = trans.query(
+        "
+        UPDATE partner
+        SET staff_role = $2
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[&partner_uuid, &staff_role],
+    )?;
+    trans.commit()?;
+    Ok(Partner::from_row(&rows.get(0)))
+}
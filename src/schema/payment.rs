@@ -0,0 +1,337 @@
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use uuid::Uuid;
+
+use super::bill::Bill;
+use super::bill_check::{check_amount_paid, BillCheck, BillCheckStatus};
+use super::context::{Context, Roles};
+use super::customer_order::{CustomerOrder, CustomerOrderStatus};
+use super::order_event::{publish_order_event, OrderEvent, OrderEventKind};
+use super::permission::Permission;
+use crate::payment::{ChargeOutcome, ChargeRequest};
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "payment_tender")]
+pub enum PaymentTender {
+    Cash,
+    Card,
+    QrEwallet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "payment_status")]
+pub enum PaymentStatus {
+    /// Recorded, with the provider's answer still outstanding.
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+#[derive(GraphQLObject)]
+pub struct Payment {
+    pub id: String,
+    pub customer_order_id: String,
+    pub bill_check_id: Option<String>,
+    pub tender: PaymentTender,
+    pub status: PaymentStatus,
+    pub amount: i32,
+    pub tendered: i32,
+    pub change: i32,
+    pub provider: String,
+    pub provider_reference: Option<String>,
+    pub failure_reason: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl Payment {
+    pub fn from_row(row: &Row) -> Payment {
+        let id: Uuid = row.get("id");
+        let customer_order_id: Uuid = row.get("customer_order_id");
+        let bill_check_id: Option<Uuid> = row.get("bill_check_id");
+        Payment {
+            id: id.hyphenated().to_string(),
+            customer_order_id: customer_order_id.hyphenated().to_string(),
+            bill_check_id: bill_check_id.map(|id| id.hyphenated().to_string()),
+            tender: row.get("tender"),
+            status: row.get("status"),
+            amount: row.get("amount"),
+            tendered: row.get("tendered"),
+            change: row.get("change"),
+            provider: row.get("provider"),
+            provider_reference: row.get("provider_reference"),
+            failure_reason: row.get("failure_reason"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewPayment {
+    pub customer_order_id: String,
+    /// Check to pay when the order has been split.
+    pub bill_check_id: Option<String>,
+    pub tender: PaymentTender,
+    /// Amount applied to the bill. Less than the amount due makes a partial payment.
+    pub amount: i32,
+    /// Cash handed over by the payer, used to work out the change. Defaults to `amount`.
+    pub tendered: Option<i32>,
+    /// Card token or scanned QR payload for non-cash tenders.
+    pub token: Option<String>,
+}
+
+pub fn amount_paid(conn: &dyn GenericConnection, customer_order_id: &Uuid) -> FieldResult<i32> {
+    let rows = conn.query(
+        "
+        SELECT COALESCE(SUM(amount), 0)::int AS amount_paid
+        FROM payment
+        WHERE customer_order_id = $1 AND status = $2
+    ",
+        &[customer_order_id, &PaymentStatus::Succeeded],
+    )?;
+    Ok(rows.get(0).get("amount_paid"))
+}
+
+fn payment_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+/// Sum of the payments still waiting on their provider, for the whole order
+/// or for one of its checks.
+fn amount_pending(
+    conn: &dyn GenericConnection,
+    customer_order_id: &Uuid,
+    bill_check_id: Option<&Uuid>,
+) -> FieldResult<i32> {
+    let rows = conn.query(
+        "
+        SELECT COALESCE(SUM(amount), 0)::int AS amount_pending
+        FROM payment
+        WHERE customer_order_id = $1
+            AND ($2::uuid IS NULL OR bill_check_id = $2)
+            AND status = $3
+    ",
+        &[customer_order_id, &bill_check_id, &PaymentStatus::Pending],
+    )?;
+    Ok(rows.get(0).get("amount_pending"))
+}
+
+/// Marks the paid check, and the order once nothing is left to pay, after a
+/// successful payment. Returns the order if it was settled.
+fn settle_paid_order(
+    conn: &dyn GenericConnection,
+    customer_order_uuid: &Uuid,
+    bill_check_uuid: Option<&Uuid>,
+    actor_uuid: &Uuid,
+    actor_role: Roles,
+) -> FieldResult<Option<CustomerOrder>> {
+    let trans = conn.transaction()?;
+    let customer_order = CustomerOrder::find_for_update(&trans, customer_order_uuid)?;
+    if let Some(bill_check_uuid) = bill_check_uuid {
+        let bill_check = BillCheck::find_for_update(&trans, bill_check_uuid)?;
+        if check_amount_paid(&trans, bill_check_uuid)? >= bill_check.amount {
+            trans.execute(
+                "
+                UPDATE bill_check
+                SET status = $2
+                WHERE id = $1
+            ",
+                &[bill_check_uuid, &BillCheckStatus::Paid],
+            )?;
+        }
+    }
+    // checks add up to the order total, so the order is done once every check is paid
+    let bill = Bill::for_customer_order(&trans, customer_order_uuid)?;
+    let settled = if amount_paid(&trans, customer_order_uuid)? >= bill.total {
+        Some(customer_order.transition(
+            &trans,
+            CustomerOrderStatus::Done,
+            actor_uuid,
+            actor_role,
+        )?)
+    } else {
+        None
+    };
+    trans.commit()?;
+    Ok(settled)
+}
+
+/// Charges part or all of a closed order's outstanding amount and settles the
+/// order once nothing is left to pay. A split order is paid check by check.
+/// Cash can only be taken by restaurant staff.
+///
+/// The payment is recorded as pending and committed before the provider is
+/// called, so no row lock is held across the network; amounts already pending
+/// count as taken, which keeps two payers from charging the same amount. The
+/// provider's answer is committed before settling is tried, so a failure to
+/// settle is reported without losing the charge.
+pub fn create_payment(context: &Context, input: NewPayment) -> FieldResult<Payment> {
+    let actor_uuid = Uuid::parse_str(context.get_client_id()?)?;
+    let actor_role = context.get_role()?;
+    if input.tender == PaymentTender::Cash && actor_role != Roles::Partner {
+        return Err(payment_error(
+            "Cash payments must be taken by restaurant staff",
+        ));
+    }
+    let tendered = input.tendered.unwrap_or(input.amount);
+    if input.amount <= 0 {
+        return Err(payment_error("Payment amount must be positive"));
+    }
+    if tendered < input.amount {
+        return Err(payment_error(
+            "Tendered amount is less than the payment amount",
+        ));
+    }
+    if input.tender != PaymentTender::Cash && tendered != input.amount {
+        return Err(payment_error("Only cash payments can give change"));
+    }
+
+    let customer_order_uuid = Uuid::parse_str(&input.customer_order_id)?;
+    let payment_uuid = Uuid::new_v4();
+    let provider = context.payment_providers.for_tender(input.tender);
+    let conn = context.pool.get()?;
+    let bill_check_uuid = {
+        let trans = conn.transaction()?;
+        let customer_order = CustomerOrder::find_for_update(&trans, &customer_order_uuid)?;
+        context.authorize_customer_order(&customer_order, Permission::TakePayments)?;
+        if customer_order.status != CustomerOrderStatus::Closed {
+            return Err(payment_error("Order must be closed before it can be paid"));
+        }
+        let (bill_check_uuid, amount_due) = match input.bill_check_id {
+            Some(ref id) => {
+                let bill_check_uuid = Uuid::parse_str(id)?;
+                let bill_check = BillCheck::find_for_update(&trans, &bill_check_uuid)?;
+                if bill_check.customer_order_id != customer_order.id {
+                    return Err(payment_error("Check belongs to another order"));
+                }
+                let check_amount_due = bill_check.amount
+                    - check_amount_paid(&trans, &bill_check_uuid)?
+                    - amount_pending(&trans, &customer_order_uuid, Some(&bill_check_uuid))?;
+                (Some(bill_check_uuid), check_amount_due)
+            }
+            None => {
+                if !BillCheck::for_customer_order(&trans, &customer_order_uuid)?.is_empty() {
+                    return Err(payment_error(
+                        "Order is split, pay one of its checks instead",
+                    ));
+                }
+                let bill = Bill::for_customer_order(&trans, &customer_order_uuid)?;
+                let order_amount_due = bill.total
+                    - amount_paid(&trans, &customer_order_uuid)?
+                    - amount_pending(&trans, &customer_order_uuid, None)?;
+                (None, order_amount_due)
+            }
+        };
+        if input.amount > amount_due {
+            return Err(payment_error("Payment amount exceeds the amount due"));
+        }
+        trans.execute(
+            "
+            INSERT INTO payment (
+                id,
+                customer_order_id,
+                bill_check_id,
+                tender,
+                status,
+                amount,
+                tendered,
+                provider,
+                created_by,
+                created_by_role
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        ",
+            &[
+                &payment_uuid,
+                &customer_order_uuid,
+                &bill_check_uuid,
+                &input.tender,
+                &PaymentStatus::Pending,
+                &input.amount,
+                &tendered,
+                &provider.name(),
+                &actor_uuid,
+                &actor_role,
+            ],
+        )?;
+        trans.commit()?;
+        bill_check_uuid
+    };
+
+    let outcome = provider.charge(&ChargeRequest {
+        payment_id: &payment_uuid,
+        customer_order_id: &customer_order_uuid,
+        tender: input.tender,
+        amount: input.amount,
+        token: input.token.as_deref(),
+    });
+    // a provider error still finalizes the payment, as failed, before it is reported
+    let (status, provider_reference, failure_reason, provider_error) = match outcome {
+        Ok(ChargeOutcome::Approved { reference }) => {
+            (PaymentStatus::Succeeded, reference, None, None)
+        }
+        Ok(ChargeOutcome::Declined { reason }) => (PaymentStatus::Failed, None, Some(reason), None),
+        Err(e) => (PaymentStatus::Failed, None, Some(e.to_string()), Some(e)),
+    };
+    let change = if status == PaymentStatus::Succeeded {
+        tendered - input.amount
+    } else {
+        0
+    };
+
+    // the provider's answer is saved on its own, so nothing later can undo a charge
+    let rows = conn.query(
+        "
+        UPDATE payment
+        SET status = $2,
+            change = $3,
+            provider_reference = $4,
+            failure_reason = $5
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[
+            &payment_uuid,
+            &status,
+            &change,
+            &provider_reference,
+            &failure_reason,
+        ],
+    )?;
+    if let Some(e) = provider_error {
+        return Err(e.into());
+    }
+    let payment = Payment::from_row(&rows.get(0));
+    if status == PaymentStatus::Succeeded {
+        let settled = settle_paid_order(
+            &*conn,
+            &customer_order_uuid,
+            bill_check_uuid.as_ref(),
+            &actor_uuid,
+            actor_role,
+        );
+        match settled {
+            Ok(Some(customer_order)) => publish_order_event(
+                context,
+                &OrderEvent::for_customer_order(
+                    OrderEventKind::CustomerOrderStatusChanged,
+                    &customer_order,
+                ),
+            ),
+            Ok(None) => {}
+            Err(e) => {
+                error!("Payment {} taken but not settled: {:?}", payment.id, e);
+                let payment_id = payment.id.as_str();
+                return Err(FieldError::new(
+                    "Payment was taken but the order could not be settled",
+                    graphql_value!({
+                        "external_error": "Payment was taken but the order could not be settled",
+                        "code": "PAYMENT_NOT_SETTLED",
+                        "payment_id": payment_id
+                    }),
+                ));
+            }
+        }
+    }
+    Ok(Payment::from_row(&rows.get(0)))
+}
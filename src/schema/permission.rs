@@ -0,0 +1,128 @@
+/// A partner's job at their restaurant, which decides what they may do there.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "staff_role")]
+pub enum StaffRole {
+    Owner,
+    Manager,
+    Cashier,
+    Waiter,
+    Kitchen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, GraphQLEnum)]
+pub enum Permission {
+    /// Restaurant profile, charges and dining tables.
+    ManageRestaurant,
+    /// Approving, deactivating and assigning roles to partners.
+    ManageStaff,
+    /// Dishes, categories and modifiers.
+    ManageMenu,
+    /// Availability toggles and per-dish stock counts.
+    UpdateAvailability,
+    /// Ingredients, recipes, suppliers and purchase orders.
+    ManageInventory,
+    /// Stock-takes, wastage and receiving deliveries.
+    RecordStock,
+    ViewOrders,
+    /// Closing and reopening customer orders.
+    ManageOrders,
+    /// Marking an order settled without taking a payment for it.
+    SettleOrders,
+    ApplyDiscounts,
+    SplitBills,
+    TakePayments,
+    /// Moving items through the kitchen up to ready.
+    PrepareItems,
+    ServeItems,
+    VoidItems,
+}
+
+pub const ALL_PERMISSIONS: [Permission; 15] = [
+    Permission::ManageRestaurant,
+    Permission::ManageStaff,
+    Permission::ManageMenu,
+    Permission::UpdateAvailability,
+    Permission::ManageInventory,
+    Permission::RecordStock,
+    Permission::ViewOrders,
+    Permission::ManageOrders,
+    Permission::SettleOrders,
+    Permission::ApplyDiscounts,
+    Permission::SplitBills,
+    Permission::TakePayments,
+    Permission::PrepareItems,
+    Permission::ServeItems,
+    Permission::VoidItems,
+];
+
+/// What a customer may do to an order they placed or joined.
+pub const CUSTOMER_PERMISSIONS: [Permission; 4] = [
+    Permission::ViewOrders,
+    Permission::ManageOrders,
+    Permission::SplitBills,
+    Permission::TakePayments,
+];
+
+impl StaffRole {
+    pub fn permits(self, permission: Permission) -> bool {
+        use self::Permission::*;
+        match self {
+            StaffRole::Owner => true,
+            StaffRole::Manager => permission != ManageStaff,
+            StaffRole::Cashier => matches!(
+                permission,
+                ViewOrders | ManageOrders | SettleOrders | SplitBills | TakePayments
+            ),
+            StaffRole::Waiter => matches!(
+                permission,
+                ViewOrders | ManageOrders | SplitBills | ServeItems
+            ),
+            StaffRole::Kitchen => matches!(
+                permission,
+                ViewOrders | UpdateAvailability | RecordStock | PrepareItems
+            ),
+        }
+    }
+
+    pub fn permissions(self) -> Vec<Permission> {
+        ALL_PERMISSIONS
+            .iter()
+            .cloned()
+            .filter(|permission| self.permits(*permission))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owner_has_every_permission() {
+        assert_eq!(StaffRole::Owner.permissions(), ALL_PERMISSIONS.to_vec());
+    }
+
+    #[test]
+    fn only_owners_manage_staff() {
+        assert!(StaffRole::Owner.permits(Permission::ManageStaff));
+        assert!(!StaffRole::Manager.permits(Permission::ManageStaff));
+        assert!(StaffRole::Manager.permits(Permission::ManageRestaurant));
+    }
+
+    #[test]
+    fn waiters_cannot_settle_or_take_payments() {
+        assert!(StaffRole::Waiter.permits(Permission::ServeItems));
+        assert!(!StaffRole::Waiter.permits(Permission::SettleOrders));
+        assert!(!StaffRole::Waiter.permits(Permission::TakePayments));
+        assert!(StaffRole::Cashier.permits(Permission::SettleOrders));
+        assert!(StaffRole::Cashier.permits(Permission::TakePayments));
+    }
+
+    #[test]
+    fn kitchen_prepares_but_does_not_serve_or_void() {
+        assert!(StaffRole::Kitchen.permits(Permission::PrepareItems));
+        assert!(!StaffRole::Kitchen.permits(Permission::ServeItems));
+        assert!(!StaffRole::Kitchen.permits(Permission::VoidItems));
+        assert!(!StaffRole::Kitchen.permits(Permission::ManageOrders));
+    }
+}
@@ -0,0 +1,604 @@
+use chrono::NaiveDateTime;
+use juniper::FieldResult;
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::context::Context;
+use super::ingredient::{inventory_error, partner_scope, receive_ingredient, Ingredient};
+use super::permission::Permission;
+
+#[derive(GraphQLObject)]
+pub struct Supplier {
+    pub id: String,
+    pub restaurant_id: String,
+    pub name: String,
+    pub contact_name: Option<String>,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+}
+
+impl Supplier {
+    pub fn from_row(row: &Row) -> Supplier {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        Supplier {
+            id: id.hyphenated().to_string(),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            name: row.get("name"),
+            contact_name: row.get("contact_name"),
+            phone: row.get("phone"),
+            email: row.get("email"),
+        }
+    }
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewSupplier {
+    pub name: String,
+    pub contact_name: Option<String>,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct SupplierUpdate {
+    pub name: Option<String>,
+    pub contact_name: Option<String>,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "purchase_order_status")]
+pub enum PurchaseOrderStatus {
+    Draft,
+    Ordered,
+    PartiallyReceived,
+    Received,
+    Cancelled,
+}
+
+pub struct PurchaseOrder {
+    pub id: String,
+    pub restaurant_id: String,
+    pub supplier_id: String,
+    pub status: PurchaseOrderStatus,
+    pub note: Option<String>,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+    pub ordered_at: Option<NaiveDateTime>,
+    pub received_at: Option<NaiveDateTime>,
+    pub cancelled_at: Option<NaiveDateTime>,
+}
+
+impl PurchaseOrder {
+    pub fn from_row(row: &Row) -> PurchaseOrder {
+        let id: Uuid = row.get("id");
+        let restaurant_id: Uuid = row.get("restaurant_id");
+        let supplier_id: Uuid = row.get("supplier_id");
+        let created_by: Uuid = row.get("created_by");
+        PurchaseOrder {
+            id: id.hyphenated().to_string(),
+            restaurant_id: restaurant_id.hyphenated().to_string(),
+            supplier_id: supplier_id.hyphenated().to_string(),
+            status: row.get("status"),
+            note: row.get("note"),
+            created_by: created_by.hyphenated().to_string(),
+            created_at: row.get("created_at"),
+            ordered_at: row.get("ordered_at"),
+            received_at: row.get("received_at"),
+            cancelled_at: row.get("cancelled_at"),
+        }
+    }
+
+    /// Locks a purchase order of the given restaurant.
+    pub fn find_for_update(
+        conn: &dyn GenericConnection,
+        id: &Uuid,
+        restaurant_id: &Uuid,
+    ) -> FieldResult<PurchaseOrder> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM purchase_order
+            WHERE id = $1 AND restaurant_id = $2
+            FOR UPDATE
+        ",
+            &[id, restaurant_id],
+        )?;
+        if rows.is_empty() {
+            return Err(inventory_error("Purchase order does not exist"));
+        }
+        Ok(PurchaseOrder::from_row(&rows.get(0)))
+    }
+}
+
+pub struct PurchaseOrderLine {
+    pub id: String,
+    pub purchase_order_id: String,
+    pub ingredient_id: String,
+    pub position: i32,
+    pub quantity_ordered: i32,
+    pub expected_cost: i32,
+    pub quantity_received: i32,
+    pub received_cost: i32,
+}
+
+impl PurchaseOrderLine {
+    pub fn from_row(row: &Row) -> PurchaseOrderLine {
+        let id: Uuid = row.get("id");
+        let purchase_order_id: Uuid = row.get("purchase_order_id");
+        let ingredient_id: Uuid = row.get("ingredient_id");
+        PurchaseOrderLine {
+            id: id.hyphenated().to_string(),
+            purchase_order_id: purchase_order_id.hyphenated().to_string(),
+            ingredient_id: ingredient_id.hyphenated().to_string(),
+            position: row.get("position"),
+            quantity_ordered: row.get("quantity_ordered"),
+            expected_cost: row.get("expected_cost"),
+            quantity_received: row.get("quantity_received"),
+            received_cost: row.get("received_cost"),
+        }
+    }
+
+    pub fn for_purchase_order(
+        conn: &dyn GenericConnection,
+        purchase_order_id: &Uuid,
+    ) -> FieldResult<Vec<PurchaseOrderLine>> {
+        let rows = conn.query(
+            "
+            SELECT *
+            FROM purchase_order_line
+            WHERE purchase_order_id = $1
+            ORDER BY position
+        ",
+            &[purchase_order_id],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| PurchaseOrderLine::from_row(&row))
+            .collect())
+    }
+
+    /// The expected cost of `quantity` units of this line, rounded half up.
+    fn expected_cost_of(&self, quantity: i32) -> i32 {
+        let numerator = i64::from(self.expected_cost) * i64::from(quantity);
+        let denominator = i64::from(self.quantity_ordered);
+        ((numerator * 2 + denominator) / (denominator * 2)) as i32
+    }
+}
+
+graphql_object!(PurchaseOrder: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field restaurant_id() -> &str {
+    self.restaurant_id.as_str()
+  }
+  field supplier_id() -> &str {
+    self.supplier_id.as_str()
+  }
+  field status() -> &PurchaseOrderStatus {
+    &self.status
+  }
+  field note() -> &Option<String> {
+    &self.note
+  }
+  field created_by() -> &str {
+    self.created_by.as_str()
+  }
+  field created_at() -> &NaiveDateTime {
+    &self.created_at
+  }
+  field ordered_at() -> &Option<NaiveDateTime> {
+    &self.ordered_at
+  }
+  field received_at() -> &Option<NaiveDateTime> {
+    &self.received_at
+  }
+  field cancelled_at() -> &Option<NaiveDateTime> {
+    &self.cancelled_at
+  }
+  field supplier(&executor) -> FieldResult<Supplier> {
+    let conn = executor.context().pool.get()?;
+    let supplier_uuid = Uuid::parse_str(&self.supplier_id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM supplier
+      WHERE id = $1
+    ", &[&supplier_uuid])?;
+    if rows.is_empty() {
+      return Err(inventory_error("Supplier does not exist"));
+    }
+    Ok(Supplier::from_row(&rows.get(0)))
+  }
+  field lines(&executor) -> FieldResult<Vec<PurchaseOrderLine>> {
+    let conn = executor.context().pool.get()?;
+    let purchase_order_uuid = Uuid::parse_str(&self.id)?;
+    PurchaseOrderLine::for_purchase_order(&*conn, &purchase_order_uuid)
+  }
+  field expected_total(&executor) -> FieldResult<i32> {
+    let conn = executor.context().pool.get()?;
+    let purchase_order_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT COALESCE(SUM(expected_cost), 0)::int AS total
+      FROM purchase_order_line
+      WHERE purchase_order_id = $1
+    ", &[&purchase_order_uuid])?;
+    Ok(rows.get(0).get("total"))
+  }
+  field received_total(&executor) -> FieldResult<i32> {
+    let conn = executor.context().pool.get()?;
+    let purchase_order_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT COALESCE(SUM(received_cost), 0)::int AS total
+      FROM purchase_order_line
+      WHERE purchase_order_id = $1
+    ", &[&purchase_order_uuid])?;
+    Ok(rows.get(0).get("total"))
+  }
+});
+
+graphql_object!(PurchaseOrderLine: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field purchase_order_id() -> &str {
+    self.purchase_order_id.as_str()
+  }
+  field ingredient_id() -> &str {
+    self.ingredient_id.as_str()
+  }
+  field position() -> i32 {
+    self.position
+  }
+  field quantity_ordered() -> i32 {
+    self.quantity_ordered
+  }
+  field expected_cost() -> i32 {
+    self.expected_cost
+  }
+  field quantity_received() -> i32 {
+    self.quantity_received
+  }
+  field received_cost() -> i32 {
+    self.received_cost
+  }
+  field quantity_outstanding() -> i32 {
+    self.quantity_ordered - self.quantity_received
+  }
+  field ingredient(&executor) -> FieldResult<Ingredient> {
+    let conn = executor.context().pool.get()?;
+    let ingredient_uuid = Uuid::parse_str(&self.ingredient_id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM ingredient
+      WHERE id = $1
+    ", &[&ingredient_uuid])?;
+    if rows.is_empty() {
+      return Err(inventory_error("Ingredient does not exist"));
+    }
+    Ok(Ingredient::from_row(&rows.get(0)))
+  }
+});
+
+#[derive(GraphQLInputObject)]
+pub struct NewPurchaseOrderLine {
+    pub ingredient_id: String,
+    pub quantity: i32,
+    pub expected_cost: i32,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewPurchaseOrder {
+    pub supplier_id: String,
+    pub note: Option<String>,
+    pub lines: Vec<NewPurchaseOrderLine>,
+}
+
+/// Goods arriving against one line. `cost` defaults to the expected cost of
+/// the quantity received.
+#[derive(GraphQLInputObject)]
+pub struct ReceivedLine {
+    pub purchase_order_line_id: String,
+    pub quantity: i32,
+    pub cost: Option<i32>,
+}
+
+pub fn create_supplier(context: &Context, input: NewSupplier) -> FieldResult<Supplier> {
+    let (restaurant_uuid, _) = partner_scope(context, Permission::ManageInventory)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        INSERT INTO supplier (
+            id,
+            restaurant_id,
+            name,
+            contact_name,
+            phone,
+            email
+        ) VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+    ",
+        &[
+            &Uuid::new_v4(),
+            &restaurant_uuid,
+            &input.name,
+            &input.contact_name,
+            &input.phone,
+            &input.email,
+        ],
+    )?;
+    Ok(Supplier::from_row(&rows.get(0)))
+}
+
+pub fn update_supplier(
+    context: &Context,
+    id: &str,
+    input: SupplierUpdate,
+) -> FieldResult<Supplier> {
+    let (restaurant_uuid, _) = partner_scope(context, Permission::ManageInventory)?;
+    let supplier_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        UPDATE supplier
+        SET name = COALESCE($3, name),
+            contact_name = COALESCE($4, contact_name),
+            phone = COALESCE($5, phone),
+            email = COALESCE($6, email)
+        WHERE id = $1 AND restaurant_id = $2
+        RETURNING *
+    ",
+        &[
+            &supplier_uuid,
+            &restaurant_uuid,
+            &input.name,
+            &input.contact_name,
+            &input.phone,
+            &input.email,
+        ],
+    )?;
+    if rows.is_empty() {
+        return Err(inventory_error("Supplier does not exist"));
+    }
+    Ok(Supplier::from_row(&rows.get(0)))
+}
+
+pub fn create_purchase_order(
+    context: &Context,
+    input: NewPurchaseOrder,
+) -> FieldResult<PurchaseOrder> {
+    let (restaurant_uuid, partner_uuid) = partner_scope(context, Permission::ManageInventory)?;
+    let supplier_uuid = Uuid::parse_str(&input.supplier_id)?;
+    if input.lines.is_empty() {
+        return Err(inventory_error("A purchase order needs at least one line"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let supplier_rows = trans.query(
+        "
+        SELECT 1
+        FROM supplier
+        WHERE id = $1 AND restaurant_id = $2
+    ",
+        &[&supplier_uuid, &restaurant_uuid],
+    )?;
+    if supplier_rows.is_empty() {
+        return Err(inventory_error("Supplier does not exist"));
+    }
+    let purchase_order_uuid = Uuid::new_v4();
+    let rows = trans.query(
+        "
+        INSERT INTO purchase_order (
+            id,
+            restaurant_id,
+            supplier_id,
+            note,
+            created_by
+        ) VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+    ",
+        &[
+            &purchase_order_uuid,
+            &restaurant_uuid,
+            &supplier_uuid,
+            &input.note,
+            &partner_uuid,
+        ],
+    )?;
+    for (position, line) in input.lines.iter().enumerate() {
+        if line.quantity <= 0 || line.expected_cost < 0 {
+            return Err(inventory_error(
+                "Line quantities must be positive and costs not negative",
+            ));
+        }
+        let ingredient_uuid = Uuid::parse_str(&line.ingredient_id)?;
+        Ingredient::find_for_update(&trans, &ingredient_uuid, &restaurant_uuid)?;
+        trans.execute(
+            "
+            INSERT INTO purchase_order_line (
+                id,
+                purchase_order_id,
+                ingredient_id,
+                position,
+                quantity_ordered,
+                expected_cost
+            ) VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+            &[
+                &Uuid::new_v4(),
+                &purchase_order_uuid,
+                &ingredient_uuid,
+                &(position as i32),
+                &line.quantity,
+                &line.expected_cost,
+            ],
+        )?;
+    }
+    trans.commit()?;
+    Ok(PurchaseOrder::from_row(&rows.get(0)))
+}
+
+/// Marks a draft as sent to the supplier, after which goods can be received.
+pub fn submit_purchase_order(context: &Context, id: &str) -> FieldResult<PurchaseOrder> {
+    let (restaurant_uuid, _) = partner_scope(context, Permission::ManageInventory)?;
+    let purchase_order_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let purchase_order =
+        PurchaseOrder::find_for_update(&trans, &purchase_order_uuid, &restaurant_uuid)?;
+    if purchase_order.status != PurchaseOrderStatus::Draft {
+        return Err(inventory_error(
+            "Only draft purchase orders can be submitted",
+        ));
+    }
+    let rows = trans.query(
+        "
+        UPDATE purchase_order
+        SET status = $2, ordered_at = now()
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[&purchase_order_uuid, &PurchaseOrderStatus::Ordered],
+    )?;
+    trans.commit()?;
+    Ok(PurchaseOrder::from_row(&rows.get(0)))
+}
+
+/// Cancels an order nothing has been received against yet.
+pub fn cancel_purchase_order(context: &Context, id: &str) -> FieldResult<PurchaseOrder> {
+    let (restaurant_uuid, _) = partner_scope(context, Permission::ManageInventory)?;
+    let purchase_order_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let purchase_order =
+        PurchaseOrder::find_for_update(&trans, &purchase_order_uuid, &restaurant_uuid)?;
+    match purchase_order.status {
+        PurchaseOrderStatus::Draft | PurchaseOrderStatus::Ordered => {}
+        _ => {
+            return Err(inventory_error(
+                "Only purchase orders with nothing received can be cancelled",
+            ))
+        }
+    }
+    let rows = trans.query(
+        "
+        UPDATE purchase_order
+        SET status = $2, cancelled_at = now()
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[&purchase_order_uuid, &PurchaseOrderStatus::Cancelled],
+    )?;
+    trans.commit()?;
+    Ok(PurchaseOrder::from_row(&rows.get(0)))
+}
+
+/// Books a delivery into stock. Lines may arrive over several deliveries; the
+/// order becomes Received once every line is in full.
+pub fn receive_purchase_order(
+    context: &Context,
+    id: &str,
+    received: Vec<ReceivedLine>,
+) -> FieldResult<PurchaseOrder> {
+    let (restaurant_uuid, partner_uuid) = partner_scope(context, Permission::RecordStock)?;
+    let purchase_order_uuid = Uuid::parse_str(id)?;
+    if received.is_empty() {
+        return Err(inventory_error("Nothing to receive"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let purchase_order =
+        PurchaseOrder::find_for_update(&trans, &purchase_order_uuid, &restaurant_uuid)?;
+    match purchase_order.status {
+        PurchaseOrderStatus::Ordered | PurchaseOrderStatus::PartiallyReceived => {}
+        _ => {
+            return Err(inventory_error(
+                "Goods can only be received against a submitted purchase order",
+            ))
+        }
+    }
+    let lines = PurchaseOrderLine::for_purchase_order(&trans, &purchase_order_uuid)?;
+    let mut seen = HashSet::new();
+    for item in &received {
+        let line_uuid = Uuid::parse_str(&item.purchase_order_line_id)?;
+        if !seen.insert(line_uuid) {
+            return Err(inventory_error(
+                "Each line can only be received once per delivery",
+            ));
+        }
+        let line = match lines
+            .iter()
+            .find(|line| line.id == line_uuid.hyphenated().to_string())
+        {
+            Some(line) => line,
+            None => return Err(inventory_error("Line is not part of this purchase order")),
+        };
+        if item.quantity <= 0 || item.quantity > line.quantity_ordered - line.quantity_received {
+            return Err(inventory_error(
+                "Received quantity must be positive and within what is outstanding",
+            ));
+        }
+        let cost = item
+            .cost
+            .unwrap_or_else(|| line.expected_cost_of(item.quantity));
+        if cost < 0 {
+            return Err(inventory_error("Received cost cannot be negative"));
+        }
+        trans.execute(
+            "
+            UPDATE purchase_order_line
+            SET quantity_received = quantity_received + $2,
+                received_cost = received_cost + $3
+            WHERE id = $1
+        ",
+            &[&line_uuid, &item.quantity, &cost],
+        )?;
+        let ingredient_uuid = Uuid::parse_str(&line.ingredient_id)?;
+        receive_ingredient(
+            &trans,
+            &ingredient_uuid,
+            item.quantity,
+            cost,
+            &line_uuid,
+            &partner_uuid,
+        )?;
+    }
+    let outstanding = trans.query(
+        "
+        SELECT 1
+        FROM purchase_order_line
+        WHERE purchase_order_id = $1 AND quantity_received < quantity_ordered
+    ",
+        &[&purchase_order_uuid],
+    )?;
+    let rows = if outstanding.is_empty() {
+        trans.query(
+            "
+            UPDATE purchase_order
+            SET status = $2, received_at = now()
+            WHERE id = $1
+            RETURNING *
+        ",
+            &[&purchase_order_uuid, &PurchaseOrderStatus::Received],
+        )?
+    } else {
+        trans.query(
+            "
+            UPDATE purchase_order
+            SET status = $2
+            WHERE id = $1
+            RETURNING *
+        ",
+            &[
+                &purchase_order_uuid,
+                &PurchaseOrderStatus::PartiallyReceived,
+            ],
+        )?
+    };
+    trans.commit()?;
+    Ok(PurchaseOrder::from_row(&rows.get(0)))
+}
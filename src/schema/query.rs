@@ -2,19 +2,33 @@ use juniper::{FieldError, FieldResult};
 use uuid::Uuid;
 
 use super::context::{Context, Roles};
+use super::customer::{current_customer, Customer};
 use super::customer_order::{CustomerOrder, CustomerOrderStatus};
-use super::dining_table::DiningTable;
+use super::dining_table::{resolve_table_token, DiningTable};
 use super::dish::Dish;
+use super::dish_order::{DishOrder, DishOrderStatus};
+use super::floor_area::{floor_areas, FloorArea};
+use super::ingredient::Ingredient;
+use super::partner::Partner;
+use super::permission::{Permission, StaffRole};
+use super::purchase_order::{PurchaseOrder, PurchaseOrderStatus, Supplier};
 use super::restaurant::Restaurant;
+use super::table_session::{occupied_tables, TableSession};
+use crate::notifier::Channel;
 
 pub struct Query;
 
 graphql_object!(Query: Context |&self| {
-    field request_customer_auth(&executor, phone: String) -> FieldResult<String> {
-        executor.context().request_customer_auth(&phone, Roles::Customer)?;
+    field request_customer_auth(&executor, phone: String, channel: Option<Channel>) -> FieldResult<String> {
+        let channel = channel.unwrap_or(Channel::Sms);
+        executor.context().request_customer_auth(&phone, channel)?;
         Ok("Requested".to_owned())
     }
 
+    field current_customer(&executor) -> FieldResult<Customer> {
+        current_customer(executor.context())
+    }
+
     field current_customer_order(&executor) -> FieldResult<CustomerOrder> {
         let context = executor.context();
         context.authorize(Roles::Customer)?;
@@ -24,24 +38,178 @@ graphql_object!(Query: Context |&self| {
         let customer_order_rows = conn.query("
             SELECT *
             FROM customer_order
-            WHERE customer_id = $1 AND status = $2
-        ", &[&customer_uuid, &CustomerOrderStatus::Open])?;
+            WHERE status IN ($2, $3) AND (
+                customer_id = $1
+                OR id IN (SELECT customer_order_id FROM customer_order_participant WHERE customer_id = $1)
+            )
+            ORDER BY created_at DESC
+            LIMIT 1
+        ", &[&customer_uuid, &CustomerOrderStatus::Open, &CustomerOrderStatus::Closed])?;
         if customer_order_rows.is_empty() {
             return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
         }
 
-        let row = customer_order_rows.get(0);
-        let id: Uuid = row.get("id");
-        let restaurant_id: Uuid = row.get("restaurant_id");
-        let dining_table_id: Uuid = row.get("dining_table_id");
-        let customer_id: Uuid = row.get("customer_id");
-        Ok(CustomerOrder {
-            id: id.hyphenated().to_string(),
-            restaurant_id: restaurant_id.hyphenated().to_string(),
-            dining_table_id: dining_table_id.hyphenated().to_string(),
-            customer_id: customer_id.hyphenated().to_string(),
-            status: row.get("status"),
-        })
+        Ok(CustomerOrder::from_row(&customer_order_rows.get(0)))
+    }
+
+    field occupied_tables(&executor) -> FieldResult<Vec<TableSession>> {
+        occupied_tables(executor.context())
+    }
+
+    field kitchen_queue(&executor) -> FieldResult<Vec<DishOrder>> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ViewOrders)?)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT dish_order.*
+            FROM dish_order
+            JOIN customer_order ON customer_order.id = dish_order.customer_order_id
+            WHERE customer_order.restaurant_id = $1 AND dish_order.status IN ($2, $3, $4)
+            ORDER BY dish_order.created_at
+        ", &[&restaurant_uuid, &DishOrderStatus::Queued, &DishOrderStatus::Preparing, &DishOrderStatus::Ready])?;
+        let mut dish_orders = vec!();
+        for row in &rows {
+            dish_orders.push(DishOrder::from_row(&row));
+        }
+        Ok(dish_orders)
+    }
+
+    field low_stock_dishes(&executor, threshold: Option<i32>) -> FieldResult<Vec<Dish>> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::UpdateAvailability)?)?;
+        let threshold = threshold.unwrap_or(5);
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM dish
+            WHERE restaurant_id = $1 AND archived_at IS NULL AND stock <= $2
+            ORDER BY stock, name
+        ", &[&restaurant_uuid, &threshold])?;
+        let mut dishes = vec!();
+        for row in &rows {
+            dishes.push(Dish::from_row(&row));
+        }
+        Ok(dishes)
+    }
+
+    field ingredients(&executor) -> FieldResult<Vec<Ingredient>> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::RecordStock)?)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM ingredient
+            WHERE restaurant_id = $1
+            ORDER BY name
+        ", &[&restaurant_uuid])?;
+        let mut ingredients = vec!();
+        for row in &rows {
+            ingredients.push(Ingredient::from_row(&row));
+        }
+        Ok(ingredients)
+    }
+
+    field reorder_alerts(&executor) -> FieldResult<Vec<Ingredient>> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::RecordStock)?)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM ingredient
+            WHERE restaurant_id = $1 AND on_hand <= reorder_point
+            ORDER BY on_hand - reorder_point, name
+        ", &[&restaurant_uuid])?;
+        let mut ingredients = vec!();
+        for row in &rows {
+            ingredients.push(Ingredient::from_row(&row));
+        }
+        Ok(ingredients)
+    }
+
+    field suppliers(&executor) -> FieldResult<Vec<Supplier>> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageInventory)?)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM supplier
+            WHERE restaurant_id = $1
+            ORDER BY name
+        ", &[&restaurant_uuid])?;
+        let mut suppliers = vec!();
+        for row in &rows {
+            suppliers.push(Supplier::from_row(&row));
+        }
+        Ok(suppliers)
+    }
+
+    field purchase_orders(&executor, status: Option<PurchaseOrderStatus>) -> FieldResult<Vec<PurchaseOrder>> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageInventory)?)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM purchase_order
+            WHERE restaurant_id = $1 AND ($2::purchase_order_status IS NULL OR status = $2)
+            ORDER BY created_at DESC
+        ", &[&restaurant_uuid, &status])?;
+        let mut purchase_orders = vec!();
+        for row in &rows {
+            purchase_orders.push(PurchaseOrder::from_row(&row));
+        }
+        Ok(purchase_orders)
+    }
+
+    field purchase_order(&executor, id: String) -> FieldResult<PurchaseOrder> {
+        let context = executor.context();
+        let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ManageInventory)?)?;
+        let purchase_order_uuid = Uuid::parse_str(&id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM purchase_order
+            WHERE id = $1 AND restaurant_id = $2
+        ", &[&purchase_order_uuid, &restaurant_uuid])?;
+        if rows.is_empty() {
+            return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
+        }
+        Ok(PurchaseOrder::from_row(&rows.get(0)))
+    }
+
+    field partners(&executor, restaurant_id: String, pending_only: Option<bool>) -> FieldResult<Vec<Partner>> {
+        let context = executor.context();
+        context.authorize_restaurant(&restaurant_id, Permission::ManageStaff)?;
+        let restaurant_uuid = Uuid::parse_str(&restaurant_id)?;
+        let pending_only = pending_only.unwrap_or(false);
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM partner
+            WHERE restaurant_id = $1 AND (approved_at IS NULL OR NOT $2)
+            ORDER BY created_at
+        ", &[&restaurant_uuid, &pending_only])?;
+        let mut partners = vec!();
+        for row in &rows {
+            partners.push(Partner::from_row(&row));
+        }
+        Ok(partners)
+    }
+
+    field my_permissions(&executor) -> FieldResult<Vec<Permission>> {
+        let context = executor.context();
+        context.authorize(Roles::Partner)?;
+        let partner_uuid = Uuid::parse_str(context.get_client_id()?)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT staff_role
+            FROM partner
+            WHERE id = $1 AND is_active
+        ", &[&partner_uuid])?;
+        if rows.is_empty() {
+            return Ok(vec!());
+        }
+        let staff_role: StaffRole = rows.get(0).get("staff_role");
+        Ok(staff_role.permissions())
     }
 
     field restaurant(&executor, id: String) -> FieldResult<Restaurant> {
@@ -56,16 +224,7 @@ graphql_object!(Query: Context |&self| {
         if rows.is_empty() {
             return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
         }
-        let row = rows.get(0);
-        let row_id: Uuid = row.get("id");
-        Ok(Restaurant {
-            id: row_id.hyphenated().to_string(),
-            name: row.get("name"),
-            address: row.get("address"),
-            logo: row.get("logo"),
-            cover: row.get("cover"),
-            location_url: row.get("location_url"),
-        })
+        Ok(Restaurant::from_row(&rows.get(0)))
     }
 
     field dining_table(&executor, id: String) -> FieldResult<DiningTable> {
@@ -79,13 +238,17 @@ graphql_object!(Query: Context |&self| {
         if rows.is_empty() {
             return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
         }
-        let row = rows.get(0);
-        let row_id: Uuid = row.get("id");
-        Ok(DiningTable {
-            id: row_id.hyphenated().to_string(),
-            name: row.get("name"),
-            restaurant_id: row.get("restaurant_id"),
-        })
+        Ok(DiningTable::from_row(&rows.get(0)))
+    }
+
+    field dining_table_by_token(&executor, token: String) -> FieldResult<DiningTable> {
+        let conn = executor.context().pool.get()?;
+        resolve_table_token(&*conn, &token)
+    }
+
+    // the floor map; tables without an area are listed by dining_tables
+    field floor_areas(&executor, restaurant_id: String) -> FieldResult<Vec<FloorArea>> {
+        floor_areas(executor.context(), &restaurant_id)
     }
 
     field dish(&executor, id: String) -> FieldResult<Dish> {
@@ -99,14 +262,68 @@ graphql_object!(Query: Context |&self| {
         if rows.is_empty() {
             return Err(FieldError::new("Not found", graphql_value!({ "internal_error": "Not found" })));
         }
-        let row = rows.get(0);
-        let row_id: Uuid = row.get("id");
-        Ok(Dish {
-            id: row_id.hyphenated().to_string(),
-            name: row.get("name"),
-            price: row.get("price"),
-            description: row.get("description"),
-            restaurant_id: row.get("restaurant_id"),
-        })
+        Ok(Dish::from_row(&rows.get(0)))
+    }
+
+    field restaurants(&executor, include_archived: Option<bool>) -> FieldResult<Vec<Restaurant>> {
+        let context = executor.context();
+        let include_archived = include_archived.unwrap_or(false);
+        if include_archived {
+            context.authorize(Roles::Admin)?;
+        }
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM restaurant
+            WHERE archived_at IS NULL OR $1
+            ORDER BY name
+        ", &[&include_archived])?;
+        let mut restaurants = vec!();
+        for row in &rows {
+            restaurants.push(Restaurant::from_row(&row));
+        }
+        Ok(restaurants)
+    }
+
+    field dining_tables(&executor, restaurant_id: String, include_archived: Option<bool>) -> FieldResult<Vec<DiningTable>> {
+        let context = executor.context();
+        let include_archived = include_archived.unwrap_or(false);
+        if include_archived {
+            context.authorize_restaurant(&restaurant_id, Permission::ManageRestaurant)?;
+        }
+        let restaurant_uuid = Uuid::parse_str(&restaurant_id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM dining_table
+            WHERE restaurant_id = $1 AND (archived_at IS NULL OR $2)
+            ORDER BY name
+        ", &[&restaurant_uuid, &include_archived])?;
+        let mut dining_tables = vec!();
+        for row in &rows {
+            dining_tables.push(DiningTable::from_row(&row));
+        }
+        Ok(dining_tables)
+    }
+
+    field dishes(&executor, restaurant_id: String, include_archived: Option<bool>) -> FieldResult<Vec<Dish>> {
+        let context = executor.context();
+        let include_archived = include_archived.unwrap_or(false);
+        if include_archived {
+            context.authorize_restaurant(&restaurant_id, Permission::ManageMenu)?;
+        }
+        let restaurant_uuid = Uuid::parse_str(&restaurant_id)?;
+        let conn = context.pool.get()?;
+        let rows = conn.query("
+            SELECT *
+            FROM dish
+            WHERE restaurant_id = $1 AND (archived_at IS NULL OR $2)
+            ORDER BY name
+        ", &[&restaurant_uuid, &include_archived])?;
+        let mut dishes = vec!();
+        for row in &rows {
+            dishes.push(Dish::from_row(&row));
+        }
+        Ok(dishes)
     }
 });
@@ -0,0 +1,32 @@
+use juniper::{FieldError, FieldResult};
+use r2d2_redis::redis::{self, Commands, PipelineCommands};
+
+use super::context::Context;
+
+/// Counts a hit against `key` and fails once more than `limit` hits land
+/// within `window_seconds` of the first one.
+pub fn check_rate_limit(
+    context: &Context,
+    key: &str,
+    limit: i64,
+    window_seconds: usize,
+) -> FieldResult<()> {
+    let redis = context.redis_pool.get()?;
+    let key = format!("rate_limit:{}", key);
+    let (hits, ttl): (i64, i64) = redis::pipe()
+        .atomic()
+        .incr(&key, 1)
+        .ttl(&key)
+        .query(&*redis)?;
+    // the first hit opens the window
+    if ttl < 0 {
+        let _: () = redis.expire(&key, window_seconds)?;
+    }
+    if hits > limit {
+        return Err(FieldError::new(
+            "Too many requests, try again later",
+            graphql_value!({ "external_error": "Too many requests, try again later", "code": "RATE_LIMITED" }),
+        ));
+    }
+    Ok(())
+}
@@ -1,8 +1,12 @@
-use juniper::FieldResult;
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
 use uuid::Uuid;
 
 use super::context::Context;
 use super::dining_table::DiningTable;
+use super::dish::Dish;
+use super::menu_category::MenuCategory;
 
 pub struct Restaurant {
     pub id: String,
@@ -11,6 +15,37 @@ pub struct Restaurant {
     pub logo: String,
     pub cover: String,
     pub location_url: String,
+    pub tax_rate: i32,
+    pub service_charge_rate: i32,
+    pub archived_at: Option<NaiveDateTime>,
+}
+
+impl Restaurant {
+    pub fn from_row(row: &Row) -> Restaurant {
+        let id: Uuid = row.get("id");
+        Restaurant {
+            id: id.hyphenated().to_string(),
+            name: row.get("name"),
+            address: row.get("address"),
+            logo: row.get("logo"),
+            cover: row.get("cover"),
+            location_url: row.get("location_url"),
+            tax_rate: row.get("tax_rate"),
+            service_charge_rate: row.get("service_charge_rate"),
+            archived_at: row.get("archived_at"),
+        }
+    }
+}
+
+/// Rates are basis points and may not exceed 100%.
+pub fn validate_rate(rate: i32) -> FieldResult<i32> {
+    if !(0..=10_000).contains(&rate) {
+        return Err(FieldError::new(
+            "Rate must be between 0 and 10000 basis points",
+            graphql_value!({ "external_error": "Rate must be between 0 and 10000 basis points" }),
+        ));
+    }
+    Ok(rate)
 }
 
 graphql_object!(Restaurant: Context | &self | {
@@ -32,27 +67,65 @@ graphql_object!(Restaurant: Context | &self | {
   field location_url() -> &str {
     self.location_url.as_str()
   }
+  field tax_rate() -> i32 {
+    self.tax_rate
+  }
+  field service_charge_rate() -> i32 {
+    self.service_charge_rate
+  }
+  field archived_at() -> &Option<NaiveDateTime> {
+    &self.archived_at
+  }
   field dining_table(&executor) -> FieldResult<Vec<DiningTable>> {
     let conn = executor.context().pool.get()?;
     let restaurant_id = Uuid::parse_str(&self.id)?;
     let rows = conn.query("
         SELECT *
         FROM dining_table
-        WHERE restaurant_id = $1
+        WHERE restaurant_id = $1 AND archived_at IS NULL
+        ORDER BY name
     ", &[&restaurant_id])?;
     let mut dining_table_vec = vec!();
     for row in &rows {
-      let row_id: Uuid = row.get("id");
-      let restaurant_id: Uuid = row.get("restaurant_id");
-      let dining_table = DiningTable {
-        id: row_id.hyphenated().to_string(),
-        name: row.get("name"),
-        restaurant_id: restaurant_id.hyphenated().to_string(),
-      };
-      dining_table_vec.push(dining_table);
+      dining_table_vec.push(DiningTable::from_row(&row));
     }
     Ok(dining_table_vec)
   }
+  field dishes(&executor) -> FieldResult<Vec<Dish>> {
+    let conn = executor.context().pool.get()?;
+    let restaurant_id = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+        SELECT *
+        FROM dish
+        WHERE restaurant_id = $1 AND archived_at IS NULL
+        ORDER BY name
+    ", &[&restaurant_id])?;
+    let mut dishes = vec!();
+    for row in &rows {
+      dishes.push(Dish::from_row(&row));
+    }
+    Ok(dishes)
+  }
+  field menu(&executor) -> FieldResult<Vec<MenuCategory>> {
+    let conn = executor.context().pool.get()?;
+    let restaurant_id = Uuid::parse_str(&self.id)?;
+    MenuCategory::for_restaurant(&*conn, &restaurant_id)
+  }
+  field uncategorized_dishes(&executor) -> FieldResult<Vec<Dish>> {
+    let conn = executor.context().pool.get()?;
+    let restaurant_id = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+        SELECT *
+        FROM dish
+        WHERE restaurant_id = $1 AND menu_category_id IS NULL AND archived_at IS NULL
+        ORDER BY position, name
+    ", &[&restaurant_id])?;
+    let mut dishes = vec!();
+    for row in &rows {
+      dishes.push(Dish::from_row(&row));
+    }
+    Ok(dishes)
+  }
 });
 
 #[derive(GraphQLInputObject)]
@@ -62,4 +135,21 @@ pub struct NewRestaurant {
     pub logo: String,
     pub cover: String,
     pub location_url: String,
+    pub tax_rate: Option<i32>,
+    pub service_charge_rate: Option<i32>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct RestaurantUpdate {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    pub logo: Option<String>,
+    pub cover: Option<String>,
+    pub location_url: Option<String>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct RestaurantCharges {
+    pub tax_rate: i32,
+    pub service_charge_rate: i32,
 }
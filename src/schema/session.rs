@@ -0,0 +1,287 @@
+//! Sign-in sessions. Access tokens are short-lived JWTs naming the session
+//! they belong to, and the session itself lives in Redis together with the
+//! hash of its current refresh token. Deleting a session revokes every token
+//! it has issued.
+
+use chrono::prelude::*;
+use crypto::digest::Digest;
+use crypto::sha3::Sha3;
+use crypto::util::fixed_time_eq;
+use juniper::{FieldError, FieldResult};
+use jwt::{encode, Header};
+use r2d2::Pool;
+use r2d2_redis::redis::{self, Commands, Connection, PipelineCommands};
+use r2d2_redis::RedisConnectionManager;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::env;
+use uuid::Uuid;
+
+use super::context::{Claims, Context, Roles};
+
+const ACCESS_TOKEN_SECONDS: i64 = 15 * 60;
+/// Sessions that go this long without a refresh are forgotten.
+const SESSION_SECONDS: usize = 30 * 24 * 60 * 60;
+
+#[derive(GraphQLObject)]
+pub struct AuthTokens {
+    pub access_token: String,
+    /// Single use; exchange it with `refreshTokens` for a new pair.
+    pub refresh_token: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionInfo {
+    client_id: String,
+    role: Roles,
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("session:{}", session_id)
+}
+
+fn refresh_key(session_id: &str) -> String {
+    format!("session:{}:refresh", session_id)
+}
+
+fn client_sessions_key(client_id: &str) -> String {
+    format!("client_sessions:{}", client_id)
+}
+
+fn invalid_refresh_token() -> FieldError {
+    FieldError::new(
+        "Refresh token is no longer valid",
+        graphql_value!({ "internal_error": "Refresh token is no longer valid" }),
+    )
+}
+
+fn random_secret() -> FieldResult<String> {
+    let mut bytes = [0u8; 32];
+    OsRng::new()?.fill_bytes(&mut bytes);
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn hash_secret(secret: &str) -> String {
+    let mut hasher = Sha3::sha3_256();
+    hasher.input_str(secret);
+    hasher.result_str()
+}
+
+fn format_refresh_token(session_id: &str, secret: &str) -> String {
+    format!("{}.{}", session_id, secret)
+}
+
+/// Splits a refresh token into its session id and secret.
+fn parse_refresh_token(refresh_token: &str) -> Option<(&str, &str)> {
+    let mut parts = refresh_token.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(session_id), Some(secret)) if !session_id.is_empty() && !secret.is_empty() => {
+            Some((session_id, secret))
+        }
+        _ => None,
+    }
+}
+
+/// Partners keep their sessions only while their account is approved and
+/// active.
+fn is_partner_allowed(context: &Context, partner_id: &str) -> FieldResult<bool> {
+    let partner_uuid = Uuid::parse_str(partner_id)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT 1
+        FROM partner
+        WHERE id = $1 AND is_active AND approved_at IS NOT NULL
+    ",
+        &[&partner_uuid],
+    )?;
+    Ok(!rows.is_empty())
+}
+
+fn issue_tokens(
+    session_id: &str,
+    client_id: &str,
+    role: Roles,
+    secret: &str,
+) -> FieldResult<AuthTokens> {
+    let key = env::var("JWT_AUTH_SECRET")?;
+    let exp = Utc::now().timestamp() + ACCESS_TOKEN_SECONDS;
+    let claims = Claims {
+        sub: client_id.to_owned(),
+        company: "RRMDN".to_owned(),
+        exp,
+        role,
+        sid: session_id.to_owned(),
+    };
+    Ok(AuthTokens {
+        access_token: encode(&Header::default(), &claims, key.as_ref())?,
+        refresh_token: format_refresh_token(session_id, secret),
+        expires_at: NaiveDateTime::from_timestamp(exp, 0),
+    })
+}
+
+fn delete_session(redis: &Connection, session_id: &str, client_id: &str) -> FieldResult<()> {
+    let _: () = redis::pipe()
+        .atomic()
+        .del(session_key(session_id))
+        .ignore()
+        .del(refresh_key(session_id))
+        .ignore()
+        .srem(client_sessions_key(client_id), session_id)
+        .ignore()
+        .query(redis)?;
+    Ok(())
+}
+
+pub fn start_session(context: &Context, client_id: &str, role: Roles) -> FieldResult<AuthTokens> {
+    let redis = context.redis_pool.get()?;
+    let session_id = Uuid::new_v4().hyphenated().to_string();
+    let secret = random_secret()?;
+    let info = serde_json::to_string(&SessionInfo {
+        client_id: client_id.to_owned(),
+        role,
+    })?;
+    let _: () = redis::pipe()
+        .atomic()
+        .set_ex(session_key(&session_id), info, SESSION_SECONDS)
+        .ignore()
+        .set_ex(
+            refresh_key(&session_id),
+            hash_secret(&secret),
+            SESSION_SECONDS,
+        )
+        .ignore()
+        .sadd(client_sessions_key(client_id), &session_id)
+        .ignore()
+        .expire(client_sessions_key(client_id), SESSION_SECONDS)
+        .ignore()
+        .query(&*redis)?;
+    issue_tokens(&session_id, client_id, role, &secret)
+}
+
+/// Swaps a refresh token for a new pair. Refresh tokens rotate on every use,
+/// so a token that has already been spent means it leaked, and the whole
+/// session is revoked. So is the session of a partner who has since been
+/// deactivated.
+pub fn refresh_session(context: &Context, refresh_token: &str) -> FieldResult<AuthTokens> {
+    let (session_id, secret) = match parse_refresh_token(refresh_token) {
+        Some(parts) => parts,
+        None => return Err(invalid_refresh_token()),
+    };
+    let redis = context.redis_pool.get()?;
+    let info: Option<String> = redis.get(session_key(session_id))?;
+    let info: SessionInfo = match info {
+        Some(info) => serde_json::from_str(&info)?,
+        None => return Err(invalid_refresh_token()),
+    };
+    if info.role == Roles::Partner && !is_partner_allowed(context, &info.client_id)? {
+        delete_session(&redis, session_id, &info.client_id)?;
+        return Err(invalid_refresh_token());
+    }
+    let next_secret = random_secret()?;
+    // GETSET makes rotation atomic: of two requests racing with the same
+    // token, only one sees its hash come back
+    let (previous,): (Option<String>,) = redis::pipe()
+        .atomic()
+        .getset(refresh_key(session_id), hash_secret(&next_secret))
+        .expire(refresh_key(session_id), SESSION_SECONDS)
+        .ignore()
+        .expire(session_key(session_id), SESSION_SECONDS)
+        .ignore()
+        .expire(client_sessions_key(&info.client_id), SESSION_SECONDS)
+        .ignore()
+        .query(&*redis)?;
+    let presented = hash_secret(secret);
+    let matches = previous
+        .map(|previous| fixed_time_eq(previous.as_bytes(), presented.as_bytes()))
+        .unwrap_or(false);
+    if !matches {
+        delete_session(&redis, session_id, &info.client_id)?;
+        return Err(invalid_refresh_token());
+    }
+    issue_tokens(session_id, &info.client_id, info.role, &next_secret)
+}
+
+/// Ends the session the caller's access token belongs to.
+pub fn end_session(context: &Context) -> FieldResult<bool> {
+    let session_id = context.get_session_id()?;
+    let client_id = context.get_client_id()?;
+    let redis = context.redis_pool.get()?;
+    delete_session(&redis, session_id, client_id)?;
+    Ok(true)
+}
+
+/// Ends every session of the caller, on all of their devices.
+pub fn end_all_sessions(context: &Context) -> FieldResult<bool> {
+    let client_id = context.get_client_id()?;
+    revoke_client_sessions(&context.redis_pool, client_id)?;
+    Ok(true)
+}
+
+pub fn revoke_client_sessions(
+    redis_pool: &Pool<RedisConnectionManager>,
+    client_id: &str,
+) -> FieldResult<()> {
+    let redis = redis_pool.get()?;
+    let session_ids: Vec<String> = redis.smembers(client_sessions_key(client_id))?;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for session_id in &session_ids {
+        pipe.del(session_key(session_id))
+            .ignore()
+            .del(refresh_key(session_id))
+            .ignore();
+    }
+    pipe.del(client_sessions_key(client_id)).ignore();
+    let _: () = pipe.query(&*redis)?;
+    Ok(())
+}
+
+/// Whether an access token's session is still live. Fails closed when Redis
+/// can't be reached.
+pub fn is_session_active(redis_pool: &Pool<RedisConnectionManager>, session_id: &str) -> bool {
+    let redis = match redis_pool.get() {
+        Ok(redis) => redis,
+        Err(_) => return false,
+    };
+    redis.exists(session_key(session_id)).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_hash_to_sha3_hex() {
+        // SHA3-256 of "abc", from the FIPS 202 examples
+        assert_eq!(
+            hash_secret("abc"),
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+        );
+        assert_ne!(hash_secret("abc"), hash_secret("abd"));
+    }
+
+    #[test]
+    fn refresh_tokens_round_trip() {
+        let token = format_refresh_token("9f2b6c1e-session", "00ff");
+        assert_eq!(token, "9f2b6c1e-session.00ff");
+        assert_eq!(
+            parse_refresh_token(&token),
+            Some(("9f2b6c1e-session", "00ff"))
+        );
+    }
+
+    #[test]
+    fn secrets_keep_any_further_dots() {
+        assert_eq!(parse_refresh_token("session.a.b"), Some(("session", "a.b")));
+    }
+
+    #[test]
+    fn malformed_refresh_tokens_are_rejected() {
+        assert_eq!(parse_refresh_token(""), None);
+        assert_eq!(parse_refresh_token("session"), None);
+        assert_eq!(parse_refresh_token("session."), None);
+        assert_eq!(parse_refresh_token(".secret"), None);
+    }
+}
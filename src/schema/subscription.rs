@@ -0,0 +1,63 @@
+use juniper::FieldResult;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use super::context::Context;
+use super::customer_order::CustomerOrder;
+use super::order_event::{customer_order_channel, restaurant_channel, OrderEvent};
+use super::permission::Permission;
+
+/// Root for subscription documents. juniper runs it like a query: once with no
+/// event to authorize the caller and collect the channels to listen on, then
+/// once for every event delivered on those channels.
+pub struct Subscription {
+    event: Option<(String, OrderEvent)>,
+    channels: Arc<Mutex<Vec<String>>>,
+}
+
+impl Subscription {
+    /// Root for the setup run, which records the requested channels into `channels`.
+    pub fn setup(channels: Arc<Mutex<Vec<String>>>) -> Subscription {
+        Subscription {
+            event: None,
+            channels,
+        }
+    }
+
+    pub fn for_event(channel: String, event: OrderEvent) -> Subscription {
+        Subscription {
+            event: Some((channel, event)),
+            channels: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    fn resolve(&self, channel: String) -> Option<OrderEvent> {
+        match self.event {
+            None => {
+                self.channels.lock().unwrap().push(channel);
+                None
+            }
+            Some((ref event_channel, ref event)) if *event_channel == channel => {
+                Some(event.clone())
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+graphql_object!(Subscription: Context | &self | {
+    field customer_order_events(&executor, customer_order_id: String) -> FieldResult<Option<OrderEvent>> {
+        let context = executor.context();
+        let customer_order_uuid = Uuid::parse_str(&customer_order_id)?;
+        let conn = context.pool.get()?;
+        let customer_order = CustomerOrder::find(&*conn, &customer_order_uuid)?;
+        context.authorize_customer_order(&customer_order, Permission::ViewOrders)?;
+        Ok(self.resolve(customer_order_channel(&customer_order.id)))
+    }
+
+    field restaurant_events(&executor) -> FieldResult<Option<OrderEvent>> {
+        let context = executor.context();
+        let restaurant_id = context.authorize_permission(Permission::ViewOrders)?;
+        Ok(self.resolve(restaurant_channel(&restaurant_id)))
+    }
+});
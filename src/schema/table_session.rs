@@ -0,0 +1,823 @@
+use super::context::{Context, Roles};
+use super::customer::Customer;
+use super::customer_order::{generate_invite_code, CustomerOrder, CustomerOrderStatus};
+use super::dining_table::{mark_needs_cleaning, resolve_table_token};
+use super::order_event::{publish_order_event, OrderEvent, OrderEventKind};
+use super::permission::Permission;
+use chrono::NaiveDateTime;
+use juniper::{FieldError, FieldResult};
+use postgres::rows::Row;
+use postgres::GenericConnection;
+use uuid::Uuid;
+
+/// A dining table's occupancy, from the first diner sitting down until the
+/// order is settled. Everyone seated shares the session's order.
+pub struct TableSession {
+    pub id: String,
+    pub dining_table_id: String,
+    pub customer_order_id: String,
+    pub opened_by: String,
+    pub opened_at: NaiveDateTime,
+    pub closed_at: Option<NaiveDateTime>,
+}
+
+impl TableSession {
+    pub fn from_row(row: &Row) -> TableSession {
+        let id: Uuid = row.get("id");
+        let dining_table_id: Uuid = row.get("dining_table_id");
+        let customer_order_id: Uuid = row.get("customer_order_id");
+        let opened_by: Uuid = row.get("opened_by");
+        TableSession {
+            id: id.hyphenated().to_string(),
+            dining_table_id: dining_table_id.hyphenated().to_string(),
+            customer_order_id: customer_order_id.hyphenated().to_string(),
+            opened_by: opened_by.hyphenated().to_string(),
+            opened_at: row.get("opened_at"),
+            closed_at: row.get("closed_at"),
+        }
+    }
+}
+
+fn table_session_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "external_error": message }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, GraphQLEnum)]
+#[postgres(name = "table_session_event_kind")]
+pub enum TableSessionEventKind {
+    Transfer,
+    Merge,
+    Split,
+}
+
+/// Audit record of a party being moved, merged or split by staff.
+#[derive(GraphQLObject)]
+pub struct TableSessionEvent {
+    pub id: String,
+    pub created_at: NaiveDateTime,
+    pub kind: TableSessionEventKind,
+    pub table_session_id: String,
+    /// The session merged into, or split off into.
+    pub other_table_session_id: Option<String>,
+    pub from_dining_table_id: String,
+    pub to_dining_table_id: String,
+    pub dish_order_count: i32,
+    pub performed_by: String,
+}
+
+impl TableSessionEvent {
+    pub fn from_row(row: &Row) -> TableSessionEvent {
+        let id: Uuid = row.get("id");
+        let table_session_id: Uuid = row.get("table_session_id");
+        let other_table_session_id: Option<Uuid> = row.get("other_table_session_id");
+        let from_dining_table_id: Uuid = row.get("from_dining_table_id");
+        let to_dining_table_id: Uuid = row.get("to_dining_table_id");
+        let performed_by: Uuid = row.get("performed_by");
+        TableSessionEvent {
+            id: id.hyphenated().to_string(),
+            created_at: row.get("created_at"),
+            kind: row.get("kind"),
+            table_session_id: table_session_id.hyphenated().to_string(),
+            other_table_session_id: other_table_session_id.map(|id| id.hyphenated().to_string()),
+            from_dining_table_id: from_dining_table_id.hyphenated().to_string(),
+            to_dining_table_id: to_dining_table_id.hyphenated().to_string(),
+            dish_order_count: row.get("dish_order_count"),
+            performed_by: performed_by.hyphenated().to_string(),
+        }
+    }
+}
+
+struct Event<'a> {
+    kind: TableSessionEventKind,
+    table_session_id: &'a Uuid,
+    other_table_session_id: Option<&'a Uuid>,
+    from_dining_table_id: &'a Uuid,
+    to_dining_table_id: &'a Uuid,
+    dish_order_count: i32,
+    performed_by: &'a Uuid,
+}
+
+fn record_event(conn: &dyn GenericConnection, event: &Event) -> FieldResult<()> {
+    conn.execute(
+        "
+        INSERT INTO table_session_event (
+            kind,
+            table_session_id,
+            other_table_session_id,
+            from_dining_table_id,
+            to_dining_table_id,
+            dish_order_count,
+            performed_by
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ",
+        &[
+            &event.kind,
+            event.table_session_id,
+            &event.other_table_session_id,
+            event.from_dining_table_id,
+            event.to_dining_table_id,
+            &event.dish_order_count,
+            event.performed_by,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Loads an open session of the restaurant and locks it.
+fn find_open_for_update(
+    conn: &dyn GenericConnection,
+    id: &Uuid,
+    restaurant_id: &Uuid,
+) -> FieldResult<TableSession> {
+    let rows = conn.query(
+        "
+        SELECT table_session.*
+        FROM table_session
+        JOIN dining_table ON dining_table.id = table_session.dining_table_id
+        WHERE table_session.id = $1
+            AND dining_table.restaurant_id = $2
+            AND table_session.closed_at IS NULL
+        FOR UPDATE OF table_session
+    ",
+        &[id, restaurant_id],
+    )?;
+    if rows.is_empty() {
+        return Err(table_session_error("Table session is not open"));
+    }
+    Ok(TableSession::from_row(&rows.get(0)))
+}
+
+/// Locks a table of the restaurant that nobody is seated at.
+fn lock_free_table(
+    conn: &dyn GenericConnection,
+    dining_table_id: &Uuid,
+    restaurant_id: &Uuid,
+) -> FieldResult<()> {
+    let rows = conn.query(
+        "
+        SELECT 1
+        FROM dining_table
+        WHERE id = $1 AND restaurant_id = $2 AND archived_at IS NULL
+        FOR UPDATE
+    ",
+        &[dining_table_id, restaurant_id],
+    )?;
+    if rows.is_empty() {
+        return Err(table_session_error("Dining table does not exist"));
+    }
+    let rows = conn.query(
+        "
+        SELECT 1
+        FROM table_session
+        WHERE dining_table_id = $1 AND closed_at IS NULL
+    ",
+        &[dining_table_id],
+    )?;
+    if !rows.is_empty() {
+        return Err(FieldError::new(
+            "Dining table is occupied",
+            graphql_value!({ "external_error": "Dining table is occupied", "code": "TABLE_OCCUPIED" }),
+        ));
+    }
+    Ok(())
+}
+
+/// Items can only move between orders that are still being ordered on and
+/// that nobody has started paying.
+fn lock_movable_order(
+    conn: &dyn GenericConnection,
+    customer_order_id: &Uuid,
+) -> FieldResult<CustomerOrder> {
+    let customer_order = CustomerOrder::find_for_update(conn, customer_order_id)?;
+    if customer_order.status != CustomerOrderStatus::Open {
+        return Err(table_session_error(
+            "Only open orders can be merged or split",
+        ));
+    }
+    let rows = conn.query(
+        "
+        SELECT 1
+        FROM payment
+        WHERE customer_order_id = $1 AND status <> 'Failed'
+    ",
+        &[customer_order_id],
+    )?;
+    if !rows.is_empty() {
+        return Err(table_session_error(
+            "Order is already being paid and cannot be merged or split",
+        ));
+    }
+    Ok(customer_order)
+}
+
+/// Locks the rows of both ids lowest first, so that two transactions taking
+/// the same pair in opposite roles wait on each other instead of deadlocking.
+fn lock_pair<T, F>(first: &Uuid, second: &Uuid, mut lock: F) -> FieldResult<(T, T)>
+where
+    F: FnMut(&Uuid) -> FieldResult<T>,
+{
+    if first <= second {
+        let first = lock(first)?;
+        Ok((first, lock(second)?))
+    } else {
+        let second = lock(second)?;
+        Ok((lock(first)?, second))
+    }
+}
+
+/// Ends the session of an order once it has been settled, freeing the table.
+pub fn close_table_session_for_order(
+    conn: &dyn GenericConnection,
+    customer_order_id: &Uuid,
+) -> FieldResult<()> {
+    let rows = conn.query(
+        "
+        UPDATE table_session
+        SET closed_at = now()
+        WHERE customer_order_id = $1 AND closed_at IS NULL
+        RETURNING dining_table_id
+    ",
+        &[customer_order_id],
+    )?;
+    for row in &rows {
+        mark_needs_cleaning(conn, &row.get("dining_table_id"))?;
+    }
+    Ok(())
+}
+
+/// Seats the calling customer at the table whose QR code they scanned. The
+/// first diner opens a session with a fresh order; later diners join that order.
+pub fn seat_customer(context: &Context, table_token: &str) -> FieldResult<CustomerOrder> {
+    context.authorize(Roles::Customer)?;
+    let customer_id = context.get_client_id()?;
+    let customer_uuid = Uuid::parse_str(customer_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let dining_table = resolve_table_token(&trans, table_token)?;
+    let dining_table_uuid = Uuid::parse_str(&dining_table.id)?;
+    // locking the table makes two diners sitting down at once share one session
+    let dining_table_rows = trans.query(
+        "
+        SELECT dining_table.restaurant_id, restaurant.tax_rate, restaurant.service_charge_rate
+        FROM dining_table
+        JOIN restaurant ON restaurant.id = dining_table.restaurant_id
+        WHERE dining_table.id = $1
+            AND dining_table.archived_at IS NULL
+            AND restaurant.archived_at IS NULL
+        FOR UPDATE OF dining_table
+    ",
+        &[&dining_table_uuid],
+    )?;
+    if dining_table_rows.is_empty() {
+        return Err(table_session_error("Dining table does not exist"));
+    }
+    let session_rows = trans.query(
+        "
+        SELECT *
+        FROM table_session
+        WHERE dining_table_id = $1 AND closed_at IS NULL
+    ",
+        &[&dining_table_uuid],
+    )?;
+    if !session_rows.is_empty() {
+        let session = TableSession::from_row(&session_rows.get(0));
+        let customer_order_uuid = Uuid::parse_str(&session.customer_order_id)?;
+        let customer_order = CustomerOrder::find(&trans, &customer_order_uuid)?;
+        if &customer_order.customer_id != customer_id {
+            trans.execute(
+                "
+                INSERT INTO customer_order_participant (
+                    customer_order_id,
+                    customer_id
+                ) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+            ",
+                &[&customer_order_uuid, &customer_uuid],
+            )?;
+        }
+        trans.commit()?;
+        return Ok(customer_order);
+    }
+
+    let dining_table_row = dining_table_rows.get(0);
+    let restaurant_uuid: Uuid = dining_table_row.get("restaurant_id");
+    let tax_rate: i32 = dining_table_row.get("tax_rate");
+    let service_charge_rate: i32 = dining_table_row.get("service_charge_rate");
+    let customer_order_uuid = Uuid::new_v4();
+    let status = CustomerOrderStatus::Open;
+    let invite_code = generate_invite_code()?;
+    // rates are copied onto the order so later restaurant changes leave it untouched
+    trans.execute(
+        "
+        INSERT INTO customer_order (
+            id,
+            restaurant_id,
+            dining_table_id,
+            customer_id,
+            status,
+            tax_rate,
+            service_charge_rate,
+            invite_code
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    ",
+        &[
+            &customer_order_uuid,
+            &restaurant_uuid,
+            &dining_table_uuid,
+            &customer_uuid,
+            &status,
+            &tax_rate,
+            &service_charge_rate,
+            &invite_code,
+        ],
+    )?;
+    trans.execute(
+        "
+        INSERT INTO table_session (
+            id,
+            dining_table_id,
+            customer_order_id,
+            opened_by
+        ) VALUES ($1, $2, $3, $4)
+    ",
+        &[
+            &Uuid::new_v4(),
+            &dining_table_uuid,
+            &customer_order_uuid,
+            &customer_uuid,
+        ],
+    )?;
+    trans.commit()?;
+
+    let customer_order = CustomerOrder {
+        id: customer_order_uuid.hyphenated().to_string(),
+        restaurant_id: restaurant_uuid.hyphenated().to_string(),
+        dining_table_id: dining_table_uuid.hyphenated().to_string(),
+        customer_id: customer_uuid.hyphenated().to_string(),
+        status,
+        invite_code: Some(invite_code),
+    };
+    publish_order_event(
+        context,
+        &OrderEvent::for_customer_order(OrderEventKind::CustomerOrderCreated, &customer_order),
+    );
+    Ok(customer_order)
+}
+
+/// Frees a table by hand, for diners who left without the order being
+/// settled. The order itself stays as it is.
+pub fn close_table_session(context: &Context, id: &str) -> FieldResult<TableSession> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageOrders)?)?;
+    let session_uuid = Uuid::parse_str(id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let rows = trans.query(
+        "
+        UPDATE table_session
+        SET closed_at = now()
+        FROM dining_table
+        WHERE table_session.id = $1
+            AND dining_table.id = table_session.dining_table_id
+            AND dining_table.restaurant_id = $2
+            AND table_session.closed_at IS NULL
+        RETURNING table_session.*
+    ",
+        &[&session_uuid, &restaurant_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(table_session_error("Table session is not open"));
+    }
+    let session = TableSession::from_row(&rows.get(0));
+    mark_needs_cleaning(&trans, &Uuid::parse_str(&session.dining_table_id)?)?;
+    trans.commit()?;
+    Ok(session)
+}
+
+/// Moves a party, their order and its session to a free table.
+pub fn transfer_table_session(
+    context: &Context,
+    id: &str,
+    dining_table_id: &str,
+) -> FieldResult<TableSession> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageOrders)?)?;
+    let partner_uuid = Uuid::parse_str(context.get_client_id()?)?;
+    let session_uuid = Uuid::parse_str(id)?;
+    let to_dining_table_uuid = Uuid::parse_str(dining_table_id)?;
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let session = find_open_for_update(&trans, &session_uuid, &restaurant_uuid)?;
+    let from_dining_table_uuid = Uuid::parse_str(&session.dining_table_id)?;
+    if from_dining_table_uuid == to_dining_table_uuid {
+        return Err(table_session_error("Party is already seated at that table"));
+    }
+    lock_free_table(&trans, &to_dining_table_uuid, &restaurant_uuid)?;
+    let customer_order_uuid = Uuid::parse_str(&session.customer_order_id)?;
+    let rows = trans.query(
+        "
+        UPDATE table_session
+        SET dining_table_id = $2
+        WHERE id = $1
+        RETURNING *
+    ",
+        &[&session_uuid, &to_dining_table_uuid],
+    )?;
+    trans.execute(
+        "
+        UPDATE customer_order
+        SET dining_table_id = $2, updated_at = now()
+        WHERE id = $1
+    ",
+        &[&customer_order_uuid, &to_dining_table_uuid],
+    )?;
+    record_event(
+        &trans,
+        &Event {
+            kind: TableSessionEventKind::Transfer,
+            table_session_id: &session_uuid,
+            other_table_session_id: None,
+            from_dining_table_id: &from_dining_table_uuid,
+            to_dining_table_id: &to_dining_table_uuid,
+            dish_order_count: 0,
+            performed_by: &partner_uuid,
+        },
+    )?;
+    mark_needs_cleaning(&trans, &from_dining_table_uuid)?;
+    trans.commit()?;
+    Ok(TableSession::from_row(&rows.get(0)))
+}
+
+/// Pushes two tables together: every item, diner and discount of `id` moves
+/// onto the order of `into_id`, and the emptied order is settled at zero,
+/// which frees its table.
+pub fn merge_table_sessions(
+    context: &Context,
+    id: &str,
+    into_id: &str,
+) -> FieldResult<TableSession> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageOrders)?)?;
+    let partner_uuid = Uuid::parse_str(context.get_client_id()?)?;
+    let session_uuid = Uuid::parse_str(id)?;
+    let into_session_uuid = Uuid::parse_str(into_id)?;
+    if session_uuid == into_session_uuid {
+        return Err(table_session_error("A table cannot be merged into itself"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let (session, into_session) = lock_pair(&session_uuid, &into_session_uuid, |id| {
+        find_open_for_update(&trans, id, &restaurant_uuid)
+    })?;
+    let customer_order_uuid = Uuid::parse_str(&session.customer_order_id)?;
+    let into_customer_order_uuid = Uuid::parse_str(&into_session.customer_order_id)?;
+    let (customer_order, into_customer_order) =
+        lock_pair(&customer_order_uuid, &into_customer_order_uuid, |id| {
+            lock_movable_order(&trans, id)
+        })?;
+    let dish_order_count = trans.execute(
+        "
+        UPDATE dish_order
+        SET customer_order_id = $2
+        WHERE customer_order_id = $1
+    ",
+        &[&customer_order_uuid, &into_customer_order_uuid],
+    )?;
+    // everyone who sat at the merged table can keep ordering
+    trans.execute(
+        "
+        INSERT INTO customer_order_participant (
+            customer_order_id,
+            customer_id
+        )
+        SELECT $2, customer_id
+        FROM customer_order
+        WHERE id = $1 AND customer_id <> $3
+        UNION
+        SELECT $2, customer_id
+        FROM customer_order_participant
+        WHERE customer_order_id = $1 AND customer_id <> $3
+        ON CONFLICT DO NOTHING
+    ",
+        &[
+            &customer_order_uuid,
+            &into_customer_order_uuid,
+            &Uuid::parse_str(&into_customer_order.customer_id)?,
+        ],
+    )?;
+    trans.execute(
+        "
+        UPDATE customer_order
+        SET discount = discount + (SELECT discount FROM customer_order WHERE id = $1),
+            updated_at = now()
+        WHERE id = $2
+    ",
+        &[&customer_order_uuid, &into_customer_order_uuid],
+    )?;
+    trans.execute(
+        "
+        UPDATE customer_order
+        SET discount = 0
+        WHERE id = $1
+    ",
+        &[&customer_order_uuid],
+    )?;
+    let customer_order = customer_order
+        .transition(
+            &trans,
+            CustomerOrderStatus::Closed,
+            &partner_uuid,
+            Roles::Partner,
+        )?
+        .transition(
+            &trans,
+            CustomerOrderStatus::Done,
+            &partner_uuid,
+            Roles::Partner,
+        )?;
+    record_event(
+        &trans,
+        &Event {
+            kind: TableSessionEventKind::Merge,
+            table_session_id: &session_uuid,
+            other_table_session_id: Some(&into_session_uuid),
+            from_dining_table_id: &Uuid::parse_str(&session.dining_table_id)?,
+            to_dining_table_id: &Uuid::parse_str(&into_session.dining_table_id)?,
+            dish_order_count: dish_order_count as i32,
+            performed_by: &partner_uuid,
+        },
+    )?;
+    trans.commit()?;
+    publish_order_event(
+        context,
+        &OrderEvent::for_customer_order(
+            OrderEventKind::CustomerOrderStatusChanged,
+            &customer_order,
+        ),
+    );
+    Ok(into_session)
+}
+
+/// Moves some diners, with everything they ordered, from a session to a new
+/// one at a free table. The first listed diner owns the new order; the diner
+/// who opened the original table stays with it.
+pub fn split_table_session(
+    context: &Context,
+    id: &str,
+    dining_table_id: &str,
+    customer_ids: &[String],
+) -> FieldResult<TableSession> {
+    let restaurant_uuid =
+        Uuid::parse_str(&context.authorize_permission(Permission::ManageOrders)?)?;
+    let partner_uuid = Uuid::parse_str(context.get_client_id()?)?;
+    let session_uuid = Uuid::parse_str(id)?;
+    let to_dining_table_uuid = Uuid::parse_str(dining_table_id)?;
+    let mut customer_uuids = vec![];
+    for customer_id in customer_ids {
+        customer_uuids.push(Uuid::parse_str(customer_id)?);
+    }
+    if customer_uuids.is_empty() {
+        return Err(table_session_error("Choose the diners to move"));
+    }
+    let conn = context.pool.get()?;
+    let trans = conn.transaction()?;
+    let session = find_open_for_update(&trans, &session_uuid, &restaurant_uuid)?;
+    let customer_order_uuid = Uuid::parse_str(&session.customer_order_id)?;
+    let customer_order = lock_movable_order(&trans, &customer_order_uuid)?;
+    let owner_uuid = Uuid::parse_str(&customer_order.customer_id)?;
+    if customer_uuids.contains(&owner_uuid) {
+        return Err(table_session_error(
+            "The diner who opened the table stays with it",
+        ));
+    }
+    let rows = trans.query(
+        "
+        SELECT customer_id
+        FROM customer_order_participant
+        WHERE customer_order_id = $1 AND customer_id = ANY($2)
+    ",
+        &[&customer_order_uuid, &customer_uuids],
+    )?;
+    if rows.len() != customer_uuids.len() {
+        return Err(table_session_error(
+            "Some diners are not seated at this table",
+        ));
+    }
+    lock_free_table(&trans, &to_dining_table_uuid, &restaurant_uuid)?;
+
+    let split_customer_order_uuid = Uuid::new_v4();
+    let split_session_uuid = Uuid::new_v4();
+    let new_owner_uuid = customer_uuids[0];
+    // the split-off order keeps the rates the party was quoted
+    trans.execute(
+        "
+        INSERT INTO customer_order (
+            id,
+            restaurant_id,
+            dining_table_id,
+            customer_id,
+            status,
+            tax_rate,
+            service_charge_rate,
+            invite_code
+        )
+        SELECT $2, restaurant_id, $3, $4, $5, tax_rate, service_charge_rate, $6
+        FROM customer_order
+        WHERE id = $1
+    ",
+        &[
+            &customer_order_uuid,
+            &split_customer_order_uuid,
+            &to_dining_table_uuid,
+            &new_owner_uuid,
+            &CustomerOrderStatus::Open,
+            &generate_invite_code()?,
+        ],
+    )?;
+    let rows = trans.query(
+        "
+        INSERT INTO table_session (
+            id,
+            dining_table_id,
+            customer_order_id,
+            opened_by
+        ) VALUES ($1, $2, $3, $4)
+        RETURNING *
+    ",
+        &[
+            &split_session_uuid,
+            &to_dining_table_uuid,
+            &split_customer_order_uuid,
+            &new_owner_uuid,
+        ],
+    )?;
+    let dish_order_count = trans.execute(
+        "
+        UPDATE dish_order
+        SET customer_order_id = $2
+        WHERE customer_order_id = $1 AND customer_id = ANY($3)
+    ",
+        &[
+            &customer_order_uuid,
+            &split_customer_order_uuid,
+            &customer_uuids,
+        ],
+    )?;
+    trans.execute(
+        "
+        DELETE FROM customer_order_participant
+        WHERE customer_order_id = $1 AND customer_id = ANY($2)
+    ",
+        &[&customer_order_uuid, &customer_uuids],
+    )?;
+    trans.execute(
+        "
+        INSERT INTO customer_order_participant (
+            customer_order_id,
+            customer_id
+        )
+        SELECT $1, customer_id
+        FROM unnest($2::uuid[]) AS customer_id
+        WHERE customer_id <> $3
+    ",
+        &[&split_customer_order_uuid, &customer_uuids, &new_owner_uuid],
+    )?;
+    record_event(
+        &trans,
+        &Event {
+            kind: TableSessionEventKind::Split,
+            table_session_id: &session_uuid,
+            other_table_session_id: Some(&split_session_uuid),
+            from_dining_table_id: &Uuid::parse_str(&session.dining_table_id)?,
+            to_dining_table_id: &to_dining_table_uuid,
+            dish_order_count: dish_order_count as i32,
+            performed_by: &partner_uuid,
+        },
+    )?;
+    trans.commit()?;
+    let split_session = TableSession::from_row(&rows.get(0));
+    let split_customer_order = CustomerOrder::find(&*conn, &split_customer_order_uuid)?;
+    publish_order_event(
+        context,
+        &OrderEvent::for_customer_order(
+            OrderEventKind::CustomerOrderCreated,
+            &split_customer_order,
+        ),
+    );
+    Ok(split_session)
+}
+
+/// The partner's currently occupied tables, longest seated first.
+pub fn occupied_tables(context: &Context) -> FieldResult<Vec<TableSession>> {
+    let restaurant_uuid = Uuid::parse_str(&context.authorize_permission(Permission::ViewOrders)?)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT table_session.*
+        FROM table_session
+        JOIN dining_table ON dining_table.id = table_session.dining_table_id
+        WHERE dining_table.restaurant_id = $1 AND table_session.closed_at IS NULL
+        ORDER BY table_session.opened_at
+    ",
+        &[&restaurant_uuid],
+    )?;
+    let mut sessions = vec![];
+    for row in &rows {
+        sessions.push(TableSession::from_row(&row));
+    }
+    Ok(sessions)
+}
+
+graphql_object!(TableSession: Context | &self | {
+  field id() -> &str {
+    self.id.as_str()
+  }
+  field dining_table_id() -> &str {
+    self.dining_table_id.as_str()
+  }
+  field customer_order_id() -> &str {
+    self.customer_order_id.as_str()
+  }
+  field opened_by() -> &str {
+    self.opened_by.as_str()
+  }
+  field opened_at() -> &NaiveDateTime {
+    &self.opened_at
+  }
+  field closed_at() -> &Option<NaiveDateTime> {
+    &self.closed_at
+  }
+  field occupied_seconds(&executor) -> FieldResult<i32> {
+    let conn = executor.context().pool.get()?;
+    let session_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT EXTRACT(EPOCH FROM COALESCE(closed_at, now()) - opened_at)::int AS occupied_seconds
+      FROM table_session
+      WHERE id = $1
+    ", &[&session_uuid])?;
+    Ok(rows.get(0).get("occupied_seconds"))
+  }
+  field customer_order(&executor) -> FieldResult<CustomerOrder> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.customer_order_id)?;
+    CustomerOrder::find(&*conn, &customer_order_uuid)
+  }
+  field events(&executor) -> FieldResult<Vec<TableSessionEvent>> {
+    let conn = executor.context().pool.get()?;
+    let session_uuid = Uuid::parse_str(&self.id)?;
+    let rows = conn.query("
+      SELECT *
+      FROM table_session_event
+      WHERE table_session_id = $1 OR other_table_session_id = $1
+      ORDER BY created_at
+    ", &[&session_uuid])?;
+    let mut events = vec!();
+    for row in &rows {
+      events.push(TableSessionEvent::from_row(&row));
+    }
+    Ok(events)
+  }
+  field diners(&executor) -> FieldResult<Vec<Customer>> {
+    let conn = executor.context().pool.get()?;
+    let customer_order_uuid = Uuid::parse_str(&self.customer_order_id)?;
+    let rows = conn.query("
+      SELECT customer.*
+      FROM customer
+      JOIN customer_order ON customer_order.customer_id = customer.id
+      WHERE customer_order.id = $1
+      UNION
+      SELECT customer.*
+      FROM customer
+      JOIN customer_order_participant ON customer_order_participant.customer_id = customer.id
+      WHERE customer_order_participant.customer_order_id = $1
+    ", &[&customer_order_uuid])?;
+    let mut diners = vec!();
+    for row in &rows {
+      diners.push(Customer::from_row(&row));
+    }
+    Ok(diners)
+  }
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_order(first: &Uuid, second: &Uuid) -> (Vec<Uuid>, (Uuid, Uuid)) {
+        let mut locked = vec![];
+        let pair = lock_pair(first, second, |id| {
+            locked.push(*id);
+            Ok(*id)
+        })
+        .unwrap();
+        (locked, pair)
+    }
+
+    #[test]
+    fn pairs_are_locked_lowest_first_but_returned_as_given() {
+        let low = Uuid::parse_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let high = Uuid::parse_str("ffffffff-0000-0000-0000-000000000000").unwrap();
+        assert_eq!(lock_order(&low, &high), (vec![low, high], (low, high)));
+        assert_eq!(lock_order(&high, &low), (vec![low, high], (high, low)));
+    }
+}
@@ -0,0 +1,109 @@
+use r2d2::Pool;
+use r2d2_postgres::{PostgresConnectionManager, TlsMode};
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+use std::env;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::notifier::{self, Notifier};
+use crate::payment::PaymentProviders;
+
+/// Process-wide state built once in `main` and shared by every request `Context`.
+pub struct AppState {
+    pub pool: Pool<PostgresConnectionManager>,
+    pub redis_pool: Pool<RedisConnectionManager>,
+    pub payment_providers: Arc<PaymentProviders>,
+    pub notifier: Arc<dyn Notifier>,
+    /// Subscriptions hold a dedicated Redis connection each, outside the pool.
+    pub redis_connection_string: String,
+}
+
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl PoolConfig {
+    /// Reads `<PREFIX>_POOL_MAX_SIZE`, `<PREFIX>_POOL_MIN_IDLE`,
+    /// `<PREFIX>_POOL_CONNECTION_TIMEOUT_SECS` and `<PREFIX>_POOL_IDLE_TIMEOUT_SECS`.
+    pub fn from_env(prefix: &str) -> Result<PoolConfig, Box<dyn Error>> {
+        let max_size = env_or(&format!("{}_POOL_MAX_SIZE", prefix), 15)?;
+        let min_idle = env_opt(&format!("{}_POOL_MIN_IDLE", prefix))?;
+        let connection_timeout = env_or(&format!("{}_POOL_CONNECTION_TIMEOUT_SECS", prefix), 5)?;
+        let idle_timeout = env_opt(&format!("{}_POOL_IDLE_TIMEOUT_SECS", prefix))?.unwrap_or(600);
+        Ok(PoolConfig {
+            max_size,
+            min_idle,
+            connection_timeout: Duration::from_secs(connection_timeout),
+            idle_timeout: if idle_timeout == 0 {
+                None
+            } else {
+                Some(Duration::from_secs(idle_timeout))
+            },
+        })
+    }
+}
+
+impl AppState {
+    pub fn from_env() -> Result<AppState, Box<dyn Error>> {
+        let manager =
+            PostgresConnectionManager::new(env::var("POSTGRES_CONNECTION_STRING")?, TlsMode::None)?;
+        let redis_connection_string = env::var("REDIS_CONNECTION_STRING")?;
+        let redis_manager = RedisConnectionManager::new(redis_connection_string.as_str())?;
+        let postgres_config = PoolConfig::from_env("POSTGRES")?;
+        let redis_config = PoolConfig::from_env("REDIS")?;
+        let state = AppState {
+            pool: build_pool(manager, &postgres_config)?,
+            redis_pool: build_pool(redis_manager, &redis_config)?,
+            payment_providers: Arc::new(PaymentProviders::from_env()?),
+            notifier: Arc::from(notifier::from_env()?),
+            redis_connection_string,
+        };
+        state.check()?;
+        Ok(state)
+    }
+
+    /// Fails fast at startup instead of on the first request when either backend is unreachable.
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        let conn = self.pool.get()?;
+        conn.query("SELECT 1", &[])?;
+        let redis_conn = self.redis_pool.get()?;
+        let _: String = redis::cmd("PING").query(&*redis_conn)?;
+        Ok(())
+    }
+}
+
+fn build_pool<M: r2d2::ManageConnection>(
+    manager: M,
+    config: &PoolConfig,
+) -> Result<Pool<M>, r2d2::Error> {
+    Pool::builder()
+        .max_size(config.max_size)
+        .min_idle(config.min_idle)
+        .connection_timeout(config.connection_timeout)
+        .idle_timeout(config.idle_timeout)
+        .build(manager)
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T, Box<dyn Error>>
+where
+    T::Err: Error + 'static,
+{
+    Ok(env_opt(name)?.unwrap_or(default))
+}
+
+fn env_opt<T: FromStr>(name: &str) -> Result<Option<T>, Box<dyn Error>>
+where
+    T::Err: Error + 'static,
+{
+    match env::var(name) {
+        Ok(value) => Ok(Some(value.parse()?)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
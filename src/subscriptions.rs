@@ -0,0 +1,383 @@
+//! WebSocket transport for GraphQL subscriptions, speaking the `graphql-ws`
+//! protocol used by Apollo and GraphiQL subscription clients. Events reach
+//! this process through Redis pub/sub, so every API instance sees every event.
+
+use juniper::http::GraphQLRequest;
+use juniper::{EmptyMutation, InputValue, RootNode};
+use r2d2_redis::redis;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use ws::{CloseCode, Factory, Handler, Handshake, Message, Request, Response, Sender, WebSocket};
+
+use crate::schema::context::Context;
+use crate::schema::order_event::OrderEvent;
+use crate::schema::subscription::Subscription;
+use crate::state::AppState;
+
+const PROTOCOL: &str = "graphql-ws";
+/// How often an idle subscription makes sure its session is still live.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Each running operation holds a thread and a Redis connection.
+const MAX_OPERATIONS_PER_CONNECTION: usize = 10;
+const MAX_OPERATIONS_PER_CLIENT: usize = 20;
+
+/// Running operations per client, across all of the client's connections.
+type ActiveOperations = Arc<Mutex<HashMap<String, usize>>>;
+
+#[derive(Deserialize)]
+struct ClientMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    id: Option<String>,
+    payload: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct StartPayload {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+}
+
+#[derive(Deserialize)]
+struct InitPayload {
+    #[serde(rename = "authToken")]
+    auth_token: Option<String>,
+}
+
+pub struct Connection {
+    out: Sender,
+    state: Arc<AppState>,
+    token: Option<String>,
+    /// Peer address, which stands in for the client until it authenticates.
+    peer: Option<String>,
+    operations: HashMap<String, Arc<AtomicBool>>,
+    active: ActiveOperations,
+}
+
+pub struct ConnectionFactory {
+    state: Arc<AppState>,
+    active: ActiveOperations,
+}
+
+impl Factory for ConnectionFactory {
+    type Handler = Connection;
+
+    fn connection_made(&mut self, out: Sender) -> Connection {
+        Connection {
+            out,
+            state: self.state.clone(),
+            token: None,
+            peer: None,
+            operations: HashMap::new(),
+            active: self.active.clone(),
+        }
+    }
+}
+
+/// Counts one running operation against its client until dropped.
+struct ClientSlot {
+    active: ActiveOperations,
+    client: String,
+}
+
+impl ClientSlot {
+    fn acquire(active: &ActiveOperations, client: &str) -> Option<ClientSlot> {
+        let mut counts = active.lock().unwrap();
+        let count = counts.entry(client.to_owned()).or_insert(0);
+        if *count >= MAX_OPERATIONS_PER_CLIENT {
+            return None;
+        }
+        *count += 1;
+        Some(ClientSlot {
+            active: active.clone(),
+            client: client.to_owned(),
+        })
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        let mut counts = self.active.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Binds the listener up front so a taken port fails startup; the returned
+/// server is then `run` on its own thread.
+pub fn bind(
+    state: Arc<AppState>,
+    host: &str,
+) -> Result<WebSocket<ConnectionFactory>, Box<ws::Error>> {
+    let factory = ConnectionFactory {
+        state,
+        active: Arc::new(Mutex::new(HashMap::new())),
+    };
+    Ok(ws::Builder::new().build(factory)?.bind(host)?)
+}
+
+fn text(message: serde_json::Value) -> Message {
+    Message::text(message.to_string())
+}
+
+fn operation_error(id: &str, message: &str) -> serde_json::Value {
+    json!({ "type": "error", "id": id, "payload": { "message": message } })
+}
+
+/// juniper only executes queries, so a subscription operation is run as one.
+/// Only the operation keyword is swapped; the rest of the document is kept.
+fn as_query(document: &str) -> String {
+    let trimmed = document.trim_start();
+    match trimmed.strip_prefix("subscription") {
+        Some(rest) if !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_') => {
+            format!("query{}", rest)
+        }
+        _ => document.to_owned(),
+    }
+}
+
+fn execute(context: &Context, root: Subscription, request: &GraphQLRequest) -> serde_json::Value {
+    let root_node = RootNode::new(root, EmptyMutation::<Context>::new());
+    let response = request.execute(&root_node, context);
+    serde_json::to_value(&response).unwrap_or(serde_json::Value::Null)
+}
+
+impl Connection {
+    /// Starts relaying events for the operation, or returns the `error`
+    /// message to send back when it can't.
+    fn start(&mut self, id: String, payload: StartPayload) -> Result<(), serde_json::Value> {
+        let request = GraphQLRequest::new(
+            as_query(&payload.query),
+            payload.operation_name,
+            payload.variables,
+        );
+        let context = Context::new(&self.state, self.token.as_deref(), None);
+
+        // a restarted id replaces its operation, and finished ones free their place
+        self.stop_operation(&id);
+        self.operations
+            .retain(|_, stopped| !stopped.load(Ordering::SeqCst));
+        if self.operations.len() >= MAX_OPERATIONS_PER_CONNECTION {
+            return Err(operation_error(
+                &id,
+                "Too many active subscriptions on this connection",
+            ));
+        }
+        let client = match context.get_client_id() {
+            Ok(client_id) => format!("client:{}", client_id),
+            Err(_) => format!("peer:{}", self.peer.as_deref().unwrap_or("unknown")),
+        };
+        let slot = match ClientSlot::acquire(&self.active, &client) {
+            Some(slot) => slot,
+            None => return Err(operation_error(&id, "Too many active subscriptions")),
+        };
+
+        let channels = Arc::new(Mutex::new(vec![]));
+        let response = execute(&context, Subscription::setup(channels.clone()), &request);
+        if response.get("errors").is_some() {
+            return Err(json!({ "type": "error", "id": id, "payload": response }));
+        }
+        let channels = channels.lock().unwrap().clone();
+        if channels.is_empty() {
+            return Err(operation_error(&id, "Subscription selects no events"));
+        }
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        self.operations.insert(id.clone(), stopped.clone());
+        let out = self.out.clone();
+        let state = self.state.clone();
+        let token = self.token.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay(&out, &state, token, &id, channels, &request, &stopped) {
+                warn!("Subscription {} stopped: {}", id, e);
+                let _ = out.send(text(operation_error(&id, &e.to_string())));
+            }
+            stopped.store(true, Ordering::SeqCst);
+            drop(slot);
+        });
+        Ok(())
+    }
+
+    fn stop_operation(&mut self, id: &str) {
+        if let Some(stopped) = self.operations.remove(id) {
+            stopped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn stop_all(&mut self) {
+        for (_, stopped) in self.operations.drain() {
+            stopped.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Listens on the subscription's channels and pushes one `data` message per
+/// event until the operation is stopped or the socket goes away.
+fn relay(
+    out: &Sender,
+    state: &AppState,
+    token: Option<String>,
+    id: &str,
+    channels: Vec<String>,
+    request: &GraphQLRequest,
+    stopped: &AtomicBool,
+) -> redis::RedisResult<()> {
+    let client = redis::Client::open(state.redis_connection_string.as_str())?;
+    let mut conn = client.get_connection()?;
+    let mut pubsub = conn.as_pubsub();
+    for channel in &channels {
+        pubsub.subscribe(channel.as_str())?;
+    }
+    // wake up regularly to notice that the client has stopped listening
+    pubsub.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let context = Context::new(state, token.as_deref(), None);
+    let mut session_checked_at = Instant::now();
+    while !stopped.load(Ordering::SeqCst) {
+        let message = pubsub.get_message();
+        // expiry is checked every time round, the session in Redis every
+        // interval and before every event, so a log out ends the stream too
+        let check_session =
+            message.is_ok() || session_checked_at.elapsed() >= SESSION_CHECK_INTERVAL;
+        if check_session {
+            session_checked_at = Instant::now();
+        }
+        if !context.is_token_current(check_session) {
+            let _ = out.send(text(operation_error(id, "Session has ended")));
+            let _ = out.send(text(json!({ "type": "complete", "id": id })));
+            break;
+        }
+        let message = match message {
+            Ok(message) => message,
+            Err(ref e) if e.is_timeout() => continue,
+            Err(e) => return Err(e),
+        };
+        let payload: String = message.get_payload()?;
+        let event: OrderEvent = match serde_json::from_str(&payload) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!(
+                    "Ignoring malformed event on {}: {}",
+                    message.get_channel_name(),
+                    e
+                );
+                continue;
+            }
+        };
+        let root = Subscription::for_event(message.get_channel_name().to_owned(), event);
+        let response = execute(&context, root, request);
+        if out
+            .send(text(
+                json!({ "type": "data", "id": id, "payload": response }),
+            ))
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Handler for Connection {
+    fn on_request(&mut self, req: &Request) -> ws::Result<Response> {
+        let mut response = Response::from_request(req)?;
+        if req.protocols()?.contains(&PROTOCOL) {
+            response.set_protocol(PROTOCOL);
+        }
+        Ok(response)
+    }
+
+    fn on_open(&mut self, shake: Handshake) -> ws::Result<()> {
+        self.peer = shake.peer_addr.map(|addr| addr.ip().to_string());
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> ws::Result<()> {
+        let message: ClientMessage = match msg.as_text().map(serde_json::from_str) {
+            Ok(Ok(message)) => message,
+            _ => {
+                return self.out.send(text(
+                    json!({ "type": "connection_error", "payload": { "message": "Malformed message" } }),
+                ))
+            }
+        };
+        match (message.kind.as_str(), message.id) {
+            ("connection_init", _) => {
+                let init: Option<InitPayload> = message
+                    .payload
+                    .and_then(|payload| serde_json::from_value(payload).ok());
+                self.token = init.and_then(|init| init.auth_token);
+                self.out.send(text(json!({ "type": "connection_ack" })))
+            }
+            ("start", Some(id)) => {
+                match message
+                    .payload
+                    .and_then(|payload| serde_json::from_value::<StartPayload>(payload).ok())
+                {
+                    Some(payload) => match self.start(id, payload) {
+                        Ok(()) => Ok(()),
+                        Err(reply) => self.out.send(text(reply)),
+                    },
+                    None => self
+                        .out
+                        .send(text(operation_error(&id, "Malformed start payload"))),
+                }
+            }
+            ("stop", Some(id)) => {
+                self.stop_operation(&id);
+                self.out.send(text(json!({ "type": "complete", "id": id })))
+            }
+            ("connection_terminate", _) => {
+                self.stop_all();
+                self.out.close(CloseCode::Normal)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn on_close(&mut self, _: CloseCode, _: &str) {
+        self.stop_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscriptions_run_as_queries() {
+        assert_eq!(
+            as_query("  subscription Orders { orderEvents { id } }"),
+            "query Orders { orderEvents { id } }"
+        );
+        assert_eq!(as_query("subscription{ a }"), "query{ a }");
+    }
+
+    #[test]
+    fn other_documents_are_left_alone() {
+        assert_eq!(as_query("query { a }"), "query { a }");
+        assert_eq!(as_query("subscriptions { a }"), "subscriptions { a }");
+        assert_eq!(as_query("{ subscription }"), "{ subscription }");
+    }
+
+    #[test]
+    fn client_slots_are_capped_and_released() {
+        let active: ActiveOperations = Arc::new(Mutex::new(HashMap::new()));
+        let slots: Vec<_> = (0..MAX_OPERATIONS_PER_CLIENT)
+            .map(|_| ClientSlot::acquire(&active, "client:a").unwrap())
+            .collect();
+        assert!(ClientSlot::acquire(&active, "client:a").is_none());
+        assert!(ClientSlot::acquire(&active, "client:b").is_some());
+        drop(slots);
+        assert!(active.lock().unwrap().get("client:a").is_none());
+        assert!(ClientSlot::acquire(&active, "client:a").is_some());
+    }
+}
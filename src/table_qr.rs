@@ -0,0 +1,289 @@
+//! Printable QR codes for dining tables, served outside GraphQL because they
+//! are images. Each code carries the table's signed token rather than its id,
+//! see `schema::dining_table::table_token`.
+
+use image::{DynamicImage, ImageOutputFormat, Luma};
+use iron::headers::ContentType;
+use iron::mime::Mime;
+use iron::prelude::*;
+use iron::{status, Handler};
+use juniper::{FieldError, FieldResult};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use std::env;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::schema::context::{context_factory, Context};
+use crate::schema::dining_table::table_token;
+use crate::schema::permission::Permission;
+use crate::state::AppState;
+
+/// Smallest edge of a rendered code in pixels, big enough to print on a
+/// table tent.
+const QR_SIZE: u32 = 320;
+
+pub struct TableQrHandler {
+    state: Arc<AppState>,
+}
+
+impl TableQrHandler {
+    pub fn new(state: Arc<AppState>) -> TableQrHandler {
+        TableQrHandler { state }
+    }
+}
+
+struct QrTable {
+    name: String,
+    content: String,
+}
+
+impl Handler for TableQrHandler {
+    /// Serves `tables/<id>.png`, `tables/<id>.svg` and
+    /// `restaurants/<id>/sheet`, to partners allowed to manage the restaurant.
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let path: Vec<String> = req.url.path().iter().map(|s| s.to_string()).collect();
+        let context = context_factory(&self.state, req)?;
+        let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+        let result = match path.as_slice() {
+            ["tables", file] => table_code(&context, file),
+            ["restaurants", id, "sheet"] => restaurant_sheet(&context, id),
+            _ => return Ok(Response::with(status::NotFound)),
+        };
+        Ok(result.unwrap_or_else(|e| error_response(&e)))
+    }
+}
+
+fn not_found() -> FieldError {
+    FieldError::new(
+        "Not found",
+        graphql_value!({ "internal_error": "Not found", "code": "NOT_FOUND" }),
+    )
+}
+
+/// Picks the status from the error's `code` extension. Anything without a
+/// known code is unexpected, so it is logged and only a bare 500 goes out.
+fn error_response(e: &FieldError) -> Response {
+    let code = e
+        .extensions()
+        .as_object_value()
+        .and_then(|extensions| extensions.get_field_value("code"))
+        .and_then(|code| code.as_scalar_value::<String>());
+    match code.map(String::as_str) {
+        Some("UNAUTHENTICATED") => Response::with((status::Unauthorized, "Unauthenticated")),
+        Some("UNAUTHORIZED") => Response::with((status::Forbidden, "Unauthorized")),
+        Some("NOT_FOUND") => Response::with((status::NotFound, "Not found")),
+        _ => {
+            error!("Table QR request failed: {}", e.message());
+            Response::with((status::InternalServerError, "Internal server error"))
+        }
+    }
+}
+
+fn with_content_type(mut response: Response, content_type: &str) -> Response {
+    let mime: Mime = content_type.parse().unwrap();
+    response.headers.set(ContentType(mime));
+    response
+}
+
+/// What the code encodes: the token alone, or a link to the ordering app
+/// when `TABLE_QR_BASE_URL` is set.
+fn qr_content(token: String) -> String {
+    match env::var("TABLE_QR_BASE_URL") {
+        Ok(base_url) => format!("{}{}", base_url, token),
+        Err(_) => token,
+    }
+}
+
+fn render_svg(content: &str) -> FieldResult<String> {
+    let code = QrCode::new(content.as_bytes())?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(QR_SIZE, QR_SIZE)
+        .build())
+}
+
+fn render_png(content: &str) -> FieldResult<Vec<u8>> {
+    let code = QrCode::new(content.as_bytes())?;
+    let image = code
+        .render::<Luma<u8>>()
+        .min_dimensions(QR_SIZE, QR_SIZE)
+        .build();
+    let mut png = vec![];
+    DynamicImage::ImageLuma8(image).write_to(&mut png, ImageOutputFormat::PNG)?;
+    Ok(png)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Loads the restaurant's tables, or just `dining_table_id`, once the caller
+/// is allowed to print codes for them.
+fn load_tables(
+    context: &Context,
+    restaurant_id: &Uuid,
+    dining_table_id: Option<&Uuid>,
+) -> FieldResult<Vec<QrTable>> {
+    context.authorize_restaurant(
+        &restaurant_id.hyphenated().to_string(),
+        Permission::ManageRestaurant,
+    )?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT id, name, qr_token_version
+        FROM dining_table
+        WHERE restaurant_id = $1
+            AND archived_at IS NULL
+            AND ($2::uuid IS NULL OR id = $2)
+        ORDER BY name
+    ",
+        &[restaurant_id, &dining_table_id],
+    )?;
+    let mut tables = vec![];
+    for row in &rows {
+        let id: Uuid = row.get("id");
+        tables.push(QrTable {
+            name: row.get("name"),
+            content: qr_content(table_token(
+                &id,
+                restaurant_id,
+                row.get("qr_token_version"),
+            )?),
+        });
+    }
+    Ok(tables)
+}
+
+fn table_code(context: &Context, file: &str) -> FieldResult<Response> {
+    let mut parts = file.rsplitn(2, '.');
+    let (extension, id) = match (parts.next(), parts.next()) {
+        (Some(extension), Some(id)) => (extension, id),
+        _ => return Err(not_found()),
+    };
+    let dining_table_uuid = Uuid::parse_str(id).map_err(|_| not_found())?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT restaurant_id
+        FROM dining_table
+        WHERE id = $1
+    ",
+        &[&dining_table_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(not_found());
+    }
+    let restaurant_uuid: Uuid = rows.get(0).get("restaurant_id");
+    let table = match load_tables(context, &restaurant_uuid, Some(&dining_table_uuid))?.pop() {
+        Some(table) => table,
+        None => return Err(not_found()),
+    };
+    match extension {
+        "png" => Ok(with_content_type(
+            Response::with((status::Ok, render_png(&table.content)?)),
+            "image/png",
+        )),
+        "svg" => Ok(with_content_type(
+            Response::with((status::Ok, render_svg(&table.content)?)),
+            "image/svg+xml",
+        )),
+        _ => Err(not_found()),
+    }
+}
+
+/// One page of labelled codes for every table in use, laid out for printing
+/// and cutting.
+fn restaurant_sheet(context: &Context, id: &str) -> FieldResult<Response> {
+    let restaurant_uuid = Uuid::parse_str(id).map_err(|_| not_found())?;
+    let tables = load_tables(context, &restaurant_uuid, None)?;
+    let conn = context.pool.get()?;
+    let rows = conn.query(
+        "
+        SELECT name
+        FROM restaurant
+        WHERE id = $1
+    ",
+        &[&restaurant_uuid],
+    )?;
+    if rows.is_empty() {
+        return Err(not_found());
+    }
+    let restaurant_name: String = rows.get(0).get("name");
+    let mut figures = String::new();
+    for table in &tables {
+        figures.push_str(&format!(
+            "<figure>{}<figcaption>{}</figcaption></figure>\n",
+            render_svg(&table.content)?,
+            escape_html(&table.name)
+        ));
+    }
+    let html = format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{name} table codes</title>
+<style>
+body {{ font-family: sans-serif; }}
+main {{ display: flex; flex-wrap: wrap; }}
+figure {{ margin: 8mm; text-align: center; page-break-inside: avoid; }}
+figcaption {{ font-size: 18pt; margin-top: 4mm; }}
+</style>
+</head>
+<body>
+<h1>{name}</h1>
+<main>
+{figures}</main>
+</body>
+</html>
+",
+        name = escape_html(&restaurant_name),
+        figures = figures
+    );
+    Ok(with_content_type(
+        Response::with((status::Ok, html)),
+        "text/html; charset=utf-8",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_status_by_code() {
+        let unauthenticated = FieldError::new(
+            "Unauthenticated",
+            graphql_value!({ "internal_error": "Unauthenticated", "code": "UNAUTHENTICATED" }),
+        );
+        let unauthorized = FieldError::new(
+            "Unauthorized",
+            graphql_value!({ "internal_error": "Unauthorized", "code": "UNAUTHORIZED" }),
+        );
+        assert_eq!(
+            error_response(&unauthenticated).status,
+            Some(status::Unauthorized)
+        );
+        assert_eq!(
+            error_response(&unauthorized).status,
+            Some(status::Forbidden)
+        );
+        assert_eq!(error_response(&not_found()).status, Some(status::NotFound));
+    }
+
+    #[test]
+    fn unexpected_errors_are_a_bare_500() {
+        let e = FieldError::from("connection refused to db.internal:5432");
+        assert_eq!(error_response(&e).status, Some(status::InternalServerError));
+        let unauthorized_text = FieldError::new("Unauthorized", graphql_value!(None));
+        assert_eq!(
+            error_response(&unauthorized_text).status,
+            Some(status::InternalServerError)
+        );
+    }
+}